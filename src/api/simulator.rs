@@ -34,6 +34,25 @@ impl SmsService {
         self.send(phone, &message)
     }
 
+    pub fn request_seller_acceptance(&self, phone: &str, escrow_id: &str, amount: f64, currency: &str, deadline: &str) -> Result<(), std::io::Error> {
+        let message = format!(
+            "NEW ORDER\nEscrow: {}\nAmount: {} {}\nDeliver by: {}\n\nAccept or reject these terms before the buyer can fund the escrow.",
+            &escrow_id[..8], amount, currency, deadline
+        );
+        
+        self.send(phone, &message)
+    }
+
+    pub fn notify_terms_response(&self, phone: &str, escrow_id: &str, accepted: bool) -> Result<(), std::io::Error> {
+        let message = if accepted {
+            format!("ORDER ACCEPTED\nEscrow: {}\n\nThe seller accepted your order. Fund the escrow to confirm it.", &escrow_id[..8])
+        } else {
+            format!("ORDER DECLINED\nEscrow: {}\n\nThe seller cannot fill this order. The escrow has been cancelled.", &escrow_id[..8])
+        };
+        
+        self.send(phone, &message)
+    }
+
     pub fn notify_seller_delivery(&self, phone: &str, escrow_id: &str, amount: f64, currency: &str) -> Result<(), std::io::Error> {
         let message = format!(
             "FUNDS GUARANTEED!\nEscrow: {}\nAmount: {} {}\n\nBuyer has escrowed funds. You can safely deliver goods.",
@@ -52,6 +71,15 @@ impl SmsService {
         self.send(phone, &message)
     }
 
+    pub fn notify_pin_locked(&self, phone: &str, escrow_id: &str, locked_until: &str) -> Result<(), std::io::Error> {
+        let message = format!(
+            "SECURITY ALERT!\nEscrow: {}\nToo many wrong release PINs were entered.\n\nRelease is locked until {}. If this wasn't you, do not share your PIN.",
+            &escrow_id[..8], locked_until
+        );
+        
+        self.send(phone, &message)
+    }
+
     pub fn send(&self, phone: &str, message: &str) -> Result<(), std::io::Error> {
         let now = Local::now();
         let timestamp = now.format("%H:%M:%S");
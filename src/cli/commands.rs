@@ -1,16 +1,50 @@
-use clap::{Args, Subcommand};
+use crate::types::{EscrowState, EvidenceKind, GpsLocation, LineItem, UserType};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, Subcommand, ValueEnum};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::path::PathBuf;
 use uuid::Uuid;
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum StorageBackend {
+    File,
+    Sqlite,
+    Memory,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     Create(CreateArgs),
+    Accept(AcceptArgs),
+    Reject(RejectArgs),
     Fund(FundArgs),
     Release(ReleaseArgs),
+    Confirm(ConfirmArgs),
+    ResendPin(ResendPinArgs),
+    Register(RegisterArgs),
+    Keygen(KeygenArgs),
+    Voucher(VoucherArgs),
+    VerifyVoucher(VerifyVoucherArgs),
+    Redeem(RedeemArgs),
+    AssignDriver(AssignDriverArgs),
     Cancel(CancelArgs),
     Dispute(DisputeArgs),
+    SubmitEvidence(SubmitEvidenceArgs),
+    Evidence(EvidenceArgs),
     Vote(VoteArgs),
-    List,
+    Arbitrators,
+    Decide(DecideArgs),
+    Appeal(AppealArgs),
+    DisputeDeadlines,
+    RefundExpired,
+    List(ListArgs),
     Get(GetArgs),
+    History(HistoryArgs),
+    Verify,
+    Backup(BackupArgs),
+    Restore(RestoreArgs),
+    RotateKey,
     Trust(TrustArgs),
     Demo(DemoArgs),
     Sms(SmsArgs),
@@ -19,8 +53,9 @@ pub enum Commands {
 
 #[derive(Args)]
 pub struct CreateArgs {
-    #[arg(short, long)]
-    pub amount: f64,
+    /// Total to hold; defaults to the sum of the line items
+    #[arg(short, long, required_unless_present = "items")]
+    pub amount: Option<f64>,
     
     #[arg(short, long, default_value = "ZAR")]
     pub currency: String,
@@ -37,14 +72,50 @@ pub struct CreateArgs {
     #[arg(long)]
     pub seller_phone: String,
     
-    #[arg(short, long, default_value = "Monthly stock purchase")]
-    pub description: String,
+    /// Free-text summary; defaults to a list of the line items
+    #[arg(short, long)]
+    pub description: Option<String>,
+    
+    /// A product on the order; repeat for each. The buyer later confirms how
+    /// many of each arrived
+    #[arg(long = "item", value_name = "SKU:QUANTITY@UNIT_PRICE", conflicts_with = "milestones")]
+    pub items: Vec<LineItem>,
     
-    #[arg(short, long, default_value_t = 30)]
+    #[arg(long, default_value_t = 30)]
     pub days: i64,
     
     #[arg(long, default_value_t = false)]
     pub with_sms: bool,
+    
+    /// Release with codes from the buyer's authenticator app instead of a PIN
+    #[arg(long, default_value_t = false)]
+    pub totp: bool,
+    
+    /// One delivery of a split order, released with its own PIN; repeat for
+    /// each delivery. The amounts must add up to --amount
+    #[arg(long = "milestone", value_name = "DESCRIPTION=AMOUNT")]
+    pub milestones: Vec<MilestoneArg>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MilestoneArg {
+    pub description: String,
+    pub amount: Decimal,
+}
+
+impl FromStr for MilestoneArg {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (description, amount) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Expected DESCRIPTION=AMOUNT but got: {}", s))?;
+        let amount = Decimal::from_str(amount.trim()).map_err(|_| format!("Invalid amount: {}", amount))?;
+        Ok(Self {
+            description: description.trim().to_string(),
+            amount,
+        })
+    }
 }
 
 #[derive(Args)]
@@ -54,6 +125,13 @@ pub struct FundArgs {
     
     #[arg(short, long)]
     pub amount: f64,
+    
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Secret key file of the buyer, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
 }
 
 #[derive(Args)]
@@ -64,8 +142,180 @@ pub struct ReleaseArgs {
     #[arg(short, long)]
     pub user_id: Uuid,
     
+    /// Release PIN, or the current one-time code for TOTP escrows
     #[arg(short, long)]
     pub pin: String,
+    
+    /// Release only this delivery of a milestone escrow
+    #[arg(short, long)]
+    pub milestone: Option<u32>,
+    
+    /// Where the handover happened, as latitude,longitude
+    #[arg(long, allow_hyphen_values = true)]
+    pub gps: Option<GpsLocation>,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ConfirmArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Units of a SKU that actually arrived; SKUs not listed are taken as
+    /// delivered in full
+    #[arg(short, long, value_name = "SKU=QUANTITY")]
+    pub received: Vec<ReceivedArg>,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReceivedArg {
+    pub sku: String,
+    pub quantity: u32,
+}
+
+impl FromStr for ReceivedArg {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sku, quantity) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Expected SKU=QUANTITY but got: {}", s))?;
+        Ok(Self {
+            sku: sku.trim().to_string(),
+            quantity: quantity.trim().parse().map_err(|_| format!("Invalid quantity: {}", quantity))?,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct ResendPinArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Send to this number instead, and keep it for future messages
+    #[arg(long)]
+    pub phone: Option<String>,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RegisterArgs {
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    #[arg(short, long)]
+    pub phone: String,
+    
+    /// buyer, seller, arbitrator, driver or admin
+    #[arg(short, long)]
+    pub role: UserType,
+    
+    #[arg(short, long, default_value = "")]
+    pub name: String,
+}
+
+#[derive(Args)]
+pub struct KeygenArgs {
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// File to write the secret key to; keep it on the user's device
+    #[arg(short, long)]
+    pub output: PathBuf,
+    
+    /// Replace a key the user already has
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct VoucherArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    /// The buyer's secret key file
+    #[arg(short, long)]
+    pub key: PathBuf,
+    
+    #[arg(long, default_value_t = 48)]
+    pub valid_hours: i64,
+}
+
+#[derive(Args)]
+pub struct VerifyVoucherArgs {
+    #[arg(short, long)]
+    pub voucher: String,
+    
+    /// The buyer's public key, as cached by the driver's app
+    #[arg(short, long)]
+    pub public_key: String,
+}
+
+#[derive(Args)]
+pub struct RedeemArgs {
+    #[arg(short, long)]
+    pub voucher: String,
+}
+
+#[derive(Args)]
+pub struct AcceptArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    /// The seller accepting the terms
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RejectArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    /// The seller rejecting the terms
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct AssignDriverArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    /// The buyer making the assignment
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    #[arg(short, long)]
+    pub driver_id: Uuid,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
 }
 
 #[derive(Args)]
@@ -75,6 +325,10 @@ pub struct CancelArgs {
     
     #[arg(short, long)]
     pub user_id: Uuid,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
 }
 
 #[derive(Args)]
@@ -84,6 +338,37 @@ pub struct DisputeArgs {
     
     #[arg(short, long)]
     pub user_id: Uuid,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct SubmitEvidenceArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// statement, photo, file or note (a delivery note)
+    #[arg(long, default_value = "statement")]
+    pub kind: EvidenceKind,
+    
+    /// The text itself, a photo's SHA-256 in hex, or where a file can be found
+    #[arg(short, long)]
+    pub content: String,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct EvidenceArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
 }
 
 #[derive(Args)]
@@ -96,6 +381,99 @@ pub struct VoteArgs {
     
     #[arg(short, long)]
     pub vote: bool,
+    
+    /// Instead of all or nothing, give the seller this percentage of the
+    /// held funds (up to two decimal places) and refund the rest
+    #[arg(long, value_name = "PERCENT", conflicts_with = "vote")]
+    pub split: Option<Decimal>,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct AppealArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Args)]
+pub struct DecideArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+    
+    /// The admin deciding
+    #[arg(short, long)]
+    pub user_id: Uuid,
+    
+    /// Share of the held funds the seller gets: 100 releases everything,
+    /// 0 refunds the buyer
+    #[arg(long, value_name = "PERCENT")]
+    pub seller_percent: Decimal,
+    
+    /// Secret key file of the user acting, from `keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ListSort {
+    Created,
+    Expires,
+    Amount,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(long)]
+    pub buyer_id: Option<Uuid>,
+    
+    #[arg(long)]
+    pub seller_id: Option<Uuid>,
+    
+    /// Only escrows in this state (e.g. funded, in-dispute)
+    #[arg(long)]
+    pub state: Option<EscrowState>,
+    
+    #[arg(long)]
+    pub currency: Option<String>,
+    
+    /// Created on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_start)]
+    pub created_from: Option<DateTime<Utc>>,
+    
+    /// Created on or before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_end)]
+    pub created_to: Option<DateTime<Utc>>,
+    
+    /// Expiring on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_start)]
+    pub expires_from: Option<DateTime<Utc>>,
+    
+    /// Expiring on or before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_end)]
+    pub expires_to: Option<DateTime<Utc>>,
+    
+    #[arg(long, value_enum, default_value = "created")]
+    pub sort: ListSort,
+    
+    /// Oldest / smallest first instead of newest / largest first
+    #[arg(long, default_value_t = false)]
+    pub asc: bool,
+    
+    #[arg(long, default_value_t = 1)]
+    pub page: usize,
+    
+    #[arg(long, default_value_t = 20)]
+    pub per_page: usize,
 }
 
 #[derive(Args)]
@@ -104,6 +482,26 @@ pub struct GetArgs {
     pub escrow_id: Uuid,
 }
 
+#[derive(Args)]
+pub struct HistoryArgs {
+    #[arg(short, long)]
+    pub escrow_id: Uuid,
+}
+
+#[derive(Args)]
+pub struct BackupArgs {
+    /// Archive file to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Archive file to read
+    #[arg(short, long)]
+    pub input: PathBuf,
+}
+
 #[derive(Args)]
 pub struct TrustArgs {
     #[arg(short, long)]
@@ -123,4 +521,27 @@ pub struct SmsArgs {
     
     #[arg(short, long)]
     pub message: String,
+}
+
+fn parse_date(s: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(s) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+    
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("Expected YYYY-MM-DD or an RFC 3339 timestamp, got {}", s))?;
+    let time = if end_of_day {
+        date.and_hms_nano_opt(23, 59, 59, 999_999_999)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    };
+    Ok(time.expect("valid time of day").and_utc())
+}
+
+fn parse_start(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_date(s, false)
+}
+
+fn parse_end(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_date(s, true)
 }
\ No newline at end of file
@@ -0,0 +1,157 @@
+use crate::escrow::dispute::DisputePolicy;
+use crate::escrow::pin::PinPolicy;
+use crate::storage::{Keyring, StorageError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Cannot read config {path}: {reason}")]
+    Read { path: PathBuf, reason: String },
+
+    #[error("Cannot write config {path}: {reason}")]
+    Write { path: PathBuf, reason: String },
+
+    #[error("Invalid config {path}: {reason}")]
+    Invalid { path: PathBuf, reason: String },
+
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+/// Local settings kept outside the data store: the keys protecting personal
+/// data and the policies escrows are run under. Missing sections fall back
+/// to defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub encryption: EncryptionConfig,
+    pub pin: PinPolicy,
+    pub dispute: DisputePolicy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Id of the key new values are encrypted with.
+    pub active_key: String,
+    /// Every key that may still be needed to decrypt stored values.
+    pub keys: Vec<EncryptionKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionKey {
+    pub id: String,
+    /// Base64-encoded 256-bit AES key.
+    pub key: String,
+    /// When a newer key replaced this one. A retired key is never used to
+    /// encrypt, but is kept so older backups can still be decrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl AppConfig {
+    /// Reads the config at `path`, creating it with a freshly generated
+    /// encryption key if it does not exist yet.
+    pub fn load_or_init(path: &Path) -> Result<Self, ConfigError> {
+        let mut config = if path.exists() {
+            let contents = fs::read_to_string(path).map_err(|e| ConfigError::Read {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            })?;
+            serde_json::from_str(&contents).map_err(|e| ConfigError::Invalid {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            })?
+        } else {
+            AppConfig::default()
+        };
+
+        if config.encryption.keys.is_empty() {
+            config.encryption.add_key();
+            config.save(path)?;
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let write_error = |e: std::io::Error| ConfigError::Write {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        };
+
+        let contents = serde_json::to_string_pretty(self).map_err(|e| ConfigError::Invalid {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents).map_err(write_error)?;
+        restrict_permissions(&tmp_path).map_err(write_error)?;
+        fs::rename(&tmp_path, path).map_err(write_error)
+    }
+}
+
+impl EncryptionConfig {
+    pub fn keyring(&self) -> Result<Keyring, StorageError> {
+        let keys: Vec<(String, String)> = self
+            .keys
+            .iter()
+            .map(|k| (k.id.clone(), k.key.clone()))
+            .collect();
+        Keyring::new(&self.active_key, &keys)
+    }
+
+    /// Generates a new key and makes it the active one. The previous key is
+    /// marked retired but stays in the list for decryption.
+    pub fn add_key(&mut self) -> &EncryptionKey {
+        let now = Utc::now();
+        let active = self.active_key.clone();
+        for key in self.keys.iter_mut().filter(|k| k.id == active) {
+            key.retired_at = Some(now);
+        }
+
+        let id = format!("k{}", now.format("%Y%m%d%H%M%S%f"));
+        self.keys.push(EncryptionKey {
+            id: id.clone(),
+            key: Keyring::generate_key(),
+            retired_at: None,
+        });
+        self.active_key = id;
+        self.keys.last().expect("key was just added")
+    }
+
+    pub fn retired_keys(&self) -> usize {
+        self.keys.iter().filter(|k| k.retired_at.is_some()).count()
+    }
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_out_key_still_decrypts() {
+        let mut config = EncryptionConfig::default();
+        config.add_key();
+        let sealed = config.keyring().unwrap().encrypt("+27820000000").unwrap();
+
+        config.add_key();
+        assert_eq!(config.keys.len(), 2);
+        assert_eq!(config.retired_keys(), 1);
+        assert_eq!(config.keyring().unwrap().decrypt(&sealed).unwrap(), "+27820000000");
+    }
+}
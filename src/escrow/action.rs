@@ -0,0 +1,297 @@
+use crate::escrow::errors::EscrowError;
+use crate::types::{EvidenceKind, GpsLocation};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rust_decimal::Decimal;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Signed ahead of every action so the signature cannot be reused as a
+/// release voucher or any other signed message.
+const SIGNING_CONTEXT: &[u8] = b"spaza-escrow/action/v2";
+/// How old a signed action may be when it reaches the contract, and how far
+/// ahead of our clock the signer's clock may run.
+const MAX_AGE_SECS: i64 = 10 * 60;
+pub(crate) const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+/// Something a user asks the contract to do to an escrow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscrowAction {
+    /// `location` is where the releasing phone was, as proof of delivery.
+    Release { location: Option<GpsLocation> },
+    ReissuePin,
+    AssignDriver { driver_id: Uuid },
+    Cancel,
+    Dispute,
+    Vote { release_to_seller: bool },
+    /// A vote to pay the seller `seller_percent` of what is still held.
+    VoteSplit { seller_percent: Decimal },
+    /// `evidence` is `evidence_digest` of the submission.
+    SubmitEvidence { evidence: [u8; 32] },
+    /// An admin's decision on an escalated dispute.
+    DecideDispute { seller_percent: Decimal },
+    Appeal,
+    AcceptTerms,
+    RejectTerms,
+    ReleaseMilestone { milestone: u32, location: Option<GpsLocation> },
+    /// `received` is `received_digest` of the confirmed quantities.
+    ConfirmDelivery { received: [u8; 32] },
+    /// The buyer pays `amount` into the escrow.
+    Fund { amount: Decimal },
+}
+
+impl EscrowAction {
+    fn tag(&self) -> u8 {
+        match self {
+            EscrowAction::Release { location: None } => 1,
+            EscrowAction::ReissuePin => 2,
+            EscrowAction::Cancel => 3,
+            EscrowAction::Dispute => 4,
+            EscrowAction::Vote { release_to_seller: true } => 5,
+            EscrowAction::Vote { release_to_seller: false } => 6,
+            EscrowAction::Release { location: Some(_) } => 7,
+            EscrowAction::AssignDriver { .. } => 8,
+            EscrowAction::AcceptTerms => 9,
+            EscrowAction::RejectTerms => 10,
+            EscrowAction::ReleaseMilestone { location: None, .. } => 11,
+            EscrowAction::ReleaseMilestone { location: Some(_), .. } => 12,
+            EscrowAction::ConfirmDelivery { .. } => 13,
+            EscrowAction::VoteSplit { .. } => 14,
+            EscrowAction::SubmitEvidence { .. } => 15,
+            EscrowAction::DecideDispute { .. } => 16,
+            EscrowAction::Appeal => 17,
+            EscrowAction::Fund { .. } => 18,
+        }
+    }
+
+    /// Data the action carries beyond its tag, at a fixed width per tag.
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            EscrowAction::Release { location: Some(location) } => location_bytes(location),
+            EscrowAction::ReleaseMilestone { milestone, location } => {
+                let mut bytes = milestone.to_be_bytes().to_vec();
+                bytes.extend(location.as_ref().map(location_bytes).unwrap_or_default());
+                bytes
+            }
+            EscrowAction::AssignDriver { driver_id } => driver_id.as_bytes().to_vec(),
+            EscrowAction::ConfirmDelivery { received } => received.to_vec(),
+            EscrowAction::VoteSplit { seller_percent } | EscrowAction::DecideDispute { seller_percent } => {
+                seller_percent.serialize().to_vec()
+            }
+            EscrowAction::SubmitEvidence { evidence } => evidence.to_vec(),
+            EscrowAction::Fund { amount } => amount.serialize().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// An action signed with the actor's Ed25519 key. The contract checks the
+/// signature against the public key on the actor's user record before it
+/// trusts `actor_id`. The signature covers the escrow version it was made
+/// against, so once the escrow moves on it cannot be replayed.
+#[derive(Debug, Clone)]
+pub struct SignedAction {
+    pub action: EscrowAction,
+    pub escrow_id: Uuid,
+    pub escrow_version: u64,
+    pub actor_id: Uuid,
+    pub issued_at: DateTime<Utc>,
+    pub signature: Signature,
+}
+
+impl SignedAction {
+    pub fn sign(
+        signing_key: &SigningKey,
+        action: EscrowAction,
+        escrow_id: Uuid,
+        escrow_version: u64,
+        actor_id: Uuid,
+    ) -> Self {
+        let issued_at = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+        let message = canonical_bytes(action, escrow_id, escrow_version, actor_id, issued_at);
+
+        Self {
+            action,
+            escrow_id,
+            escrow_version,
+            actor_id,
+            issued_at,
+            signature: signing_key.sign(&message),
+        }
+    }
+
+    /// Checks the signature and that it was made recently. Any failure is
+    /// reported as `Unauthorized` for the claimed actor.
+    pub fn verify(&self, actor_key: &VerifyingKey, now: DateTime<Utc>) -> Result<(), EscrowError> {
+        let message = canonical_bytes(
+            self.action,
+            self.escrow_id,
+            self.escrow_version,
+            self.actor_id,
+            self.issued_at,
+        );
+        let fresh = self.issued_at <= now + Duration::seconds(MAX_CLOCK_SKEW_SECS)
+            && now - self.issued_at <= Duration::seconds(MAX_AGE_SECS);
+
+        if !fresh || actor_key.verify(&message, &self.signature).is_err() {
+            return Err(EscrowError::Unauthorized(self.actor_id));
+        }
+        Ok(())
+    }
+}
+
+/// SHA-256 over the received quantities, so a delivery confirmation can be
+/// signed without the action carrying a variable-length list.
+pub fn received_digest(received: &[u32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for quantity in received {
+        hasher.update(quantity.to_be_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// SHA-256 over a piece of dispute evidence, for the same reason.
+pub fn evidence_digest(kind: EvidenceKind, content: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(kind.to_string());
+    hasher.update([0]);
+    hasher.update(content);
+    hasher.finalize().into()
+}
+
+fn location_bytes(location: &GpsLocation) -> Vec<u8> {
+    let mut bytes = location.latitude.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&location.longitude.to_be_bytes());
+    bytes
+}
+
+/// The exact bytes that get signed: context, action tag, escrow id and
+/// version, actor id, issue time in whole seconds and any action payload.
+pub fn canonical_bytes(
+    action: EscrowAction,
+    escrow_id: Uuid,
+    escrow_version: u64,
+    actor_id: Uuid,
+    issued_at: DateTime<Utc>,
+) -> Vec<u8> {
+    let mut bytes = SIGNING_CONTEXT.to_vec();
+    bytes.push(action.tag());
+    bytes.extend_from_slice(escrow_id.as_bytes());
+    bytes.extend_from_slice(&escrow_version.to_be_bytes());
+    bytes.extend_from_slice(actor_id.as_bytes());
+    bytes.extend_from_slice(&issued_at.timestamp().to_be_bytes());
+    bytes.extend_from_slice(&action.payload());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity;
+
+    /// Signs `action` as if it had been made at `issued_at`.
+    fn signed_at(key: &SigningKey, action: EscrowAction, issued_at: DateTime<Utc>) -> SignedAction {
+        let (escrow_id, actor_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let message = canonical_bytes(action, escrow_id, 3, actor_id, issued_at);
+        SignedAction {
+            action,
+            escrow_id,
+            escrow_version: 3,
+            actor_id,
+            issued_at,
+            signature: key.sign(&message),
+        }
+    }
+
+    #[test]
+    fn signature_verifies_with_the_signers_key_only() {
+        let key = identity::generate_signing_key();
+        let signed = SignedAction::sign(&key, EscrowAction::Cancel, Uuid::new_v4(), 1, Uuid::new_v4());
+
+        signed.verify(&key.verifying_key(), Utc::now()).unwrap();
+        let other = identity::generate_signing_key();
+        assert!(matches!(
+            signed.verify(&other.verifying_key(), Utc::now()),
+            Err(EscrowError::Unauthorized(id)) if id == signed.actor_id
+        ));
+    }
+
+    #[test]
+    fn changing_any_signed_field_breaks_the_signature() {
+        let key = identity::generate_signing_key();
+        let signed = SignedAction::sign(&key, EscrowAction::Dispute, Uuid::new_v4(), 2, Uuid::new_v4());
+        let tampered = [
+            SignedAction { action: EscrowAction::Cancel, ..signed.clone() },
+            SignedAction { escrow_id: Uuid::new_v4(), ..signed.clone() },
+            SignedAction { escrow_version: 3, ..signed.clone() },
+            SignedAction { actor_id: Uuid::new_v4(), ..signed.clone() },
+            SignedAction { issued_at: signed.issued_at - Duration::seconds(1), ..signed.clone() },
+        ];
+
+        for action in tampered {
+            assert!(action.verify(&key.verifying_key(), Utc::now()).is_err(), "{:?} verified", action);
+        }
+    }
+
+    #[test]
+    fn old_actions_are_refused() {
+        let key = identity::generate_signing_key();
+        let now = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+
+        let recent = signed_at(&key, EscrowAction::Dispute, now - Duration::seconds(MAX_AGE_SECS));
+        recent.verify(&key.verifying_key(), now).unwrap();
+        let stale = signed_at(&key, EscrowAction::Dispute, now - Duration::seconds(MAX_AGE_SECS + 1));
+        assert!(stale.verify(&key.verifying_key(), now).is_err());
+    }
+
+    #[test]
+    fn actions_from_the_future_are_allowed_only_small_clock_skew() {
+        let key = identity::generate_signing_key();
+        let now = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+
+        let skewed = signed_at(&key, EscrowAction::Cancel, now + Duration::seconds(MAX_CLOCK_SKEW_SECS));
+        skewed.verify(&key.verifying_key(), now).unwrap();
+        let ahead = signed_at(&key, EscrowAction::Cancel, now + Duration::seconds(MAX_CLOCK_SKEW_SECS + 1));
+        assert!(ahead.verify(&key.verifying_key(), now).is_err());
+    }
+
+    #[test]
+    fn every_action_signs_differently() {
+        let (escrow_id, actor_id, issued_at) = (Uuid::new_v4(), Uuid::new_v4(), Utc::now());
+        let location = GpsLocation { latitude: -26.2, longitude: 28.0 };
+        let actions = [
+            EscrowAction::Release { location: None },
+            EscrowAction::Release { location: Some(location) },
+            EscrowAction::ReissuePin,
+            EscrowAction::AssignDriver { driver_id: Uuid::nil() },
+            EscrowAction::Cancel,
+            EscrowAction::Dispute,
+            EscrowAction::SubmitEvidence { evidence: [0; 32] },
+            EscrowAction::DecideDispute { seller_percent: Decimal::from(70) },
+            EscrowAction::Appeal,
+            EscrowAction::Fund { amount: Decimal::from(50) },
+            EscrowAction::Vote { release_to_seller: true },
+            EscrowAction::Vote { release_to_seller: false },
+            EscrowAction::VoteSplit { seller_percent: Decimal::from(50) },
+            EscrowAction::AcceptTerms,
+            EscrowAction::RejectTerms,
+            EscrowAction::ReleaseMilestone { milestone: 1, location: None },
+            EscrowAction::ReleaseMilestone { milestone: 1, location: Some(location) },
+            EscrowAction::ConfirmDelivery { received: [0; 32] },
+        ];
+
+        let messages: Vec<_> = actions
+            .iter()
+            .map(|action| canonical_bytes(*action, escrow_id, 1, actor_id, issued_at))
+            .collect();
+        for (i, message) in messages.iter().enumerate() {
+            assert!(!messages[i + 1..].contains(message), "{:?} collides", actions[i]);
+        }
+    }
+
+    #[test]
+    fn received_digest_depends_on_every_quantity() {
+        assert_ne!(received_digest(&[1, 2]), received_digest(&[2, 1]));
+        assert_ne!(received_digest(&[1]), received_digest(&[1, 0]));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::escrow::events::{self, EscrowEvent, EscrowEventKind};
+use crate::types::Escrow;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// `prev_hash` of the very first event in the log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize)]
+struct HashInput<'a> {
+    sequence: u64,
+    escrow_id: Uuid,
+    occurred_at: &'a DateTime<Utc>,
+    kind: &'a EscrowEventKind,
+    prev_hash: &'a str,
+}
+
+/// SHA-256 over the event's content and the hash of the event before it.
+pub fn compute_hash(event: &EscrowEvent) -> String {
+    let input = HashInput {
+        sequence: event.sequence,
+        escrow_id: event.escrow_id,
+        occurred_at: &event.occurred_at,
+        kind: &event.kind,
+        prev_hash: &event.prev_hash,
+    };
+    let bytes = serde_json::to_vec(&input).expect("event serialization cannot fail");
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Links `event` after `previous` in the chain, filling in its sequence
+/// number and both hashes.
+pub fn seal(event: &mut EscrowEvent, previous: Option<&EscrowEvent>) {
+    event.sequence = previous.map(|p| p.sequence + 1).unwrap_or(1);
+    event.prev_hash = previous
+        .map(|p| p.hash.clone())
+        .unwrap_or_else(|| GENESIS_HASH.to_string());
+    event.hash = compute_hash(event);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditIssue {
+    /// The record's content no longer matches its own hash.
+    Edited { sequence: u64 },
+    /// The record does not point at the hash of the record before it.
+    BrokenLink { sequence: u64 },
+    /// Sequence numbers jump, so records in between were removed.
+    Missing { from: u64, to: u64 },
+    /// The stored escrow disagrees with what its history replays to.
+    StateMismatch { escrow_id: Uuid },
+    /// A stored escrow has no history at all.
+    NoHistory { escrow_id: Uuid },
+}
+
+impl fmt::Display for AuditIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditIssue::Edited { sequence } => {
+                write!(f, "record #{} was modified after it was written", sequence)
+            }
+            AuditIssue::BrokenLink { sequence } => {
+                write!(f, "record #{} does not link to the record before it", sequence)
+            }
+            AuditIssue::Missing { from, to } if from == to => {
+                write!(f, "record #{} was deleted", from)
+            }
+            AuditIssue::Missing { from, to } => {
+                write!(f, "records #{} to #{} were deleted", from, to)
+            }
+            AuditIssue::StateMismatch { escrow_id } => {
+                write!(f, "escrow {} does not match its recorded history", escrow_id)
+            }
+            AuditIssue::NoHistory { escrow_id } => {
+                write!(f, "escrow {} has no recorded history", escrow_id)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditReport {
+    pub records_checked: usize,
+    pub head_hash: Option<String>,
+    pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+    pub fn is_intact(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Walks the whole log checking every hash and link, then replays each
+/// escrow and compares the result with the stored record. Deleting the most
+/// recent records of an escrow shows up as a state mismatch.
+pub fn verify(log: &[EscrowEvent], escrows: &[Escrow]) -> AuditReport {
+    let mut issues = Vec::new();
+    let mut previous: Option<&EscrowEvent> = None;
+
+    for event in log {
+        let expected_sequence = previous.map(|p| p.sequence + 1).unwrap_or(1);
+        if event.sequence > expected_sequence {
+            issues.push(AuditIssue::Missing {
+                from: expected_sequence,
+                to: event.sequence - 1,
+            });
+        }
+
+        let expected_prev = previous.map(|p| p.hash.as_str()).unwrap_or(GENESIS_HASH);
+        if event.prev_hash != expected_prev {
+            issues.push(AuditIssue::BrokenLink { sequence: event.sequence });
+        }
+
+        if event.hash != compute_hash(event) {
+            issues.push(AuditIssue::Edited { sequence: event.sequence });
+        }
+
+        previous = Some(event);
+    }
+
+    let mut by_escrow: HashMap<Uuid, Vec<EscrowEvent>> = HashMap::new();
+    for event in log {
+        by_escrow.entry(event.escrow_id).or_default().push(event.clone());
+    }
+
+    for escrow in escrows {
+        match by_escrow.get(&escrow.id) {
+            None => issues.push(AuditIssue::NoHistory { escrow_id: escrow.id }),
+            Some(history) => {
+                let matches = events::replay(history)
+                    .map(|replayed| same_state(&replayed, escrow))
+                    .unwrap_or(false);
+                if !matches {
+                    issues.push(AuditIssue::StateMismatch { escrow_id: escrow.id });
+                }
+            }
+        }
+    }
+
+    AuditReport {
+        records_checked: log.len(),
+        head_hash: log.last().map(|e| e.hash.clone()),
+        issues,
+    }
+}
+
+/// Compares every field of the two escrows except the release secrets:
+/// `release_pin_hash`, `totp_secret` and each milestone's `pin_hash`. These
+/// are kept out of the event log on purpose, so replay cannot rebuild them.
+fn same_state(replayed: &Escrow, stored: &Escrow) -> bool {
+    without_secrets(replayed) == without_secrets(stored)
+}
+
+fn without_secrets(escrow: &Escrow) -> Escrow {
+    let mut escrow = escrow.clone();
+    escrow.release_pin_hash = None;
+    escrow.totp_secret = None;
+    for milestone in &mut escrow.milestones {
+        milestone.pin_hash = None;
+    }
+    escrow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::escrow;
+    use rstest::rstest;
+    use rust_decimal::Decimal;
+
+    /// A sealed log for one escrow: created, funded and released, along
+    /// with the escrow in the state that log leads to.
+    fn sealed_log() -> (Vec<EscrowEvent>, Escrow) {
+        let mut escrow = escrow();
+        let mut log = vec![EscrowEvent::created(&escrow)];
+        let kinds = vec![
+            EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id },
+            EscrowEventKind::Released {
+                released_by: escrow.buyer_id,
+                voucher_id: None,
+                location: None,
+                totp_step: None,
+            },
+        ];
+        for kind in kinds {
+            let event = EscrowEvent::new(escrow.id, kind);
+            event.apply(&mut escrow).unwrap();
+            log.push(event);
+        }
+
+        let mut previous: Option<EscrowEvent> = None;
+        for (i, event) in log.iter_mut().enumerate() {
+            event.sequence = i as u64 + 1;
+            seal(event, previous.as_ref());
+            previous = Some(event.clone());
+        }
+        (log, escrow)
+    }
+
+    #[test]
+    fn untouched_log_is_intact() {
+        let (log, escrow) = sealed_log();
+        let report = verify(&log, &[escrow]);
+
+        assert!(report.is_intact(), "{:?}", report.issues);
+        assert_eq!(report.records_checked, 3);
+        assert_eq!(report.head_hash.as_deref(), Some(log[2].hash.as_str()));
+        assert_eq!(log[0].prev_hash, GENESIS_HASH);
+    }
+
+    #[test]
+    fn edited_record_is_caught() {
+        let (mut log, escrow) = sealed_log();
+        log[1].kind = EscrowEventKind::Funded { amount: Decimal::from(1), funded_by: escrow.buyer_id };
+
+        let report = verify(&log, &[escrow]);
+        assert!(report.issues.contains(&AuditIssue::Edited { sequence: 2 }));
+    }
+
+    #[test]
+    fn resealed_record_breaks_the_next_link() {
+        let (mut log, escrow) = sealed_log();
+        log[1].occurred_at += chrono::Duration::seconds(1);
+        log[1].hash = compute_hash(&log[1]);
+
+        let report = verify(&log, &[escrow]);
+        assert!(report.issues.contains(&AuditIssue::BrokenLink { sequence: 3 }));
+        assert!(!report.issues.contains(&AuditIssue::Edited { sequence: 2 }));
+    }
+
+    #[test]
+    fn deleted_record_is_caught() {
+        let (mut log, escrow) = sealed_log();
+        log.remove(1);
+
+        let report = verify(&log, &[escrow]);
+        assert!(report.issues.contains(&AuditIssue::Missing { from: 2, to: 2 }));
+        assert!(report.issues.contains(&AuditIssue::BrokenLink { sequence: 3 }));
+    }
+
+    #[test]
+    fn truncated_history_shows_as_a_state_mismatch() {
+        let (mut log, escrow) = sealed_log();
+        log.pop();
+
+        let escrow_id = escrow.id;
+        let report = verify(&log, &[escrow]);
+        assert_eq!(report.issues, vec![AuditIssue::StateMismatch { escrow_id }]);
+    }
+
+    #[rstest]
+    #[case::driver(|e: &mut Escrow| e.driver_id = Some(Uuid::new_v4()))]
+    #[case::arbitrators(|e: &mut Escrow| e.arbitrators.push(Uuid::new_v4()))]
+    #[case::expiry(|e: &mut Escrow| e.expires_at += chrono::Duration::days(30))]
+    #[case::handover(|e: &mut Escrow| e.handover = None)]
+    #[case::version(|e: &mut Escrow| e.version += 1)]
+    fn tampered_escrow_shows_as_a_state_mismatch(#[case] tamper: fn(&mut Escrow)) {
+        let (log, mut escrow) = sealed_log();
+        tamper(&mut escrow);
+
+        let escrow_id = escrow.id;
+        let report = verify(&log, &[escrow]);
+        assert_eq!(report.issues, vec![AuditIssue::StateMismatch { escrow_id }]);
+    }
+
+    #[test]
+    fn release_secrets_are_not_compared() {
+        let (log, mut escrow) = sealed_log();
+        escrow.release_pin_hash = Some(crate::escrow::pin::hash("4821"));
+
+        assert!(verify(&log, &[escrow]).is_intact());
+    }
+
+    #[test]
+    fn escrow_without_history_is_reported() {
+        let (log, _) = sealed_log();
+        let stray = escrow();
+
+        let escrow_id = stray.id;
+        let report = verify(&log, &[stray]);
+        assert_eq!(report.issues, vec![AuditIssue::NoHistory { escrow_id }]);
+    }
+}
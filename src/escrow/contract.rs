@@ -1,13 +1,56 @@
-use crate::types::escrow::{Escrow, EscrowState, DisputeResolution, Vote, DisputeDecision};
+use crate::types::escrow::{DisputeDecision, Escrow, EscrowState, EvidenceKind, ReleaseMode};
+use crate::types::{User, UserType};
+use crate::escrow::action::{self, EscrowAction, SignedAction};
+use crate::escrow::dispute::DisputePolicy;
 use crate::escrow::errors::EscrowError;
-use chrono::Utc;
+use crate::escrow::events::{EscrowEvent, EscrowEventKind};
+use crate::escrow::permissions;
+use crate::escrow::pin::{self, PinPolicy};
+use crate::escrow::totp;
+use crate::escrow::voucher::ReleaseVoucher;
+use crate::identity;
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::VerifyingKey;
 use rust_decimal::Decimal;
 use uuid::Uuid;
 
 pub struct EscrowContract;
 
 impl EscrowContract {
-    pub fn fund_escrow(escrow: &mut Escrow, amount: Decimal) -> Result<(), EscrowError> {
+    /// The seller agrees to the amount, description and deadline, after
+    /// which the buyer can fund the escrow.
+    pub fn accept_terms(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let seller_id = Self::check_terms_response(escrow, signed, actor, EscrowAction::AcceptTerms)?;
+        Self::commit(escrow, EscrowEventKind::TermsAccepted { accepted_by: seller_id })
+    }
+    
+    /// The seller cannot fill the order; the escrow is cancelled before any
+    /// money is locked up in it.
+    pub fn reject_terms(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let seller_id = Self::check_terms_response(escrow, signed, actor, EscrowAction::RejectTerms)?;
+        Self::commit(escrow, EscrowEventKind::TermsRejected { rejected_by: seller_id })
+    }
+    
+    /// The buyer pays the signed amount into an escrow the seller has
+    /// accepted.
+    pub fn fund_escrow(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let EscrowAction::Fund { amount } = signed.action else {
+            return Err(EscrowError::Unauthorized(signed.actor_id));
+        };
+        let user_id = Self::authenticate(escrow, signed, actor, signed.action)?;
+        
         if escrow.state != EscrowState::Created {
             return Err(EscrowError::InvalidStateTransition {
                 from: format!("{:?}", escrow.state),
@@ -15,6 +58,10 @@ impl EscrowContract {
             });
         }
         
+        if escrow.buyer_id != user_id {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        
         if amount < escrow.amount {
             return Err(EscrowError::InsufficientFunds {
                 required: escrow.amount,
@@ -22,12 +69,104 @@ impl EscrowContract {
             });
         }
         
-        escrow.state = EscrowState::Funded;
-        escrow.funded_at = Some(Utc::now());
-        Ok(())
+        Self::commit(escrow, EscrowEventKind::Funded { amount, funded_by: user_id })
+    }
+    
+    /// `code` is the static PIN, or for `ReleaseMode::Totp` escrows the
+    /// current code from the buyer's authenticator. Wrong codes of either
+    /// kind count towards the same lockout. The buyer or the escrow's
+    /// assigned driver may submit it; the signed location, if any, is kept
+    /// as proof of delivery.
+    pub fn release_to_seller(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        code: &str,
+        policy: &PinPolicy,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let EscrowAction::Release { location } = signed.action else {
+            return Err(EscrowError::Unauthorized(signed.actor_id));
+        };
+        let user_id = Self::authenticate(escrow, signed, actor, signed.action)?;
+        Self::check_releasable(escrow, user_id)?;
+        
+        if !escrow.milestones.is_empty() {
+            return Err(EscrowError::ValidationError(
+                "Escrow is released one milestone at a time".to_string(),
+            ));
+        }
+        
+        let pin_hash = escrow.release_pin_hash.clone();
+        let totp_step = Self::check_code(escrow, pin_hash.as_deref(), code, user_id, policy)?;
+        
+        Self::commit(
+            escrow,
+            EscrowEventKind::Released {
+                released_by: user_id,
+                voucher_id: None,
+                location,
+                totp_step,
+            },
+        )
+    }
+    
+    /// Releases one delivery of a milestone escrow with that milestone's
+    /// PIN, or the current code for `ReleaseMode::Totp` escrows. The escrow
+    /// completes once every milestone is released. Wrong codes for any
+    /// milestone count towards the same lockout.
+    pub fn release_milestone(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        code: &str,
+        policy: &PinPolicy,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let EscrowAction::ReleaseMilestone { milestone: number, location } = signed.action else {
+            return Err(EscrowError::Unauthorized(signed.actor_id));
+        };
+        let user_id = Self::authenticate(escrow, signed, actor, signed.action)?;
+        Self::check_releasable(escrow, user_id)?;
+        
+        let milestone = escrow
+            .milestone(number)
+            .ok_or_else(|| EscrowError::ValidationError(format!("Escrow has no milestone {}", number)))?;
+        if milestone.is_released() {
+            return Err(EscrowError::ValidationError(format!(
+                "Milestone {} is already released",
+                number
+            )));
+        }
+        
+        let pin_hash = milestone.pin_hash.clone();
+        let totp_step = Self::check_code(escrow, pin_hash.as_deref(), code, user_id, policy)?;
+        
+        Self::commit(
+            escrow,
+            EscrowEventKind::MilestoneReleased {
+                milestone: number,
+                released_by: user_id,
+                location,
+                totp_step,
+            },
+        )
     }
     
-    pub fn release_to_seller(escrow: &mut Escrow, user_id: Uuid, pin: &str) -> Result<(), EscrowError> {
+    /// The buyer confirms how many units of each line item arrived, in
+    /// line-item order. The seller is paid for what was received and the
+    /// rest goes back to the buyer; if nothing arrived it is all refunded.
+    pub fn confirm_delivery(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        received: &[u32],
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(
+            escrow,
+            signed,
+            actor,
+            EscrowAction::ConfirmDelivery { received: action::received_digest(received) },
+        )?;
+        
         if escrow.state != EscrowState::Funded {
             return Err(EscrowError::InvalidStateTransition {
                 from: format!("{:?}", escrow.state),
@@ -35,26 +174,181 @@ impl EscrowContract {
             });
         }
         
-        if escrow.is_expired() {
-            return Err(EscrowError::Expired);
+        if escrow.buyer_id != user_id {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        
+        if escrow.line_items.is_empty() || !escrow.milestones.is_empty() {
+            return Err(EscrowError::ValidationError(
+                "Only itemised escrows without milestones can be settled by quantity".to_string(),
+            ));
+        }
+        if received.len() != escrow.line_items.len() {
+            return Err(EscrowError::ValidationError(format!(
+                "Expected {} received quantities, got {}",
+                escrow.line_items.len(),
+                received.len()
+            )));
+        }
+        if let Some((item, _)) = escrow.line_items.iter().zip(received).find(|(item, q)| **q > item.quantity) {
+            return Err(EscrowError::ValidationError(format!(
+                "More {} received than the {} ordered",
+                item.sku, item.quantity
+            )));
+        }
+        
+        Self::commit(
+            escrow,
+            EscrowEventKind::DeliveryConfirmed {
+                confirmed_by: user_id,
+                received: received.to_vec(),
+            },
+        )
+    }
+    
+    /// Releases on the strength of a voucher the buyer signed, possibly
+    /// while offline. `buyer_key` is the public key on the buyer's record.
+    /// A PIN lockout does not block this, since a valid signature cannot
+    /// be guessed. A voucher releases the whole amount, so milestone and
+    /// itemised escrows cannot be settled with one.
+    pub fn redeem_voucher(
+        escrow: &mut Escrow,
+        voucher: &ReleaseVoucher,
+        buyer_key: &VerifyingKey,
+    ) -> Result<EscrowEvent, EscrowError> {
+        Self::check_releasable(escrow, voucher.buyer_id)?;
+        
+        if !escrow.milestones.is_empty() || !escrow.line_items.is_empty() {
+            return Err(EscrowError::ValidationError(
+                "Milestone and itemised escrows cannot be released with a voucher".to_string(),
+            ));
+        }
+        voucher.matches(escrow)?;
+        voucher.verify(buyer_key, Utc::now())?;
+        
+        Self::commit(
+            escrow,
+            EscrowEventKind::Released {
+                released_by: voucher.buyer_id,
+                voucher_id: Some(voucher.id),
+                location: None,
+                totp_step: None,
+            },
+        )
+    }
+    
+    /// Replaces the release PIN with one the caller has just generated and
+    /// hashed; the clear PIN is for the caller to deliver. The hash itself
+    /// stays out of the event, like the original PIN, so the event log never
+    /// holds anything a PIN could be recovered from. Replay therefore cannot
+    /// rebuild it, and the audit leaves it out of the comparison.
+    pub fn reissue_pin(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        new_pin_hash: String,
+        policy: &PinPolicy,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(escrow, signed, actor, EscrowAction::ReissuePin)?;
+        
+        if !matches!(
+            escrow.state,
+            EscrowState::AwaitingSellerAcceptance | EscrowState::Created | EscrowState::Funded
+        ) {
+            return Err(EscrowError::InvalidStateTransition {
+                from: format!("{:?}", escrow.state),
+                to: "PinReissued".to_string(),
+            });
         }
         
         if escrow.buyer_id != user_id {
             return Err(EscrowError::Unauthorized(user_id));
         }
         
-        if escrow.release_pin.as_deref() != Some(pin) {
-            return Err(EscrowError::InvalidPin);
+        if escrow.release_mode == ReleaseMode::Totp {
+            return Err(EscrowError::ValidationError(
+                "Escrow is released with one-time codes, not a PIN".to_string(),
+            ));
         }
         
-        escrow.state = EscrowState::Completed;
-        escrow.completed_at = Some(Utc::now());
-        escrow.release_pin = None;
+        if !escrow.milestones.is_empty() {
+            return Err(EscrowError::ValidationError(
+                "Milestone PINs cannot be reissued".to_string(),
+            ));
+        }
         
-        Ok(())
+        let attempts = &escrow.pin_attempts;
+        if attempts.reissues >= policy.max_reissues {
+            return Err(EscrowError::PinReissueLimit(attempts.reissues));
+        }
+        if let Some(last) = attempts.last_reissued_at {
+            let next_allowed = last + Duration::seconds(policy.reissue_cooldown_secs);
+            if Utc::now() < next_allowed {
+                return Err(EscrowError::PinReissueTooSoon(next_allowed));
+            }
+        }
+        
+        let event = Self::commit(escrow, EscrowEventKind::PinReissued { requested_by: user_id })?;
+        escrow.release_pin_hash = Some(new_pin_hash);
+        Ok(event)
+    }
+    
+    /// Lets `driver` release this escrow on the buyer's behalf, replacing
+    /// any driver assigned before.
+    pub fn assign_driver(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        driver: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(
+            escrow,
+            signed,
+            actor,
+            EscrowAction::AssignDriver { driver_id: driver.id },
+        )?;
+        
+        if !matches!(
+            escrow.state,
+            EscrowState::AwaitingSellerAcceptance | EscrowState::Created | EscrowState::Funded
+        ) {
+            return Err(EscrowError::InvalidStateTransition {
+                from: format!("{:?}", escrow.state),
+                to: "DriverAssigned".to_string(),
+            });
+        }
+        
+        if escrow.buyer_id != user_id {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        
+        if driver.user_type != UserType::Driver {
+            return Err(EscrowError::ValidationError(format!(
+                "User {} is not registered as a driver",
+                driver.id
+            )));
+        }
+        
+        Self::commit(
+            escrow,
+            EscrowEventKind::DriverAssigned {
+                driver_id: driver.id,
+                assigned_by: user_id,
+            },
+        )
     }
     
-    pub fn raise_dispute(escrow: &mut Escrow, user_id: Uuid) -> Result<(), EscrowError> {
+    /// `panel` comes from `dispute::select_panel` and becomes the escrow's
+    /// arbitrators.
+    pub fn raise_dispute(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        policy: &DisputePolicy,
+        panel: Vec<Uuid>,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(escrow, signed, actor, EscrowAction::Dispute)?;
+        
         if escrow.state != EscrowState::Funded {
             return Err(EscrowError::InvalidStateTransition {
                 from: format!("{:?}", escrow.state),
@@ -66,23 +360,84 @@ impl EscrowContract {
             return Err(EscrowError::Unauthorized(user_id));
         }
         
-        escrow.state = EscrowState::InDispute;
-        escrow.dispute_resolution = Some(DisputeResolution {
+        if panel.is_empty() {
+            return Err(EscrowError::NoArbitratorsAvailable);
+        }
+        if panel.contains(&escrow.buyer_id) || panel.contains(&escrow.seller_id) {
+            return Err(EscrowError::ValidationError(
+                "A party to the escrow cannot arbitrate its dispute".to_string(),
+            ));
+        }
+        
+        let evidence_deadline = policy.evidence_deadline(Utc::now());
+        Self::commit(escrow, EscrowEventKind::DisputeRaised {
             raised_by: user_id,
-            raised_at: Utc::now(),
-            votes: Vec::new(),
-            resolved_at: None,
-            decision: None,
-        });
+            evidence_deadline: Some(evidence_deadline),
+            panel,
+            voting_deadline: Some(policy.voting_deadline(evidence_deadline)),
+            appeal_rules: policy.appeal_rules(),
+        })
+    }
+    
+    /// Either party puts a statement, photo hash, file reference or delivery
+    /// note before the arbitrators, until the dispute's evidence deadline.
+    pub fn submit_evidence(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        kind: EvidenceKind,
+        content: &str,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(
+            escrow,
+            signed,
+            actor,
+            EscrowAction::SubmitEvidence { evidence: action::evidence_digest(kind, content) },
+        )?;
         
-        Ok(())
+        let dispute = match (&escrow.state, escrow.dispute_resolution.as_ref()) {
+            (EscrowState::InDispute, Some(dispute)) => dispute,
+            _ => {
+                return Err(EscrowError::ValidationError(
+                    "Evidence can only be submitted while a dispute is open".to_string(),
+                ))
+            }
+        };
+        
+        if escrow.buyer_id != user_id && escrow.seller_id != user_id {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        
+        if !dispute.accepts_evidence_at(Utc::now()) {
+            return Err(EscrowError::EvidenceClosed(
+                dispute.evidence_deadline.unwrap_or(dispute.raised_at),
+            ));
+        }
+        
+        kind.check_content(content).map_err(EscrowError::ValidationError)?;
+        
+        Self::commit(escrow, EscrowEventKind::EvidenceSubmitted {
+            submitted_by: user_id,
+            kind,
+            content: content.to_string(),
+        })
     }
     
     pub fn vote_on_dispute(
-        escrow: &mut Escrow, 
-        arbitrator_id: Uuid, 
-        vote: bool
-    ) -> Result<(), EscrowError> {
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let (vote, seller_percent) = match signed.action {
+            EscrowAction::Vote { release_to_seller } => (release_to_seller, None),
+            EscrowAction::VoteSplit { seller_percent } => {
+                Self::check_seller_percent(seller_percent)?;
+                (false, Some(seller_percent))
+            }
+            _ => return Err(EscrowError::Unauthorized(signed.actor_id)),
+        };
+        let arbitrator_id = Self::authenticate(escrow, signed, actor, signed.action)?;
+        
         if escrow.state != EscrowState::InDispute {
             return Err(EscrowError::InvalidStateTransition {
                 from: format!("{:?}", escrow.state),
@@ -94,61 +449,1269 @@ impl EscrowContract {
             return Err(EscrowError::NotArbitrator);
         }
         
-        let dispute = escrow.dispute_resolution.as_mut()
+        let dispute = escrow.dispute_resolution.as_ref()
             .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
         
         if dispute.votes.iter().any(|v| v.arbitrator_id == arbitrator_id) {
             return Err(EscrowError::ValidationError("Already voted".to_string()));
         }
         
-        dispute.votes.push(Vote {
+        // Arbitrators decide on the full record, so votes wait until both
+        // parties have had their chance to submit evidence.
+        if let Some(deadline) = dispute.evidence_deadline.filter(|deadline| Utc::now() <= *deadline) {
+            return Err(EscrowError::EvidenceOpen(deadline));
+        }
+        
+        if !dispute.accepts_votes_at(Utc::now()) {
+            return Err(EscrowError::VotingClosed);
+        }
+        
+        Self::commit(escrow, EscrowEventKind::VoteCast {
             arbitrator_id,
             vote,
-            voted_at: Utc::now(),
-        });
+            seller_percent,
+        })
+    }
+    
+    /// Moves on a dispute whose deadline passed. A panel decision nobody
+    /// appealed in time becomes final and is paid out. Otherwise, while
+    /// replacement rounds remain, arbitrators who have not voted are swapped
+    /// for `replacements` (from `dispute::select_replacements`) and voting
+    /// reopens; if fewer replacements were drawn than are needed, as many as
+    /// possible are swapped. When nobody can be replaced the dispute goes to
+    /// an admin, with the reason recorded, and if the admin's deadline
+    /// passes too the policy's default outcome is applied.
+    pub fn enforce_dispute_deadline(
+        escrow: &mut Escrow,
+        policy: &DisputePolicy,
+        replacements: Vec<Uuid>,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let now = Utc::now();
+        let dispute = match (&escrow.state, escrow.dispute_resolution.as_ref()) {
+            (EscrowState::InDispute, Some(dispute)) if dispute.is_overdue_at(now) => dispute,
+            _ => {
+                return Err(EscrowError::ValidationError(
+                    "No dispute deadline has passed on this escrow".to_string(),
+                ))
+            }
+        };
+        
+        if dispute.decision.is_some() {
+            return Self::commit(escrow, EscrowEventKind::AppealWindowClosed);
+        }
+        
+        if dispute.escalated_at.is_some() {
+            return Self::commit(escrow, EscrowEventKind::DisputeDecided {
+                decided_by: None,
+                decision: policy.default_outcome.clone(),
+            });
+        }
         
-        let total_arbitrators = escrow.arbitrators.len();
-        let votes_for_release = dispute.votes.iter().filter(|v| v.vote).count();
-        let votes_for_refund = dispute.votes.iter().filter(|v| !v.vote).count();
+        let missing = escrow.arbitrators_yet_to_vote();
+        let reason = if missing.is_empty() {
+            "the panel voted without reaching a decision"
+        } else if dispute.replacement_rounds >= policy.max_replacement_rounds {
+            "no replacement rounds remain"
+        } else if replacements.is_empty() {
+            "no arbitrator is free to replace those who did not vote"
+        } else {
+            if replacements.iter().any(|id| {
+                escrow.arbitrators.contains(id) || *id == escrow.buyer_id || *id == escrow.seller_id
+            }) {
+                return Err(EscrowError::ValidationError(
+                    "Replacements must be new to the panel and not a party to the escrow".to_string(),
+                ));
+            }
+            let replaced = missing.into_iter().take(replacements.len()).collect::<Vec<_>>();
+            let replacements = replacements.into_iter().take(replaced.len()).collect();
+            return Self::commit(escrow, EscrowEventKind::ArbitratorsReplaced {
+                replaced,
+                replacements,
+                voting_deadline: policy.voting_deadline(now),
+            });
+        };
+        
+        Self::commit(escrow, EscrowEventKind::DisputeEscalated {
+            escalation_deadline: policy.escalation_deadline(now),
+            reason: Some(reason.to_string()),
+        })
+    }
+    
+    /// A party the panel decided against sends the decision to `panel`, a
+    /// larger panel from `dispute::select_appeal_panel`, while the appeal
+    /// window is open and appeals remain. The funds stay held meanwhile.
+    pub fn appeal_decision(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        policy: &DisputePolicy,
+        panel: Vec<Uuid>,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(escrow, signed, actor, EscrowAction::Appeal)?;
         
-        let needed_for_majority = (total_arbitrators * 2 + 2) / 3;
+        let dispute = match (&escrow.state, escrow.dispute_resolution.as_ref()) {
+            (EscrowState::InDispute, Some(dispute)) if dispute.decision.is_some() => dispute,
+            _ => {
+                return Err(EscrowError::ValidationError(
+                    "There is no dispute decision to appeal".to_string(),
+                ))
+            }
+        };
         
-        if votes_for_release >= needed_for_majority {
-            dispute.decision = Some(DisputeDecision::ReleaseToSeller);
-            dispute.resolved_at = Some(Utc::now());
-            escrow.state = EscrowState::Completed;
-            escrow.completed_at = Some(Utc::now());
-        } else if votes_for_refund >= needed_for_majority {
-            dispute.decision = Some(DisputeDecision::RefundToBuyer);
-            dispute.resolved_at = Some(Utc::now());
-            escrow.state = EscrowState::Refunded;
+        if escrow.buyer_id != user_id && escrow.seller_id != user_id {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        if !dispute.decision.as_ref().is_some_and(|d| d.went_against(user_id, escrow)) {
+            return Err(EscrowError::ValidationError(
+                "Only a party the decision went against can appeal it".to_string(),
+            ));
         }
         
-        Ok(())
+        if !dispute.can_be_appealed() {
+            return Err(EscrowError::AppealLimit(dispute.appeals.len()));
+        }
+        if !dispute.accepts_appeal_at(Utc::now()) {
+            return Err(EscrowError::AppealWindowClosed);
+        }
+        
+        if panel.len() <= escrow.arbitrators.len() {
+            return Err(EscrowError::NoArbitratorsAvailable);
+        }
+        let sat_before = |id: &Uuid| {
+            escrow.arbitrators.contains(id)
+                || dispute.replaced.contains(id)
+                || dispute.appeals.iter().any(|a| a.panel.contains(id))
+        };
+        if panel.iter().any(|id| sat_before(id) || *id == escrow.buyer_id || *id == escrow.seller_id) {
+            return Err(EscrowError::ValidationError(
+                "An appeal panel must be new to the dispute and not a party to the escrow".to_string(),
+            ));
+        }
+        
+        Self::commit(escrow, EscrowEventKind::DecisionAppealed {
+            appealed_by: user_id,
+            panel,
+            voting_deadline: policy.voting_deadline(Utc::now()),
+        })
     }
     
-    pub fn cancel_escrow(escrow: &mut Escrow, user_id: Uuid) -> Result<(), EscrowError> {
-        if escrow.state != EscrowState::Created {
+    /// An admin decides a dispute the arbitrators could not.
+    pub fn decide_dispute(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let EscrowAction::DecideDispute { seller_percent } = signed.action else {
+            return Err(EscrowError::Unauthorized(signed.actor_id));
+        };
+        let admin_id = Self::authenticate(escrow, signed, actor, signed.action)?;
+        Self::check_seller_percent(seller_percent)?;
+        
+        let escalated = escrow.dispute_resolution.as_ref().is_some_and(|d| d.escalated_at.is_some());
+        if escrow.state != EscrowState::InDispute || !escalated {
+            return Err(EscrowError::ValidationError(
+                "Only disputes escalated to an admin can be decided directly".to_string(),
+            ));
+        }
+        
+        Self::commit(escrow, EscrowEventKind::DisputeDecided {
+            decided_by: Some(admin_id),
+            decision: DisputeDecision::from_seller_percent(seller_percent),
+        })
+    }
+    
+    pub fn cancel_escrow(
+        escrow: &mut Escrow,
+        signed: &SignedAction,
+        actor: &User,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let user_id = Self::authenticate(escrow, signed, actor, EscrowAction::Cancel)?;
+        
+        if !matches!(escrow.state, EscrowState::AwaitingSellerAcceptance | EscrowState::Created) {
             return Err(EscrowError::InvalidStateTransition {
                 from: format!("{:?}", escrow.state),
                 to: "Cancelled".to_string(),
             });
         }
         
-        if escrow.buyer_id != user_id {
+        if escrow.buyer_id != user_id && actor.user_type != UserType::Admin {
             return Err(EscrowError::Unauthorized(user_id));
         }
         
-        escrow.state = EscrowState::Cancelled;
+        Self::commit(escrow, EscrowEventKind::Cancelled { cancelled_by: user_id })
+    }
+    
+    /// Returns the funds of an escrow that passed its deadline without
+    /// being released to the buyer.
+    pub fn refund_expired(escrow: &mut Escrow) -> Result<EscrowEvent, EscrowError> {
+        if escrow.state != EscrowState::Funded || !escrow.is_expired() {
+            return Err(EscrowError::ValidationError(
+                "Only funded escrows past their expiry can be refunded automatically".to_string(),
+            ));
+        }
+        
+        Self::commit(escrow, EscrowEventKind::AutoRefunded)
+    }
+    
+    fn check_seller_percent(seller_percent: Decimal) -> Result<(), EscrowError> {
+        if seller_percent < Decimal::ZERO || seller_percent > Decimal::ONE_HUNDRED {
+            return Err(EscrowError::ValidationError(
+                "Seller share must be between 0 and 100 percent".to_string(),
+            ));
+        }
+        if seller_percent.normalize().scale() > 2 {
+            return Err(EscrowError::ValidationError(
+                "Seller share can have at most two decimal places".to_string(),
+            ));
+        }
         Ok(())
     }
     
-    pub fn auto_refund_if_expired(escrow: &mut Escrow) -> Result<bool, EscrowError> {
-        if escrow.state == EscrowState::Funded && escrow.is_expired() {
-            escrow.state = EscrowState::Refunded;
-            Ok(true)
-        } else {
-            Ok(false)
+    /// Confirms `actor`'s role allows `expected`, and that `signed` is a
+    /// fresh request for it on this escrow, at its current version, signed
+    /// with the key on `actor`'s record. Returns the actor's id.
+    fn authenticate(
+        escrow: &Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        expected: EscrowAction,
+    ) -> Result<Uuid, EscrowError> {
+        if signed.action != expected || signed.escrow_id != escrow.id || signed.actor_id != actor.id {
+            return Err(EscrowError::Unauthorized(signed.actor_id));
+        }
+        permissions::authorize(actor, expected.into())?;
+        
+        let signer_key = actor
+            .public_key
+            .as_deref()
+            .and_then(|key| identity::decode_public_key(key).ok())
+            .ok_or(EscrowError::Unauthorized(actor.id))?;
+        signed.verify(&signer_key, Utc::now())?;
+        
+        if signed.escrow_version != escrow.version {
+            return Err(EscrowError::StaleAction {
+                escrow_id: escrow.id,
+                signed: signed.escrow_version,
+                current: escrow.version,
+            });
+        }
+        Ok(actor.id)
+    }
+    
+    fn check_terms_response(
+        escrow: &Escrow,
+        signed: &SignedAction,
+        actor: &User,
+        action: EscrowAction,
+    ) -> Result<Uuid, EscrowError> {
+        let user_id = Self::authenticate(escrow, signed, actor, action)?;
+        
+        if escrow.state != EscrowState::AwaitingSellerAcceptance {
+            return Err(EscrowError::InvalidStateTransition {
+                from: format!("{:?}", escrow.state),
+                to: if action == EscrowAction::AcceptTerms { "Created" } else { "Cancelled" }.to_string(),
+            });
+        }
+        
+        if escrow.seller_id != user_id {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        
+        Ok(user_id)
+    }
+    
+    fn check_releasable(escrow: &Escrow, user_id: Uuid) -> Result<(), EscrowError> {
+        if escrow.state != EscrowState::Funded {
+            return Err(EscrowError::InvalidStateTransition {
+                from: format!("{:?}", escrow.state),
+                to: "Completed".to_string(),
+            });
+        }
+        
+        if escrow.is_expired() {
+            return Err(EscrowError::Expired);
+        }
+        
+        if escrow.buyer_id != user_id && escrow.driver_id != Some(user_id) {
+            return Err(EscrowError::Unauthorized(user_id));
+        }
+        
+        Ok(())
+    }
+    
+    /// Checks a release code against `pin_hash`, or the TOTP secret for
+    /// `ReleaseMode::Totp` escrows, refusing while a lockout is in force and
+    /// recording the failure if it does not match. Returns an accepted TOTP
+    /// code's step for the release event to record, so the same code cannot
+    /// release twice.
+    fn check_code(
+        escrow: &mut Escrow,
+        pin_hash: Option<&str>,
+        code: &str,
+        user_id: Uuid,
+        policy: &PinPolicy,
+    ) -> Result<Option<i64>, EscrowError> {
+        let now = Utc::now();
+        if let Some(until) = escrow.pin_attempts.locked_until {
+            if now < until {
+                return Err(EscrowError::PinLocked { until });
+            }
+        }
+        
+        let accepted = match escrow.release_mode {
+            ReleaseMode::Pin => pin_hash.is_some_and(|hash| pin::verify(hash, code)).then_some(None),
+            ReleaseMode::Totp => escrow
+                .totp_secret
+                .as_deref()
+                .and_then(|secret| totp::verify(secret, code, now, escrow.totp_last_step))
+                .map(Some),
+        };
+        accepted.ok_or_else(|| Self::record_pin_failure(escrow, user_id, policy, now))
+    }
+    
+    /// Counts a wrong PIN against the escrow, locking it once the policy's
+    /// allowance is used up. The returned error carries the event so the
+    /// failure is recorded even though the release is refused.
+    fn record_pin_failure(
+        escrow: &mut Escrow,
+        user_id: Uuid,
+        policy: &PinPolicy,
+        now: DateTime<Utc>,
+    ) -> EscrowError {
+        let attempts = escrow.pin_attempts.failed + 1;
+        let locked_until = (attempts >= policy.max_attempts)
+            .then(|| now + policy.lockout_duration(escrow.pin_attempts.lockouts));
+        
+        let kind = EscrowEventKind::PinFailed { attempted_by: user_id, locked_until };
+        let reason = match locked_until {
+            Some(until) => EscrowError::TooManyPinAttempts { until },
+            None => EscrowError::InvalidPin,
+        };
+        
+        match Self::commit(escrow, kind) {
+            Ok(event) => EscrowError::Rejected {
+                event: Box::new(event),
+                reason: Box::new(reason),
+            },
+            Err(e) => e,
+        }
+    }
+    
+    fn commit(escrow: &mut Escrow, kind: EscrowEventKind) -> Result<EscrowEvent, EscrowError> {
+        let event = EscrowEvent::new(escrow.id, kind);
+        event.apply(escrow)?;
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity;
+    use crate::test_support;
+    use crate::escrow::action;
+    use crate::escrow::dispute::DisputePolicy;
+    use crate::types::{AppealRules, GpsLocation, LineItem, UserType};
+    use chrono::Duration;
+    use ed25519_dalek::SigningKey;
+
+    /// A registered user together with the secret key they sign with.
+    struct Party {
+        user: User,
+        key: SigningKey,
+    }
+
+    impl Party {
+        fn new(role: UserType) -> Self {
+            let key = identity::generate_signing_key();
+            let mut user = test_support::user(role);
+            user.public_key = Some(identity::encode_public_key(&key.verifying_key()));
+            Self { user, key }
+        }
+
+        fn sign(&self, escrow: &Escrow, action: EscrowAction) -> SignedAction {
+            SignedAction::sign(&self.key, action, escrow.id, escrow.version, self.user.id)
+        }
+    }
+
+    struct Deal {
+        escrow: Escrow,
+        pin: String,
+        buyer: Party,
+        seller: Party,
+    }
+
+    /// An escrow for 1500 whose terms are still waiting on the seller.
+    fn offered() -> Deal {
+        let (buyer, seller) = (Party::new(UserType::Buyer), Party::new(UserType::Seller));
+        let (escrow, pin) = test_support::escrow_with_pin(buyer.user.id, seller.user.id);
+        Deal { escrow, pin, buyer, seller }
+    }
+
+    /// An escrow whose terms the seller accepted, waiting to be funded.
+    fn accepted() -> Deal {
+        let mut deal = offered();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user).unwrap();
+        deal
+    }
+
+    /// An escrow the seller accepted and the buyer paid in full.
+    fn funded() -> Deal {
+        let mut deal = accepted();
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: deal.escrow.amount });
+        EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user).unwrap();
+        deal
+    }
+
+    /// Submits `code` as the buyer. A refused code's event is applied to
+    /// the escrow, as `EscrowService` would persist it.
+    fn release(deal: &mut Deal, code: &str, policy: &PinPolicy) -> Result<EscrowEvent, EscrowError> {
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Release { location: None });
+        match EscrowContract::release_to_seller(&mut deal.escrow, &signed, &deal.buyer.user, code, policy) {
+            Err(EscrowError::Rejected { reason, .. }) => Err(*reason),
+            result => result,
+        }
+    }
+
+    #[test]
+    fn wrong_pins_lock_release_once_the_allowance_is_used() {
+        let mut deal = funded();
+        let policy = PinPolicy { max_attempts: 3, ..PinPolicy::default() };
+
+        assert!(matches!(release(&mut deal, "000000", &policy), Err(EscrowError::InvalidPin)));
+        assert!(matches!(release(&mut deal, "000000", &policy), Err(EscrowError::InvalidPin)));
+        assert_eq!(deal.escrow.pin_attempts.failed, 2);
+
+        let Err(EscrowError::TooManyPinAttempts { until }) = release(&mut deal, "000000", &policy) else {
+            panic!("third wrong PIN should lock release");
+        };
+        assert_eq!(deal.escrow.pin_attempts.locked_until, Some(until));
+        assert_eq!(deal.escrow.pin_attempts.lockouts, 1);
+        assert_eq!(deal.escrow.pin_attempts.failed, 0);
+
+        let pin = deal.pin.clone();
+        assert!(matches!(release(&mut deal, &pin, &policy), Err(EscrowError::PinLocked { .. })));
+        assert_eq!(deal.escrow.state, EscrowState::Funded);
+    }
+
+    #[test]
+    fn lockout_lasts_as_long_as_the_policy_says() {
+        let mut deal = funded();
+        let policy = PinPolicy { max_attempts: 1, lockout_secs: 600, ..PinPolicy::default() };
+        deal.escrow.pin_attempts.lockouts = 2;
+
+        let before = Utc::now();
+        let Err(EscrowError::TooManyPinAttempts { until }) = release(&mut deal, "000000", &policy) else {
+            panic!("wrong PIN should lock release");
+        };
+        let locked_for = (until - before).num_seconds();
+        assert!((2400..=2401).contains(&locked_for), "locked for {}s", locked_for);
+    }
+
+    #[test]
+    fn right_pin_after_the_lockout_expires_releases() {
+        let mut deal = funded();
+        deal.escrow.pin_attempts.locked_until = Some(Utc::now() - Duration::seconds(1));
+        deal.escrow.pin_attempts.lockouts = 1;
+
+        let pin = deal.pin.clone();
+        release(&mut deal, &pin, &PinPolicy::default()).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+        assert!(deal.escrow.pin_attempts.is_clear());
+    }
+
+    fn reissue(deal: &mut Deal, new_pin: &str, policy: &PinPolicy) -> Result<EscrowEvent, EscrowError> {
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::ReissuePin);
+        EscrowContract::reissue_pin(&mut deal.escrow, &signed, &deal.buyer.user, pin::hash(new_pin), policy)
+    }
+
+    #[test]
+    fn reissued_pin_replaces_the_old_one() {
+        let mut deal = funded();
+        let old_pin = deal.pin.clone();
+        reissue(&mut deal, "246810", &PinPolicy::default()).unwrap();
+
+        assert_eq!(deal.escrow.pin_attempts.reissues, 1);
+        assert!(matches!(release(&mut deal, &old_pin, &PinPolicy::default()), Err(EscrowError::InvalidPin)));
+        release(&mut deal, "246810", &PinPolicy::default()).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+    }
+
+    #[test]
+    fn reissues_are_rate_limited_and_capped() {
+        let mut deal = funded();
+        let policy = PinPolicy { max_reissues: 2, reissue_cooldown_secs: 600, ..PinPolicy::default() };
+
+        reissue(&mut deal, "111111", &policy).unwrap();
+        assert!(matches!(reissue(&mut deal, "222222", &policy), Err(EscrowError::PinReissueTooSoon(_))));
+
+        deal.escrow.pin_attempts.last_reissued_at = Some(Utc::now() - Duration::seconds(601));
+        reissue(&mut deal, "222222", &policy).unwrap();
+        deal.escrow.pin_attempts.last_reissued_at = Some(Utc::now() - Duration::seconds(601));
+        assert!(matches!(reissue(&mut deal, "333333", &policy), Err(EscrowError::PinReissueLimit(2))));
+    }
+
+    #[test]
+    fn only_the_buyer_can_have_the_pin_reissued() {
+        let mut deal = funded();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::ReissuePin);
+
+        let result = EscrowContract::reissue_pin(
+            &mut deal.escrow,
+            &signed,
+            &deal.seller.user,
+            pin::hash("246810"),
+            &PinPolicy::default(),
+        );
+        assert!(matches!(result, Err(EscrowError::Forbidden { .. })));
+    }
+
+    #[test]
+    fn totp_escrows_have_no_pin_to_reissue() {
+        let mut deal = funded();
+        deal.escrow.use_totp(totp::generate_secret());
+
+        assert!(matches!(
+            reissue(&mut deal, "246810", &PinPolicy::default()),
+            Err(EscrowError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn totp_escrow_releases_on_the_current_code_only() {
+        let mut deal = funded();
+        deal.escrow.use_totp(totp::generate_secret());
+        let code = totp::code_at(deal.escrow.totp_secret.as_deref().unwrap(), Utc::now()).unwrap();
+
+        let pin = deal.pin.clone();
+        assert!(matches!(release(&mut deal, &pin, &PinPolicy::default()), Err(EscrowError::InvalidPin)));
+        release(&mut deal, &code, &PinPolicy::default()).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+    }
+
+    #[test]
+    fn voucher_releases_a_plain_escrow() {
+        let mut deal = funded();
+        let voucher = ReleaseVoucher::issue(&deal.escrow, &deal.buyer.key, Duration::hours(1)).unwrap();
+
+        EscrowContract::redeem_voucher(&mut deal.escrow, &voucher, &deal.buyer.key.verifying_key()).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+    }
+
+    #[test]
+    fn action_signed_before_the_escrow_moved_on_is_stale() {
+        let mut deal = offered();
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Cancel);
+        let accept = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        EscrowContract::accept_terms(&mut deal.escrow, &accept, &deal.seller.user).unwrap();
+
+        let result = EscrowContract::cancel_escrow(&mut deal.escrow, &signed, &deal.buyer.user);
+        let Err(EscrowError::StaleAction { signed: at, current, .. }) = result else {
+            panic!("expected a stale action, got {:?}", result);
+        };
+        assert_eq!((at, current), (signed.escrow_version, deal.escrow.version));
+        assert!(at < current);
+        assert_eq!(deal.escrow.state, EscrowState::Created);
+    }
+
+    #[test]
+    fn action_is_bound_to_its_escrow_and_signer() {
+        let mut deal = offered();
+        let other = offered();
+        let foreign = deal.seller.sign(&other.escrow, EscrowAction::AcceptTerms);
+        assert!(matches!(
+            EscrowContract::accept_terms(&mut deal.escrow, &foreign, &deal.seller.user),
+            Err(EscrowError::Unauthorized(_))
+        ));
+
+        let mut forged = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        forged.signature = other.seller.sign(&deal.escrow, EscrowAction::AcceptTerms).signature;
+        assert!(matches!(
+            EscrowContract::accept_terms(&mut deal.escrow, &forged, &deal.seller.user),
+            Err(EscrowError::Unauthorized(_))
+        ));
+        assert_eq!(deal.escrow.state, EscrowState::AwaitingSellerAcceptance);
+    }
+
+    #[test]
+    fn action_signed_for_something_else_is_refused() {
+        let mut deal = offered();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::RejectTerms);
+
+        let result = EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user);
+        assert!(matches!(result, Err(EscrowError::Unauthorized(id)) if id == deal.seller.user.id));
+        assert_eq!(deal.escrow.state, EscrowState::AwaitingSellerAcceptance);
+    }
+
+    #[test]
+    fn escrow_cannot_be_funded_before_the_seller_accepts() {
+        let mut deal = offered();
+
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: deal.escrow.amount });
+        assert!(matches!(
+            EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user),
+            Err(EscrowError::InvalidStateTransition { .. })
+        ));
+        assert_eq!(deal.escrow.state, EscrowState::AwaitingSellerAcceptance);
+    }
+
+    #[test]
+    fn only_the_escrows_buyer_can_fund_it() {
+        let mut deal = accepted();
+        let amount = deal.escrow.amount;
+
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::Fund { amount });
+        assert!(matches!(
+            EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.seller.user),
+            Err(EscrowError::Forbidden { operation: permissions::Operation::Fund, .. })
+        ));
+
+        let stranger = Party::new(UserType::Buyer);
+        let signed = stranger.sign(&deal.escrow, EscrowAction::Fund { amount });
+        assert!(matches!(
+            EscrowContract::fund_escrow(&mut deal.escrow, &signed, &stranger.user),
+            Err(EscrowError::Unauthorized(id)) if id == stranger.user.id
+        ));
+        assert_eq!(deal.escrow.state, EscrowState::Created);
+    }
+
+    #[test]
+    fn funding_must_cover_the_escrow_amount() {
+        let mut deal = accepted();
+        let short = deal.escrow.amount - Decimal::new(1, 2);
+
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: short });
+        let result = EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user);
+        assert!(matches!(
+            result,
+            Err(EscrowError::InsufficientFunds { required, provided })
+                if required == deal.escrow.amount && provided == short
+        ));
+
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: deal.escrow.amount });
+        EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Funded);
+    }
+
+    #[test]
+    fn rejected_terms_cancel_the_escrow_for_good() {
+        let mut deal = offered();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::RejectTerms);
+        EscrowContract::reject_terms(&mut deal.escrow, &signed, &deal.seller.user).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Cancelled);
+
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        assert!(matches!(
+            EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user),
+            Err(EscrowError::InvalidStateTransition { .. })
+        ));
+    }
+
+    #[test]
+    fn only_the_named_seller_responds_to_the_terms() {
+        let mut deal = offered();
+        let other_seller = Party::new(UserType::Seller);
+
+        let signed = other_seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        assert!(matches!(
+            EscrowContract::accept_terms(&mut deal.escrow, &signed, &other_seller.user),
+            Err(EscrowError::Unauthorized(id)) if id == other_seller.user.id
+        ));
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        assert!(matches!(
+            EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.buyer.user),
+            Err(EscrowError::Forbidden { .. })
+        ));
+        assert_eq!(deal.escrow.state, EscrowState::AwaitingSellerAcceptance);
+
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Created);
+    }
+
+    fn assign(deal: &mut Deal, driver: &User) -> Result<EscrowEvent, EscrowError> {
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::AssignDriver { driver_id: driver.id });
+        EscrowContract::assign_driver(&mut deal.escrow, &signed, &deal.buyer.user, driver)
+    }
+
+    #[test]
+    fn assigned_driver_releases_and_the_handover_is_recorded() {
+        let mut deal = funded();
+        let driver = Party::new(UserType::Driver);
+        assign(&mut deal, &driver.user).unwrap();
+        assert_eq!(deal.escrow.driver_id, Some(driver.user.id));
+
+        let location = GpsLocation { latitude: -26.2041, longitude: 28.0473 };
+        let signed = driver.sign(&deal.escrow, EscrowAction::Release { location: Some(location) });
+        let pin = deal.pin.clone();
+        EscrowContract::release_to_seller(&mut deal.escrow, &signed, &driver.user, &pin, &PinPolicy::default())
+            .unwrap();
+
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+        let handover = deal.escrow.handover.as_ref().unwrap();
+        assert_eq!(handover.released_by, driver.user.id);
+        assert_eq!(handover.location, Some(location));
+    }
+
+    #[test]
+    fn driver_not_assigned_to_the_escrow_cannot_release() {
+        let mut deal = funded();
+        let assigned = Party::new(UserType::Driver);
+        let other = Party::new(UserType::Driver);
+        assign(&mut deal, &assigned.user).unwrap();
+
+        let signed = other.sign(&deal.escrow, EscrowAction::Release { location: None });
+        let pin = deal.pin.clone();
+        let result =
+            EscrowContract::release_to_seller(&mut deal.escrow, &signed, &other.user, &pin, &PinPolicy::default());
+        assert!(matches!(result, Err(EscrowError::Unauthorized(id)) if id == other.user.id));
+        assert_eq!(deal.escrow.state, EscrowState::Funded);
+    }
+
+    #[test]
+    fn only_the_buyer_assigns_and_only_drivers_can_be_assigned() {
+        let mut deal = funded();
+
+        let not_a_driver = Party::new(UserType::Seller);
+        assert!(matches!(assign(&mut deal, &not_a_driver.user), Err(EscrowError::ValidationError(_))));
+
+        let driver = Party::new(UserType::Driver);
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AssignDriver { driver_id: driver.user.id });
+        assert!(matches!(
+            EscrowContract::assign_driver(&mut deal.escrow, &signed, &deal.seller.user, &driver.user),
+            Err(EscrowError::Forbidden { .. })
+        ));
+        assert_eq!(deal.escrow.driver_id, None);
+    }
+
+    /// A funded escrow split into loads of 1000 and 500, with their PINs.
+    fn in_milestones() -> (Deal, Vec<String>) {
+        let mut deal = offered();
+        let pins = deal
+            .escrow
+            .split_into_milestones(vec![
+                ("First load".to_string(), Decimal::from(1000)),
+                ("Second load".to_string(), Decimal::from(500)),
+            ])
+            .unwrap();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user).unwrap();
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: deal.escrow.amount });
+        EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user).unwrap();
+        (deal, pins)
+    }
+
+    fn release_milestone(deal: &mut Deal, milestone: u32, code: &str) -> Result<EscrowEvent, EscrowError> {
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::ReleaseMilestone { milestone, location: None });
+        let policy = PinPolicy::default();
+        match EscrowContract::release_milestone(&mut deal.escrow, &signed, &deal.buyer.user, code, &policy) {
+            Err(EscrowError::Rejected { reason, .. }) => Err(*reason),
+            result => result,
+        }
+    }
+
+    #[test]
+    fn milestones_release_one_at_a_time_until_the_escrow_completes() {
+        let (mut deal, pins) = in_milestones();
+
+        release_milestone(&mut deal, 1, &pins[0]).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Funded);
+        assert!(deal.escrow.milestone(1).unwrap().is_released());
+        assert!(matches!(release_milestone(&mut deal, 1, &pins[0]), Err(EscrowError::ValidationError(_))));
+
+        release_milestone(&mut deal, 2, &pins[1]).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+    }
+
+    #[test]
+    fn each_milestone_needs_its_own_pin() {
+        let (mut deal, pins) = in_milestones();
+
+        assert!(matches!(release_milestone(&mut deal, 2, &pins[0]), Err(EscrowError::InvalidPin)));
+        assert!(matches!(release_milestone(&mut deal, 3, &pins[0]), Err(EscrowError::ValidationError(_))));
+        let pin = deal.pin.clone();
+        assert!(matches!(release(&mut deal, &pin, &PinPolicy::default()), Err(EscrowError::ValidationError(_))));
+        assert!(!deal.escrow.milestone(2).unwrap().is_released());
+        assert_eq!(deal.escrow.pin_attempts.failed, 1);
+    }
+
+    /// A funded order for 60 bottles of milk and 40 loaves at 15 each.
+    fn itemised() -> Deal {
+        let mut deal = offered();
+        deal.escrow
+            .set_line_items(vec![
+                LineItem::new("MILK-1L", 60, Decimal::from(15)),
+                LineItem::new("BREAD", 40, Decimal::from(15)),
+            ])
+            .unwrap();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user).unwrap();
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: deal.escrow.amount });
+        EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user).unwrap();
+        deal
+    }
+
+    fn confirm(deal: &mut Deal, received: &[u32]) -> Result<EscrowEvent, EscrowError> {
+        let action = EscrowAction::ConfirmDelivery { received: action::received_digest(received) };
+        let signed = deal.buyer.sign(&deal.escrow, action);
+        EscrowContract::confirm_delivery(&mut deal.escrow, &signed, &deal.buyer.user, received)
+    }
+
+    #[test]
+    fn partial_delivery_splits_the_funds() {
+        let mut deal = itemised();
+        confirm(&mut deal, &[60, 25]).unwrap();
+
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+        let settlement = deal.escrow.settlement.as_ref().unwrap();
+        assert_eq!(settlement.to_seller, Decimal::from(1275));
+        assert_eq!(settlement.to_buyer, Decimal::from(225));
+        assert_eq!(deal.escrow.line_items[1].received, Some(25));
+    }
+
+    #[test]
+    fn nothing_delivered_refunds_the_buyer() {
+        let mut deal = itemised();
+        confirm(&mut deal, &[0, 0]).unwrap();
+
+        assert_eq!(deal.escrow.state, EscrowState::Refunded);
+        assert_eq!(deal.escrow.settlement.as_ref().unwrap().to_buyer, deal.escrow.amount);
+    }
+
+    #[test]
+    fn received_quantities_must_match_the_order() {
+        let mut deal = itemised();
+
+        assert!(matches!(confirm(&mut deal, &[61, 40]), Err(EscrowError::ValidationError(_))));
+        assert!(matches!(confirm(&mut deal, &[60]), Err(EscrowError::ValidationError(_))));
+
+        let signed = deal.buyer.sign(
+            &deal.escrow,
+            EscrowAction::ConfirmDelivery { received: action::received_digest(&[60, 40]) },
+        );
+        assert!(matches!(
+            EscrowContract::confirm_delivery(&mut deal.escrow, &signed, &deal.buyer.user, &[60, 39]),
+            Err(EscrowError::Unauthorized(_))
+        ));
+        assert_eq!(deal.escrow.state, EscrowState::Funded);
+    }
+
+    /// A funded escrow the buyer just took to a panel of three.
+    fn raised(policy: &DisputePolicy) -> (Deal, Vec<Party>) {
+        let mut deal = funded();
+        let panel: Vec<Party> = (0..3).map(|_| Party::new(UserType::Arbitrator)).collect();
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Dispute);
+        let ids = panel.iter().map(|a| a.user.id).collect();
+        EscrowContract::raise_dispute(&mut deal.escrow, &signed, &deal.buyer.user, policy, ids).unwrap();
+        (deal, panel)
+    }
+
+    /// As `raised`, with the evidence window already over so the panel can
+    /// vote.
+    fn disputed(policy: &DisputePolicy) -> (Deal, Vec<Party>) {
+        let (mut deal, panel) = raised(policy);
+        close_evidence(&mut deal.escrow);
+        (deal, panel)
+    }
+
+    fn close_evidence(escrow: &mut Escrow) {
+        escrow.dispute_resolution.as_mut().unwrap().evidence_deadline = Some(Utc::now() - Duration::seconds(1));
+    }
+
+    fn vote(escrow: &mut Escrow, arbitrator: &Party, action: EscrowAction) -> Result<EscrowEvent, EscrowError> {
+        let signed = arbitrator.sign(escrow, action);
+        EscrowContract::vote_on_dispute(escrow, &signed, &arbitrator.user)
+    }
+
+    fn no_appeals() -> DisputePolicy {
+        DisputePolicy { max_appeals: 0, ..DisputePolicy::default() }
+    }
+
+    #[test]
+    fn split_votes_settle_by_percentage() {
+        let (mut deal, panel) = disputed(&no_appeals());
+        let share = EscrowAction::VoteSplit { seller_percent: Decimal::new(335, 1) };
+
+        vote(&mut deal.escrow, &panel[0], share).unwrap();
+        vote(&mut deal.escrow, &panel[1], share).unwrap();
+
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+        let settlement = deal.escrow.settlement.as_ref().unwrap();
+        assert_eq!(settlement.to_seller, Decimal::new(50250, 2));
+        assert_eq!(settlement.to_buyer, Decimal::new(99750, 2));
+    }
+
+    #[test]
+    fn seller_share_must_be_a_percentage_to_the_cent() {
+        let (mut deal, panel) = disputed(&DisputePolicy::default());
+
+        for seller_percent in [Decimal::new(-1, 0), Decimal::new(10001, 2), Decimal::new(33333, 3)] {
+            let result = vote(&mut deal.escrow, &panel[0], EscrowAction::VoteSplit { seller_percent });
+            assert!(matches!(result, Err(EscrowError::ValidationError(_))), "{} accepted", seller_percent);
+        }
+        vote(&mut deal.escrow, &panel[0], EscrowAction::VoteSplit { seller_percent: Decimal::new(33330, 3) })
+            .unwrap();
+        assert_eq!(deal.escrow.dispute_resolution.as_ref().unwrap().votes.len(), 1);
+    }
+
+    #[test]
+    fn only_the_panel_votes() {
+        let (mut deal, _) = disputed(&DisputePolicy::default());
+        let outsider = Party::new(UserType::Arbitrator);
+
+        let result = vote(&mut deal.escrow, &outsider, EscrowAction::Vote { release_to_seller: true });
+        assert!(matches!(result, Err(EscrowError::NotArbitrator)));
+        assert_eq!(deal.escrow.state, EscrowState::InDispute);
+    }
+
+    fn submit(
+        escrow: &mut Escrow,
+        party: &Party,
+        kind: EvidenceKind,
+        content: &str,
+    ) -> Result<EscrowEvent, EscrowError> {
+        let evidence = action::evidence_digest(kind, content);
+        let signed = party.sign(escrow, EscrowAction::SubmitEvidence { evidence });
+        EscrowContract::submit_evidence(escrow, &signed, &party.user, kind, content)
+    }
+
+    #[test]
+    fn both_parties_submit_evidence_while_the_window_is_open() {
+        let (mut deal, panel) = raised(&DisputePolicy::default());
+        submit(&mut deal.escrow, &deal.buyer, EvidenceKind::Statement, "Half the crates were broken").unwrap();
+        submit(&mut deal.escrow, &deal.seller, EvidenceKind::DeliveryNote, "DN-2291 signed by the shop").unwrap();
+        assert!(matches!(
+            submit(&mut deal.escrow, &panel[0], EvidenceKind::Statement, "I saw it happen"),
+            Err(EscrowError::Forbidden { .. })
+        ));
+
+        let evidence = &deal.escrow.dispute_resolution.as_ref().unwrap().evidence;
+        assert_eq!(evidence.len(), 2);
+        assert_eq!(evidence[1].submitted_by, deal.seller.user.id);
+    }
+
+    #[test]
+    fn panel_votes_only_after_the_evidence_window() {
+        let (mut deal, panel) = raised(&DisputePolicy::default());
+
+        let result = vote(&mut deal.escrow, &panel[0], EscrowAction::Vote { release_to_seller: true });
+        assert!(matches!(result, Err(EscrowError::EvidenceOpen(_))));
+
+        close_evidence(&mut deal.escrow);
+        assert!(matches!(
+            submit(&mut deal.escrow, &deal.buyer, EvidenceKind::Statement, "One more thing"),
+            Err(EscrowError::EvidenceClosed(_))
+        ));
+        vote(&mut deal.escrow, &panel[0], EscrowAction::Vote { release_to_seller: true }).unwrap();
+    }
+
+    #[test]
+    fn evidence_must_match_what_was_signed() {
+        let (mut deal, _) = raised(&DisputePolicy::default());
+        let signed = deal.buyer.sign(
+            &deal.escrow,
+            EscrowAction::SubmitEvidence { evidence: action::evidence_digest(EvidenceKind::Statement, "Short") },
+        );
+
+        let kind = EvidenceKind::Statement;
+        let result = EscrowContract::submit_evidence(&mut deal.escrow, &signed, &deal.buyer.user, kind, "Long");
+        assert!(matches!(result, Err(EscrowError::Unauthorized(_))));
+        assert!(deal.escrow.dispute_resolution.as_ref().unwrap().evidence.is_empty());
+    }
+
+    #[test]
+    fn dispute_needs_a_panel_free_of_the_parties() {
+        let mut deal = funded();
+        let policy = DisputePolicy::default();
+        let arbitrator = Party::new(UserType::Arbitrator);
+
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Dispute);
+        assert!(matches!(
+            EscrowContract::raise_dispute(&mut deal.escrow, &signed, &deal.buyer.user, &policy, vec![]),
+            Err(EscrowError::NoArbitratorsAvailable)
+        ));
+        let panel = vec![arbitrator.user.id, deal.seller.user.id];
+        assert!(matches!(
+            EscrowContract::raise_dispute(&mut deal.escrow, &signed, &deal.buyer.user, &policy, panel),
+            Err(EscrowError::ValidationError(_))
+        ));
+        assert_eq!(deal.escrow.state, EscrowState::Funded);
+
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::Dispute);
+        let panel = vec![arbitrator.user.id];
+        EscrowContract::raise_dispute(&mut deal.escrow, &signed, &deal.seller.user, &policy, panel).unwrap();
+        assert_eq!(deal.escrow.arbitrators, [arbitrator.user.id]);
+    }
+
+    #[test]
+    fn two_of_three_agreeing_arbitrators_decide() {
+        let (mut deal, panel) = disputed(&no_appeals());
+        let release = EscrowAction::Vote { release_to_seller: true };
+        let refund = EscrowAction::Vote { release_to_seller: false };
+
+        vote(&mut deal.escrow, &panel[0], release).unwrap();
+        vote(&mut deal.escrow, &panel[1], refund).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::InDispute);
+        assert!(matches!(
+            vote(&mut deal.escrow, &panel[0], release),
+            Err(EscrowError::ValidationError(_))
+        ));
+
+        vote(&mut deal.escrow, &panel[2], release).unwrap();
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+        let outsider = Party::new(UserType::Arbitrator);
+        assert!(matches!(vote(&mut deal.escrow, &outsider, release), Err(EscrowError::InvalidStateTransition { .. })));
+    }
+
+    #[test]
+    fn appealable_decision_holds_the_funds() {
+        let (mut deal, panel) = disputed(&DisputePolicy::default());
+        let refund = EscrowAction::Vote { release_to_seller: false };
+
+        vote(&mut deal.escrow, &panel[0], refund).unwrap();
+        vote(&mut deal.escrow, &panel[1], refund).unwrap();
+
+        assert_eq!(deal.escrow.state, EscrowState::InDispute);
+        let dispute = deal.escrow.dispute_resolution.as_ref().unwrap();
+        assert!(matches!(dispute.decision, Some(DisputeDecision::RefundToBuyer)));
+        assert!(dispute.appeal_deadline.is_some());
+    }
+
+    fn pass_voting_deadline(escrow: &mut Escrow) {
+        escrow.dispute_resolution.as_mut().unwrap().voting_deadline = Some(Utc::now() - Duration::seconds(1));
+    }
+
+    fn escalation_reason(event: &EscrowEvent) -> Option<&str> {
+        match &event.kind {
+            EscrowEventKind::DisputeEscalated { reason, .. } => reason.as_deref(),
+            other => panic!("expected an escalation, got {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn deadline_cannot_be_enforced_early() {
+        let (mut deal, _) = disputed(&DisputePolicy::default());
+
+        let result = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &DisputePolicy::default(), vec![]);
+        assert!(matches!(result, Err(EscrowError::ValidationError(_))));
+    }
+
+    #[test]
+    fn missing_voters_are_replaced_as_far_as_replacements_allow() {
+        let policy = DisputePolicy::default();
+        let (mut deal, panel) = disputed(&policy);
+        vote(&mut deal.escrow, &panel[0], EscrowAction::Vote { release_to_seller: true }).unwrap();
+        pass_voting_deadline(&mut deal.escrow);
+
+        let fresh = Party::new(UserType::Arbitrator);
+        let event = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![fresh.user.id]).unwrap();
+        let EscrowEventKind::ArbitratorsReplaced { replaced, replacements, .. } = &event.kind else {
+            panic!("expected a replacement, got {:?}", event.kind);
+        };
+        assert_eq!(replaced, &[panel[1].user.id]);
+        assert_eq!(replacements, &[fresh.user.id]);
+        assert_eq!(deal.escrow.arbitrators, [panel[0].user.id, panel[2].user.id, fresh.user.id]);
+        assert!(!deal.escrow.dispute_resolution.as_ref().unwrap().is_overdue_at(Utc::now()));
+
+        pass_voting_deadline(&mut deal.escrow);
+        let event = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![Uuid::new_v4()]).unwrap();
+        assert_eq!(escalation_reason(&event), Some("no replacement rounds remain"));
+    }
+
+
+    #[test]
+    fn escalation_records_why_nobody_was_replaced() {
+        let policy = DisputePolicy::default();
+
+        let (mut deal, _) = disputed(&policy);
+        pass_voting_deadline(&mut deal.escrow);
+        let event = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![]).unwrap();
+        assert_eq!(escalation_reason(&event), Some("no arbitrator is free to replace those who did not vote"));
+
+        let (mut deal, panel) = disputed(&policy);
+        vote(&mut deal.escrow, &panel[0], EscrowAction::Vote { release_to_seller: true }).unwrap();
+        vote(&mut deal.escrow, &panel[1], EscrowAction::VoteSplit { seller_percent: Decimal::from(50) }).unwrap();
+        vote(&mut deal.escrow, &panel[2], EscrowAction::Vote { release_to_seller: false }).unwrap();
+        pass_voting_deadline(&mut deal.escrow);
+        let event = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![]).unwrap();
+        assert_eq!(escalation_reason(&event), Some("the panel voted without reaching a decision"));
+    }
+
+    #[test]
+    fn replacements_must_be_new_to_the_panel() {
+        let policy = DisputePolicy::default();
+        let (mut deal, panel) = disputed(&policy);
+        pass_voting_deadline(&mut deal.escrow);
+
+        for conflicted in [panel[0].user.id, deal.escrow.seller_id] {
+            let replacements = vec![conflicted, Uuid::new_v4(), Uuid::new_v4()];
+            let result = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, replacements);
+            assert!(matches!(result, Err(EscrowError::ValidationError(_))));
+        }
+        assert_eq!(deal.escrow.dispute_resolution.as_ref().unwrap().replacement_rounds, 0);
+    }
+
+    #[test]
+    fn escalated_dispute_is_decided_by_an_admin_or_the_default_outcome() {
+        let policy = DisputePolicy::default();
+        let admin = Party::new(UserType::Admin);
+
+        let (mut deal, _) = disputed(&policy);
+        let decide = EscrowAction::DecideDispute { seller_percent: Decimal::from(70) };
+        let signed = admin.sign(&deal.escrow, decide);
+        assert!(matches!(
+            EscrowContract::decide_dispute(&mut deal.escrow, &signed, &admin.user),
+            Err(EscrowError::ValidationError(_))
+        ));
+        pass_voting_deadline(&mut deal.escrow);
+        EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![]).unwrap();
+        let signed = admin.sign(&deal.escrow, decide);
+        EscrowContract::decide_dispute(&mut deal.escrow, &signed, &admin.user).unwrap();
+        assert_eq!(deal.escrow.settlement.as_ref().unwrap().to_seller, Decimal::from(1050));
+
+        let (mut deal, _) = disputed(&policy);
+        pass_voting_deadline(&mut deal.escrow);
+        EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![]).unwrap();
+        deal.escrow.dispute_resolution.as_mut().unwrap().escalation_deadline = Some(Utc::now() - Duration::seconds(1));
+        let event = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![]).unwrap();
+        assert!(matches!(event.kind, EscrowEventKind::DisputeDecided { decided_by: None, .. }));
+        assert_eq!(deal.escrow.state, EscrowState::Refunded);
+    }
+    /// A dispute two of three arbitrators decided for the buyer, with the
+    /// appeal window open.
+    fn decided(policy: &DisputePolicy) -> (Deal, Vec<Party>) {
+        let (mut deal, panel) = disputed(policy);
+        let refund = EscrowAction::Vote { release_to_seller: false };
+        vote(&mut deal.escrow, &panel[0], refund).unwrap();
+        vote(&mut deal.escrow, &panel[1], refund).unwrap();
+        (deal, panel)
+    }
+
+    fn appeal(deal: &mut Deal, by_seller: bool, panel: Vec<Uuid>) -> Result<EscrowEvent, EscrowError> {
+        let party = if by_seller { &deal.seller } else { &deal.buyer };
+        let signed = party.sign(&deal.escrow, EscrowAction::Appeal);
+        EscrowContract::appeal_decision(&mut deal.escrow, &signed, &party.user, &DisputePolicy::default(), panel)
+    }
+
+    #[test]
+    fn losing_party_appeals_to_a_larger_new_panel() {
+        let (mut deal, panel) = decided(&DisputePolicy::default());
+        let appeal_panel: Vec<Party> = (0..5).map(|_| Party::new(UserType::Arbitrator)).collect();
+        let ids: Vec<Uuid> = appeal_panel.iter().map(|a| a.user.id).collect();
+
+        assert!(matches!(appeal(&mut deal, false, ids.clone()), Err(EscrowError::ValidationError(_))));
+        appeal(&mut deal, true, ids.clone()).unwrap();
+
+        assert_eq!(deal.escrow.state, EscrowState::InDispute);
+        assert_eq!(deal.escrow.arbitrators, ids);
+        let dispute = deal.escrow.dispute_resolution.as_ref().unwrap();
+        assert!(dispute.decision.is_none());
+        assert_eq!(dispute.appeals.len(), 1);
+        assert_eq!(dispute.appeals[0].panel, panel.iter().map(|a| a.user.id).collect::<Vec<_>>());
+
+        let release = EscrowAction::Vote { release_to_seller: true };
+        assert!(matches!(vote(&mut deal.escrow, &panel[2], release), Err(EscrowError::NotArbitrator)));
+        for arbitrator in &appeal_panel[..4] {
+            vote(&mut deal.escrow, arbitrator, release).unwrap();
+        }
+        assert_eq!(deal.escrow.state, EscrowState::Completed);
+    }
+
+    #[test]
+    fn appeal_panel_must_be_larger_and_new_to_the_dispute() {
+        let (mut deal, panel) = decided(&DisputePolicy::default());
+        let fresh = |n: usize| (0..n).map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+
+        assert!(matches!(appeal(&mut deal, true, fresh(3)), Err(EscrowError::NoArbitratorsAvailable)));
+        let mut reused = fresh(4);
+        reused.push(panel[2].user.id);
+        assert!(matches!(appeal(&mut deal, true, reused), Err(EscrowError::ValidationError(_))));
+        let mut with_party = fresh(4);
+        with_party.push(deal.buyer.user.id);
+        assert!(matches!(appeal(&mut deal, true, with_party), Err(EscrowError::ValidationError(_))));
+        assert!(deal.escrow.dispute_resolution.as_ref().unwrap().appeals.is_empty());
+    }
+
+    #[test]
+    fn decision_becomes_final_once_the_appeal_window_closes() {
+        let policy = DisputePolicy::default();
+        let (mut deal, _) = decided(&policy);
+        deal.escrow.dispute_resolution.as_mut().unwrap().appeal_deadline = Some(Utc::now() - Duration::seconds(1));
+
+        let panel = (0..5).map(|_| Uuid::new_v4()).collect();
+        assert!(matches!(appeal(&mut deal, true, panel), Err(EscrowError::AppealWindowClosed)));
+
+        let event = EscrowContract::enforce_dispute_deadline(&mut deal.escrow, &policy, vec![]).unwrap();
+        assert!(matches!(event.kind, EscrowEventKind::AppealWindowClosed));
+        assert_eq!(deal.escrow.state, EscrowState::Refunded);
+    }
+
+    #[test]
+    fn appeals_stop_at_the_policy_limit() {
+        let (mut deal, _) = decided(&DisputePolicy::default());
+        deal.escrow.dispute_resolution.as_mut().unwrap().appeal_rules =
+            Some(AppealRules { window_secs: 3600, max_appeals: 0 });
+
+        let panel = (0..5).map(|_| Uuid::new_v4()).collect();
+        assert!(matches!(appeal(&mut deal, true, panel), Err(EscrowError::AppealLimit(0))));
+    }
+
+    #[test]
+    fn only_funded_escrows_past_expiry_are_refunded() {
+        let mut deal = funded();
+        assert!(matches!(EscrowContract::refund_expired(&mut deal.escrow), Err(EscrowError::ValidationError(_))));
+
+        deal.escrow.expires_at = Utc::now() - Duration::seconds(1);
+        let event = EscrowContract::refund_expired(&mut deal.escrow).unwrap();
+        assert!(matches!(event.kind, EscrowEventKind::AutoRefunded));
+        assert_eq!(deal.escrow.state, EscrowState::Refunded);
+        assert!(matches!(EscrowContract::refund_expired(&mut deal.escrow), Err(EscrowError::ValidationError(_))));
+    }
+
+    #[test]
+    fn totp_code_cannot_release_twice() {
+        let mut deal = offered();
+        deal.escrow.use_totp(totp::generate_secret());
+        deal.escrow
+            .split_into_milestones(vec![
+                ("First load".to_string(), Decimal::from(1000)),
+                ("Second load".to_string(), Decimal::from(500)),
+            ])
+            .unwrap();
+        let signed = deal.seller.sign(&deal.escrow, EscrowAction::AcceptTerms);
+        EscrowContract::accept_terms(&mut deal.escrow, &signed, &deal.seller.user).unwrap();
+        let signed = deal.buyer.sign(&deal.escrow, EscrowAction::Fund { amount: deal.escrow.amount });
+        EscrowContract::fund_escrow(&mut deal.escrow, &signed, &deal.buyer.user).unwrap();
+
+        let code = totp::code_at(deal.escrow.totp_secret.as_deref().unwrap(), Utc::now()).unwrap();
+        release_milestone(&mut deal, 1, &code).unwrap();
+        assert!(matches!(release_milestone(&mut deal, 2, &code), Err(EscrowError::InvalidPin)));
+        assert!(deal.escrow.totp_last_step.is_some());
+    }
+
+    #[test]
+    fn voucher_cannot_settle_milestone_or_itemised_escrows() {
+        let mut milestones = funded();
+        milestones
+            .escrow
+            .split_into_milestones(vec![
+                ("First load".to_string(), Decimal::from(1000)),
+                ("Second load".to_string(), Decimal::from(500)),
+            ])
+            .unwrap();
+        let mut itemised = funded();
+        itemised
+            .escrow
+            .set_line_items(vec![LineItem::new("MILK-1L", 100, Decimal::from(15))])
+            .unwrap();
+
+        for deal in [&mut milestones, &mut itemised] {
+            let voucher = ReleaseVoucher::issue(&deal.escrow, &deal.buyer.key, Duration::hours(1)).unwrap();
+            let result = EscrowContract::redeem_voucher(&mut deal.escrow, &voucher, &deal.buyer.key.verifying_key());
+            assert!(matches!(result, Err(EscrowError::ValidationError(_))));
+            assert_eq!(deal.escrow.state, EscrowState::Funded);
         }
     }
-}
\ No newline at end of file
+}
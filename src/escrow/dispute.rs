@@ -0,0 +1,390 @@
+use crate::escrow::errors::EscrowError;
+use crate::storage::Storage;
+use crate::types::{AppealRules, DisputeDecision, Escrow, User, UserType};
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use uuid::Uuid;
+
+/// How disputes are run. Both parties may submit evidence for
+/// `evidence_window_secs` after a dispute is raised; arbitrators vote once
+/// that window has closed and have `voting_window_secs` to do so.
+///
+/// Arbitrators who miss the vote are replaced, up to
+/// `max_replacement_rounds` times. After that the dispute goes to an admin,
+/// and if they have not decided within `escalation_window_secs` the
+/// `default_outcome` applies.
+///
+/// A panel's decision can be appealed for `appeal_window_secs`, up to
+/// `max_appeals` times per dispute, each appeal going to a new panel
+/// `appeal_panel_increase` seats larger. Admin decisions are final.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisputePolicy {
+    pub evidence_window_secs: i64,
+    /// Arbitrators drawn for each dispute. A smaller panel sits when fewer
+    /// are free of conflicts.
+    pub panel_size: usize,
+    pub voting_window_secs: i64,
+    pub max_replacement_rounds: u32,
+    pub escalation_window_secs: i64,
+    pub default_outcome: DisputeDecision,
+    pub appeal_window_secs: i64,
+    pub max_appeals: u32,
+    pub appeal_panel_increase: usize,
+}
+
+impl Default for DisputePolicy {
+    fn default() -> Self {
+        Self {
+            evidence_window_secs: 3 * 24 * 60 * 60,
+            panel_size: 3,
+            voting_window_secs: 3 * 24 * 60 * 60,
+            max_replacement_rounds: 1,
+            escalation_window_secs: 2 * 24 * 60 * 60,
+            default_outcome: DisputeDecision::RefundToBuyer,
+            appeal_window_secs: 2 * 24 * 60 * 60,
+            max_appeals: 1,
+            appeal_panel_increase: 2,
+        }
+    }
+}
+
+impl DisputePolicy {
+    pub fn evidence_deadline(&self, raised_at: DateTime<Utc>) -> DateTime<Utc> {
+        raised_at + Duration::seconds(self.evidence_window_secs)
+    }
+    
+    /// When voting that opens at `opens_at` closes.
+    pub fn voting_deadline(&self, opens_at: DateTime<Utc>) -> DateTime<Utc> {
+        opens_at + Duration::seconds(self.voting_window_secs)
+    }
+    
+    pub fn escalation_deadline(&self, escalated_at: DateTime<Utc>) -> DateTime<Utc> {
+        escalated_at + Duration::seconds(self.escalation_window_secs)
+    }
+    
+    /// The appeal rules a newly raised dispute runs under, or `None` when
+    /// appeals are switched off.
+    pub fn appeal_rules(&self) -> Option<AppealRules> {
+        (self.max_appeals > 0 && self.appeal_window_secs > 0).then_some(AppealRules {
+            window_secs: self.appeal_window_secs,
+            max_appeals: self.max_appeals,
+        })
+    }
+}
+
+/// Registered arbitrators who can sign votes, most reputable first.
+pub fn registry(storage: &dyn Storage) -> Result<Vec<User>, EscrowError> {
+    let mut arbitrators: Vec<User> = storage
+        .list_users()?
+        .into_iter()
+        .filter(|u| u.user_type == UserType::Arbitrator && u.public_key.is_some())
+        .collect();
+    arbitrators.sort_by_key(|a| Reverse(a.trust_score.score));
+    Ok(arbitrators)
+}
+
+/// Whether `arbitrator_id` has a stake in a dispute over `escrow`: they are
+/// one of its parties, or have traded with either party before.
+pub fn has_conflict(arbitrator_id: Uuid, escrow: &Escrow, history: &[Escrow]) -> bool {
+    let parties = [escrow.buyer_id, escrow.seller_id];
+    parties.contains(&arbitrator_id)
+        || history.iter().any(|other| {
+            let traders = [other.buyer_id, other.seller_id];
+            traders.contains(&arbitrator_id) && parties.iter().any(|p| traders.contains(p))
+        })
+}
+
+/// Draws the panel for a dispute over `escrow` from the registry, leaving
+/// out anyone with a conflict. Each seat is drawn at random, weighted by
+/// reputation, from the arbitrators not yet seated.
+pub fn select_panel(
+    storage: &dyn Storage,
+    escrow: &Escrow,
+    policy: &DisputePolicy,
+) -> Result<Vec<Uuid>, EscrowError> {
+    let panel = draw_arbitrators(storage, escrow, policy.panel_size, &[])?;
+    if panel.is_empty() {
+        return Err(EscrowError::NoArbitratorsAvailable);
+    }
+    Ok(panel)
+}
+
+/// Draws one replacement for each panel member who has not voted, from
+/// arbitrators who have not sat on this dispute before. Returns fewer than
+/// needed if the registry runs out.
+pub fn select_replacements(storage: &dyn Storage, escrow: &Escrow) -> Result<Vec<Uuid>, EscrowError> {
+    let Some(dispute) = &escrow.dispute_resolution else {
+        return Ok(Vec::new());
+    };
+    let missing = escrow.arbitrators_yet_to_vote().len();
+    let sat_before: Vec<Uuid> = escrow.arbitrators.iter().chain(&dispute.replaced).copied().collect();
+    draw_arbitrators(storage, escrow, missing, &sat_before)
+}
+
+/// Draws a fresh, larger panel to hear an appeal against the current
+/// panel's decision. Nobody who has sat on the dispute before is drawn.
+pub fn select_appeal_panel(
+    storage: &dyn Storage,
+    escrow: &Escrow,
+    policy: &DisputePolicy,
+) -> Result<Vec<Uuid>, EscrowError> {
+    let Some(dispute) = &escrow.dispute_resolution else {
+        return Ok(Vec::new());
+    };
+    let sat_before: Vec<Uuid> = escrow
+        .arbitrators
+        .iter()
+        .chain(&dispute.replaced)
+        .chain(dispute.appeals.iter().flat_map(|a| &a.panel))
+        .copied()
+        .collect();
+    let size = escrow.arbitrators.len() + policy.appeal_panel_increase.max(1);
+    draw_arbitrators(storage, escrow, size, &sat_before)
+}
+
+/// Marks down arbitrators who were replaced for not voting.
+pub fn record_missed_votes(storage: &dyn Storage, arbitrator_ids: &[Uuid]) -> Result<(), EscrowError> {
+    for arbitrator_id in arbitrator_ids {
+        if let Some(mut arbitrator) = storage.get_user(*arbitrator_id)? {
+            arbitrator.trust_score.update_after_dispute(false);
+            storage.update_user(arbitrator)?;
+        }
+    }
+    Ok(())
+}
+
+fn draw_arbitrators(
+    storage: &dyn Storage,
+    escrow: &Escrow,
+    count: usize,
+    excluded: &[Uuid],
+) -> Result<Vec<Uuid>, EscrowError> {
+    let history = storage.list_escrows()?;
+    let candidates: Vec<(Uuid, f64)> = registry(storage)?
+        .into_iter()
+        .filter(|u| !excluded.contains(&u.id) && !has_conflict(u.id, escrow, &history))
+        .map(|u| (u.id, reputation_weight(&u)))
+        .collect();
+
+    Ok(draw_weighted(candidates, count, &mut rand::thread_rng()))
+}
+
+/// Once a dispute is finally decided, arbitrators who voted for the outcome
+/// gain reputation and those who voted otherwise lose some, including on
+/// panels whose decision was appealed.
+pub fn record_outcome(storage: &dyn Storage, escrow: &Escrow) -> Result<(), EscrowError> {
+    let Some(dispute) = &escrow.dispute_resolution else {
+        return Ok(());
+    };
+    let Some(decision) = &dispute.decision else {
+        return Ok(());
+    };
+
+    let earlier_votes = dispute.appeals.iter().flat_map(|a| &a.votes);
+    for vote in dispute.votes.iter().chain(earlier_votes) {
+        if let Some(mut arbitrator) = storage.get_user(vote.arbitrator_id)? {
+            let agreed = vote.seller_percent() == decision.seller_percent();
+            arbitrator.trust_score.update_after_dispute(agreed);
+            storage.update_user(arbitrator)?;
+        }
+    }
+    Ok(())
+}
+
+/// Trust score as a draw weight. Everyone keeps some chance of being drawn,
+/// however low their score.
+fn reputation_weight(arbitrator: &User) -> f64 {
+    arbitrator.trust_score.score.to_f64().unwrap_or(0.0).max(1.0)
+}
+
+fn draw_weighted<R: Rng>(mut candidates: Vec<(Uuid, f64)>, size: usize, rng: &mut R) -> Vec<Uuid> {
+    let mut drawn = Vec::new();
+    while drawn.len() < size && !candidates.is_empty() {
+        let total: f64 = candidates.iter().map(|(_, weight)| weight).sum();
+        let mut point = rng.gen_range(0.0..total);
+        let index = candidates
+            .iter()
+            .position(|(_, weight)| {
+                if point < *weight {
+                    return true;
+                }
+                point -= weight;
+                false
+            })
+            .unwrap_or(candidates.len() - 1);
+        drawn.push(candidates.swap_remove(index).0);
+    }
+    drawn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escrow::events::{EscrowEvent, EscrowEventKind};
+    use crate::identity;
+    use crate::storage::{EscrowRepository, MemoryStorage, UserRepository};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::test_support::escrow_between;
+    use rust_decimal::Decimal;
+
+    /// Registers an arbitrator with a signing key and the given score.
+    fn arbitrator(storage: &MemoryStorage, score: i64) -> Uuid {
+        let mut user = User::new(Uuid::new_v4(), "Arbitrator", "+27820000000", UserType::Arbitrator);
+        user.public_key = Some(identity::encode_public_key(&identity::generate_signing_key().verifying_key()));
+        user.trust_score.score = Decimal::from(score);
+        let id = user.id;
+        storage.create_user(user).unwrap();
+        id
+    }
+
+    #[test]
+    fn draw_picks_distinct_candidates_up_to_the_size() {
+        let candidates: Vec<(Uuid, f64)> = (0..5).map(|_| (Uuid::new_v4(), 1.0)).collect();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let drawn = draw_weighted(candidates.clone(), 3, &mut rng);
+        assert_eq!(drawn.len(), 3);
+        assert!(drawn.iter().all(|id| candidates.iter().any(|(c, _)| c == id)));
+        assert!(!drawn[1..].contains(&drawn[0]) && drawn[1] != drawn[2]);
+
+        assert_eq!(draw_weighted(candidates, 9, &mut rng).len(), 5);
+        assert!(draw_weighted(Vec::new(), 3, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn draw_favours_higher_reputation() {
+        let (trusted, others) = (Uuid::new_v4(), [Uuid::new_v4(), Uuid::new_v4()]);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let first_seats = (0..1000)
+            .filter(|_| {
+                let candidates = vec![(trusted, 90.0), (others[0], 5.0), (others[1], 5.0)];
+                draw_weighted(candidates, 1, &mut rng) == [trusted]
+            })
+            .count();
+        assert!((850..=950).contains(&first_seats), "drawn first {} times in 1000", first_seats);
+    }
+
+    #[test]
+    fn conflicts_cover_parties_and_their_past_trading_partners() {
+        let (buyer, seller, partner, stranger) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let escrow = escrow_between(buyer, seller);
+        let history = [escrow_between(partner, seller), escrow_between(stranger, Uuid::new_v4())];
+
+        assert!(has_conflict(buyer, &escrow, &history));
+        assert!(has_conflict(seller, &escrow, &history));
+        assert!(has_conflict(partner, &escrow, &history));
+        assert!(!has_conflict(stranger, &escrow, &history));
+    }
+
+    #[test]
+    fn panel_leaves_out_conflicted_and_keyless_arbitrators() {
+        let storage = MemoryStorage::new();
+        let free = [arbitrator(&storage, 50), arbitrator(&storage, 80)];
+        let conflicted = arbitrator(&storage, 99);
+        let keyless = User::new(Uuid::new_v4(), "No key", "+27820000001", UserType::Arbitrator);
+        let keyless_id = keyless.id;
+        storage.create_user(keyless).unwrap();
+
+        let escrow = escrow_between(Uuid::new_v4(), Uuid::new_v4());
+        storage.create_escrow(escrow_between(conflicted, escrow.seller_id)).unwrap();
+
+        let panel = select_panel(&storage, &escrow, &DisputePolicy::default()).unwrap();
+        assert_eq!(panel.len(), 2);
+        assert!(free.iter().all(|id| panel.contains(id)));
+        assert!(!panel.contains(&conflicted) && !panel.contains(&keyless_id));
+    }
+
+    #[test]
+    fn no_panel_without_free_arbitrators() {
+        let storage = MemoryStorage::new();
+        let escrow = escrow_between(Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(matches!(
+            select_panel(&storage, &escrow, &DisputePolicy::default()),
+            Err(EscrowError::NoArbitratorsAvailable)
+        ));
+    }
+
+    #[test]
+    fn registry_lists_the_most_reputable_first() {
+        let storage = MemoryStorage::new();
+        let low = arbitrator(&storage, 10);
+        let high = arbitrator(&storage, 90);
+
+        let ids: Vec<Uuid> = registry(&storage).unwrap().iter().map(|u| u.id).collect();
+        assert_eq!(ids, [high, low]);
+    }
+
+    #[test]
+    fn missed_votes_cost_only_the_arbitrators_replaced() {
+        let storage = MemoryStorage::new();
+        let replaced = arbitrator(&storage, 50);
+        let kept = arbitrator(&storage, 50);
+
+        record_missed_votes(&storage, &[replaced, Uuid::new_v4()]).unwrap();
+
+        let score = |id| storage.get_user(id).unwrap().unwrap().trust_score;
+        assert_eq!(score(replaced).total_transactions, 1);
+        assert_eq!(score(replaced).successful_transactions, 0);
+        assert_eq!(score(kept).total_transactions, 0);
+    }
+
+    /// Puts `escrow` in dispute before `panel`, with appeals under the
+    /// default policy.
+    fn dispute_before(escrow: &mut Escrow, panel: &[Uuid]) {
+        let kind = EscrowEventKind::DisputeRaised {
+            raised_by: escrow.buyer_id,
+            evidence_deadline: None,
+            panel: panel.to_vec(),
+            voting_deadline: None,
+            appeal_rules: DisputePolicy::default().appeal_rules(),
+        };
+        EscrowEvent::new(escrow.id, kind).apply(escrow).unwrap();
+    }
+
+    fn cast(escrow: &mut Escrow, arbitrator_id: Uuid, vote: bool) {
+        let kind = EscrowEventKind::VoteCast { arbitrator_id, vote, seller_percent: None };
+        EscrowEvent::new(escrow.id, kind).apply(escrow).unwrap();
+    }
+
+    #[test]
+    fn appeal_panel_is_larger_and_excludes_everyone_who_sat_before() {
+        let storage = MemoryStorage::new();
+        let ids: Vec<Uuid> = (0..8).map(|_| arbitrator(&storage, 50)).collect();
+        let mut escrow = escrow_between(Uuid::new_v4(), Uuid::new_v4());
+        dispute_before(&mut escrow, &ids[..3]);
+
+        let panel = select_appeal_panel(&storage, &escrow, &DisputePolicy::default()).unwrap();
+        assert_eq!(panel.len(), 5);
+        assert!(panel.iter().all(|id| !ids[..3].contains(id)));
+
+        let too_large = DisputePolicy { appeal_panel_increase: 9, ..DisputePolicy::default() };
+        assert_eq!(select_appeal_panel(&storage, &escrow, &too_large).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn outcome_rewards_arbitrators_who_backed_the_decision() {
+        let storage = MemoryStorage::new();
+        let ids: Vec<Uuid> = (0..3).map(|_| arbitrator(&storage, 50)).collect();
+        let mut escrow = escrow_between(Uuid::new_v4(), Uuid::new_v4());
+        dispute_before(&mut escrow, &ids);
+        escrow.dispute_resolution.as_mut().unwrap().appeal_rules = None;
+        cast(&mut escrow, ids[0], false);
+        cast(&mut escrow, ids[1], true);
+        cast(&mut escrow, ids[2], false);
+
+        record_outcome(&storage, &escrow).unwrap();
+
+        let score = |id| storage.get_user(id).unwrap().unwrap().trust_score;
+        assert_eq!(score(ids[0]).successful_transactions, 1);
+        assert_eq!(score(ids[1]).successful_transactions, 0);
+        assert_eq!(score(ids[1]).total_transactions, 1);
+        assert!(score(ids[0]).score > score(ids[1]).score);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::escrow::events::EscrowEvent;
+use crate::escrow::permissions::Operation;
+use crate::types::UserType;
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -21,21 +25,80 @@ pub enum EscrowError {
     #[error("Unauthorized access by user: {0}")]
     Unauthorized(Uuid),
     
+    #[error("User {user_id} is a {role:?}; that role may not {operation}")]
+    Forbidden {
+        user_id: Uuid,
+        role: UserType,
+        operation: Operation,
+    },
+    
     #[error("Invalid release PIN")]
     InvalidPin,
     
+    #[error("Too many wrong PINs; release is locked until {until}")]
+    TooManyPinAttempts { until: DateTime<Utc> },
+    
+    #[error("Release is locked until {until} after too many wrong PINs")]
+    PinLocked { until: DateTime<Utc> },
+    
+    #[error("The PIN has already been reissued {0} times")]
+    PinReissueLimit(u32),
+    
+    #[error("The PIN was reissued recently; try again after {0}")]
+    PinReissueTooSoon(DateTime<Utc>),
+    
+    #[error("Invalid release voucher: {0}")]
+    InvalidVoucher(String),
+    
     #[error("Escrow has expired")]
     Expired,
     
+    #[error("Evidence for this dispute closed at {0}")]
+    EvidenceClosed(DateTime<Utc>),
+    
+    #[error("Evidence for this dispute is open until {0}; votes are taken after that")]
+    EvidenceOpen(DateTime<Utc>),
+    
+    #[error("Voting on this dispute has closed")]
+    VotingClosed,
+    
+    #[error("This dispute has already been appealed {0} time(s), the most allowed")]
+    AppealLimit(usize),
+    
+    #[error("The appeal window for this decision has closed")]
+    AppealWindowClosed,
+    
     #[error("Dispute already resolved")]
     DisputeAlreadyResolved,
     
     #[error("User is not an arbitrator")]
     NotArbitrator,
     
+    #[error("No registered arbitrator is free of a conflict of interest with this escrow's parties")]
+    NoArbitratorsAvailable,
+    
+    #[error("Action was signed for version {signed} of escrow {escrow_id}, which is now at version {current}; sign it again")]
+    StaleAction {
+        escrow_id: Uuid,
+        signed: u64,
+        current: u64,
+    },
+    
+    #[error("Escrow {0} was modified concurrently; reload and retry")]
+    ConcurrentModification(Uuid),
+    
     #[error("Storage error: {0}")]
     StorageError(String),
     
     #[error("Validation error: {0}")]
     ValidationError(String),
+    
+    /// The call was refused, but `event` still belongs in the history (a
+    /// wrong PIN, for instance). `EscrowService` records it and then returns
+    /// `reason` to the caller.
+    #[error("{reason}")]
+    Rejected {
+        event: Box<EscrowEvent>,
+        reason: Box<EscrowError>,
+    },
 }
\ No newline at end of file
@@ -0,0 +1,672 @@
+use crate::escrow::errors::EscrowError;
+use crate::types::escrow::{
+    Appeal, AppealRules, DisputeDecision, DisputeResolution, Escrow, EscrowState, Evidence, EvidenceKind,
+    GpsLocation, Handover, PinAttempts, Settlement, Vote,
+};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+/// One append-only entry in an escrow's history. `sequence` is global across
+/// all escrows, and each event carries the hash of the one before it so the
+/// whole log forms a tamper-evident chain (see `escrow::audit`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscrowEvent {
+    pub sequence: u64,
+    pub escrow_id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub kind: EscrowEventKind,
+    #[serde(default)]
+    pub prev_hash: String,
+    #[serde(default)]
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EscrowEventKind {
+    Created { escrow: Box<Escrow> },
+    /// The seller agreed to the escrow's terms.
+    TermsAccepted { accepted_by: Uuid },
+    /// The seller turned the terms down, which cancels the escrow.
+    TermsRejected { rejected_by: Uuid },
+    Funded {
+        amount: Decimal,
+        /// Nil for events recorded before the funder was kept, and omitted
+        /// then so those records still hash the same.
+        #[serde(default, skip_serializing_if = "Uuid::is_nil")]
+        funded_by: Uuid,
+    },
+    Released {
+        released_by: Uuid,
+        /// Set when the release came from a signed voucher.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        voucher_id: Option<Uuid>,
+        /// Where the code was entered, if the device reported it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<GpsLocation>,
+        /// The time step of the one-time code that released it, if any.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        totp_step: Option<i64>,
+    },
+    /// One milestone's PIN was entered and its amount released.
+    MilestoneReleased {
+        milestone: u32,
+        released_by: Uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<GpsLocation>,
+        /// The time step of the one-time code that released it, if any;
+        /// later milestones need a code from a later step.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        totp_step: Option<i64>,
+    },
+    /// The buyer confirmed how many units of each line item arrived; the
+    /// seller is paid for those and the rest is refunded.
+    DeliveryConfirmed { confirmed_by: Uuid, received: Vec<u32> },
+    /// A wrong release PIN. `locked_until` is set when this attempt used up
+    /// the allowance and locked the escrow.
+    PinFailed {
+        attempted_by: Uuid,
+        locked_until: Option<DateTime<Utc>>,
+    },
+    /// The buyer was sent a new release PIN; the old one no longer works.
+    PinReissued { requested_by: Uuid },
+    DriverAssigned { driver_id: Uuid, assigned_by: Uuid },
+    DisputeRaised {
+        raised_by: Uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        evidence_deadline: Option<DateTime<Utc>>,
+        /// Arbitrators drawn to decide the dispute.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        panel: Vec<Uuid>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        voting_deadline: Option<DateTime<Utc>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        appeal_rules: Option<AppealRules>,
+    },
+    EvidenceSubmitted {
+        submitted_by: Uuid,
+        kind: EvidenceKind,
+        content: String,
+    },
+    VoteCast {
+        arbitrator_id: Uuid,
+        vote: bool,
+        /// Set for a vote to split the funds; see `Vote::seller_percent`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seller_percent: Option<Decimal>,
+    },
+    /// Panel members who missed the voting deadline, swapped for fresh
+    /// draws who vote by the new deadline.
+    ArbitratorsReplaced {
+        replaced: Vec<Uuid>,
+        replacements: Vec<Uuid>,
+        voting_deadline: DateTime<Utc>,
+    },
+    DisputeEscalated {
+        escalation_deadline: DateTime<Utc>,
+        /// Why the panel could not carry on.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Decided by an admin, or by the policy's default outcome when
+    /// `decided_by` is `None`.
+    DisputeDecided {
+        decided_by: Option<Uuid>,
+        decision: DisputeDecision,
+    },
+    /// The panel's decision goes to a new, larger panel.
+    DecisionAppealed {
+        appealed_by: Uuid,
+        panel: Vec<Uuid>,
+        voting_deadline: DateTime<Utc>,
+    },
+    /// Nobody appealed in time, so the panel's decision stands and is paid out.
+    AppealWindowClosed,
+    Cancelled { cancelled_by: Uuid },
+    AutoRefunded,
+}
+
+impl EscrowEvent {
+    pub fn new(escrow_id: Uuid, kind: EscrowEventKind) -> Self {
+        Self {
+            sequence: 0,
+            escrow_id,
+            occurred_at: Utc::now(),
+            kind,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    /// Records the initial terms of an escrow. Secrets such as the release
+    /// PIN or TOTP secret never enter the history, so a replayed escrow
+    /// carries neither.
+    pub fn created(escrow: &Escrow) -> Self {
+        let mut snapshot = escrow.clone();
+        snapshot.release_pin_hash = None;
+        snapshot.totp_secret = None;
+        for milestone in &mut snapshot.milestones {
+            milestone.pin_hash = None;
+        }
+
+        Self {
+            sequence: 0,
+            escrow_id: escrow.id,
+            occurred_at: escrow.created_at,
+            kind: EscrowEventKind::Created {
+                escrow: Box::new(snapshot),
+            },
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    /// Applies this event's state change to `escrow`. The contract uses this
+    /// for live transitions and `replay` uses it to rebuild from history, so
+    /// both always agree.
+    pub fn apply(&self, escrow: &mut Escrow) -> Result<(), EscrowError> {
+        if escrow.id != self.escrow_id {
+            return Err(EscrowError::ValidationError(format!(
+                "Event {} belongs to escrow {}",
+                self.sequence, self.escrow_id
+            )));
+        }
+
+        match &self.kind {
+            EscrowEventKind::Created { .. } => {
+                return Err(EscrowError::ValidationError(
+                    "Escrow already created".to_string(),
+                ));
+            }
+            EscrowEventKind::TermsAccepted { .. } => {
+                escrow.state = EscrowState::Created;
+            }
+            EscrowEventKind::TermsRejected { .. } => {
+                escrow.state = EscrowState::Cancelled;
+            }
+            EscrowEventKind::Funded { .. } => {
+                escrow.state = EscrowState::Funded;
+                escrow.funded_at = Some(self.occurred_at);
+            }
+            EscrowEventKind::Released { released_by, voucher_id, location, totp_step } => {
+                // A voucher is redeemed after the fact, so its release time
+                // says nothing about when the goods changed hands.
+                if voucher_id.is_none() {
+                    escrow.handover = Some(Handover {
+                        released_by: *released_by,
+                        at: self.occurred_at,
+                        location: *location,
+                    });
+                }
+                escrow.state = EscrowState::Completed;
+                escrow.completed_at = Some(self.occurred_at);
+                escrow.release_pin_hash = None;
+                escrow.totp_secret = None;
+                escrow.pin_attempts = PinAttempts::default();
+                if totp_step.is_some() {
+                    escrow.totp_last_step = *totp_step;
+                }
+            }
+            EscrowEventKind::MilestoneReleased { milestone, released_by, location, totp_step } => {
+                let number = *milestone;
+                let milestone = escrow
+                    .milestones
+                    .iter_mut()
+                    .find(|m| m.number == number)
+                    .ok_or_else(|| EscrowError::ValidationError(format!("No milestone {}", number)))?;
+                milestone.pin_hash = None;
+                milestone.handover = Some(Handover {
+                    released_by: *released_by,
+                    at: self.occurred_at,
+                    location: *location,
+                });
+                escrow.pin_attempts.failed = 0;
+                if totp_step.is_some() {
+                    escrow.totp_last_step = *totp_step;
+                }
+
+                if escrow.milestones.iter().all(|m| m.is_released()) {
+                    escrow.state = EscrowState::Completed;
+                    escrow.completed_at = Some(self.occurred_at);
+                    escrow.totp_secret = None;
+                    escrow.pin_attempts = PinAttempts::default();
+                }
+            }
+            EscrowEventKind::DeliveryConfirmed { received, .. } => {
+                if received.len() != escrow.line_items.len() {
+                    return Err(EscrowError::ValidationError(
+                        "Received quantities do not match the line items".to_string(),
+                    ));
+                }
+                let settlement = escrow.settlement_for_received(received);
+                for (item, quantity) in escrow.line_items.iter_mut().zip(received) {
+                    item.received = Some(*quantity);
+                }
+
+                escrow.state = if settlement.to_seller.is_zero() {
+                    EscrowState::Refunded
+                } else {
+                    escrow.completed_at = Some(self.occurred_at);
+                    EscrowState::Completed
+                };
+                escrow.settlement = Some(settlement);
+                escrow.release_pin_hash = None;
+                escrow.totp_secret = None;
+                escrow.pin_attempts = PinAttempts::default();
+            }
+            EscrowEventKind::PinFailed { locked_until, .. } => {
+                let attempts = &mut escrow.pin_attempts;
+                match locked_until {
+                    Some(until) => {
+                        attempts.failed = 0;
+                        attempts.lockouts += 1;
+                        attempts.locked_until = Some(*until);
+                    }
+                    None => attempts.failed += 1,
+                }
+            }
+            EscrowEventKind::PinReissued { .. } => {
+                escrow.pin_attempts.reissues += 1;
+                escrow.pin_attempts.last_reissued_at = Some(self.occurred_at);
+            }
+            EscrowEventKind::DriverAssigned { driver_id, .. } => {
+                escrow.driver_id = Some(*driver_id);
+            }
+            EscrowEventKind::DisputeRaised {
+                raised_by,
+                evidence_deadline,
+                panel,
+                voting_deadline,
+                appeal_rules,
+            } => {
+                escrow.state = EscrowState::InDispute;
+                if !panel.is_empty() {
+                    escrow.arbitrators = panel.clone();
+                }
+                escrow.dispute_resolution = Some(DisputeResolution {
+                    raised_by: *raised_by,
+                    raised_at: self.occurred_at,
+                    votes: Vec::new(),
+                    resolved_at: None,
+                    decision: None,
+                    evidence_deadline: *evidence_deadline,
+                    evidence: Vec::new(),
+                    voting_deadline: *voting_deadline,
+                    replaced: Vec::new(),
+                    replacement_rounds: 0,
+                    escalated_at: None,
+                    escalation_deadline: None,
+                    appeal_rules: appeal_rules.clone(),
+                    appeal_deadline: None,
+                    appeals: Vec::new(),
+                });
+            }
+            EscrowEventKind::EvidenceSubmitted { submitted_by, kind, content } => {
+                let dispute = escrow.dispute_resolution.as_mut()
+                    .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
+                dispute.evidence.push(Evidence {
+                    submitted_by: *submitted_by,
+                    kind: *kind,
+                    content: content.clone(),
+                    submitted_at: self.occurred_at,
+                });
+            }
+            EscrowEventKind::VoteCast { arbitrator_id, vote, seller_percent } => {
+                let total_arbitrators = escrow.arbitrators.len();
+                let dispute = escrow.dispute_resolution.as_mut()
+                    .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
+
+                let cast = Vote {
+                    arbitrator_id: *arbitrator_id,
+                    vote: *vote,
+                    voted_at: self.occurred_at,
+                    seller_percent: *seller_percent,
+                };
+                let share = cast.seller_percent();
+                dispute.votes.push(cast);
+
+                // Arbitrators agree when enough of them back the same share;
+                // only the newest vote can have tipped a share over the line.
+                let agreeing = dispute.votes.iter().filter(|v| v.seller_percent() == share).count();
+                let needed_for_majority = (total_arbitrators * 2).div_ceil(3);
+
+                if agreeing >= needed_for_majority {
+                    let decision = DisputeDecision::from_seller_percent(share);
+                    let appeal_window = dispute
+                        .appeal_rules
+                        .as_ref()
+                        .filter(|_| dispute.can_be_appealed())
+                        .map(|rules| Duration::seconds(rules.window_secs));
+                    match appeal_window {
+                        // Funds stay held while the decision can be appealed.
+                        Some(window) => {
+                            dispute.decision = Some(decision);
+                            dispute.appeal_deadline = Some(self.occurred_at + window);
+                        }
+                        None => settle_dispute(escrow, decision, self.occurred_at),
+                    }
+                }
+            }
+            EscrowEventKind::ArbitratorsReplaced { replaced, replacements, voting_deadline } => {
+                escrow.arbitrators.retain(|id| !replaced.contains(id));
+                escrow.arbitrators.extend(replacements);
+                let dispute = escrow.dispute_resolution.as_mut()
+                    .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
+                dispute.replaced.extend(replaced);
+                dispute.replacement_rounds += 1;
+                dispute.voting_deadline = Some(*voting_deadline);
+            }
+            EscrowEventKind::DisputeEscalated { escalation_deadline, .. } => {
+                let dispute = escrow.dispute_resolution.as_mut()
+                    .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
+                dispute.escalated_at = Some(self.occurred_at);
+                dispute.escalation_deadline = Some(*escalation_deadline);
+            }
+            EscrowEventKind::DisputeDecided { decision, .. } => {
+                settle_dispute(escrow, decision.clone(), self.occurred_at);
+            }
+            EscrowEventKind::DecisionAppealed { appealed_by, panel, voting_deadline } => {
+                let previous_panel = std::mem::replace(&mut escrow.arbitrators, panel.clone());
+                let dispute = escrow.dispute_resolution.as_mut()
+                    .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
+                let decision = dispute.decision.take()
+                    .ok_or_else(|| EscrowError::ValidationError("No decision to appeal".to_string()))?;
+                dispute.appeals.push(Appeal {
+                    appealed_by: *appealed_by,
+                    appealed_at: self.occurred_at,
+                    panel: previous_panel,
+                    votes: std::mem::take(&mut dispute.votes),
+                    decision,
+                });
+                dispute.appeal_deadline = None;
+                dispute.voting_deadline = Some(*voting_deadline);
+                dispute.replacement_rounds = 0;
+            }
+            EscrowEventKind::AppealWindowClosed => {
+                let dispute = escrow.dispute_resolution.as_mut()
+                    .ok_or_else(|| EscrowError::ValidationError("No dispute found".to_string()))?;
+                dispute.appeal_deadline = None;
+                let decision = dispute.decision.clone()
+                    .ok_or_else(|| EscrowError::ValidationError("No decision to pay out".to_string()))?;
+                settle_dispute(escrow, decision, self.occurred_at);
+            }
+            EscrowEventKind::Cancelled { .. } => {
+                escrow.state = EscrowState::Cancelled;
+            }
+            EscrowEventKind::AutoRefunded => {
+                escrow.state = EscrowState::Refunded;
+            }
+        }
+
+        escrow.version += 1;
+        Ok(())
+    }
+}
+
+impl EscrowEventKind {
+    /// The user who triggered the event, if it was not a system action.
+    pub fn actor(&self) -> Option<Uuid> {
+        match self {
+            EscrowEventKind::Created { escrow } => Some(escrow.buyer_id),
+            EscrowEventKind::TermsAccepted { accepted_by } => Some(*accepted_by),
+            EscrowEventKind::TermsRejected { rejected_by } => Some(*rejected_by),
+            EscrowEventKind::Funded { funded_by, .. } => Some(*funded_by).filter(|id| !id.is_nil()),
+            EscrowEventKind::Released { released_by, .. } => Some(*released_by),
+            EscrowEventKind::MilestoneReleased { released_by, .. } => Some(*released_by),
+            EscrowEventKind::DeliveryConfirmed { confirmed_by, .. } => Some(*confirmed_by),
+            EscrowEventKind::PinFailed { attempted_by, .. } => Some(*attempted_by),
+            EscrowEventKind::PinReissued { requested_by } => Some(*requested_by),
+            EscrowEventKind::DriverAssigned { assigned_by, .. } => Some(*assigned_by),
+            EscrowEventKind::DisputeRaised { raised_by, .. } => Some(*raised_by),
+            EscrowEventKind::EvidenceSubmitted { submitted_by, .. } => Some(*submitted_by),
+            EscrowEventKind::VoteCast { arbitrator_id, .. } => Some(*arbitrator_id),
+            EscrowEventKind::ArbitratorsReplaced { .. } => None,
+            EscrowEventKind::DisputeEscalated { .. } => None,
+            EscrowEventKind::DisputeDecided { decided_by, .. } => *decided_by,
+            EscrowEventKind::DecisionAppealed { appealed_by, .. } => Some(*appealed_by),
+            EscrowEventKind::AppealWindowClosed => None,
+            EscrowEventKind::Cancelled { cancelled_by } => Some(*cancelled_by),
+            EscrowEventKind::AutoRefunded => None,
+        }
+    }
+}
+
+impl fmt::Display for EscrowEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EscrowEventKind::Created { escrow } => {
+                write!(f, "Created for {} {}", escrow.amount, escrow.currency)
+            }
+            EscrowEventKind::TermsAccepted { .. } => write!(f, "Terms accepted by seller"),
+            EscrowEventKind::TermsRejected { .. } => write!(f, "Terms rejected by seller"),
+            EscrowEventKind::Funded { amount, .. } => write!(f, "Funded with {}", amount),
+            EscrowEventKind::Released { voucher_id: None, location: None, .. } => write!(f, "Released to seller"),
+            EscrowEventKind::Released { voucher_id: None, location: Some(location), .. } => {
+                write!(f, "Released to seller at {}", location)
+            }
+            EscrowEventKind::Released { voucher_id: Some(id), .. } => {
+                write!(f, "Released to seller by voucher {}", &id.to_string()[..8])
+            }
+            EscrowEventKind::MilestoneReleased { milestone, location: None, .. } => {
+                write!(f, "Milestone {} released", milestone)
+            }
+            EscrowEventKind::MilestoneReleased { milestone, location: Some(location), .. } => {
+                write!(f, "Milestone {} released at {}", milestone, location)
+            }
+            EscrowEventKind::DeliveryConfirmed { received, .. } => write!(
+                f,
+                "Delivery confirmed: {} units received",
+                received.iter().map(|q| *q as u64).sum::<u64>()
+            ),
+            EscrowEventKind::PinFailed { locked_until: None, .. } => write!(f, "Wrong PIN entered"),
+            EscrowEventKind::PinFailed { locked_until: Some(until), .. } => write!(
+                f,
+                "Wrong PIN entered; locked until {}",
+                until.format("%Y-%m-%d %H:%M:%S")
+            ),
+            EscrowEventKind::PinReissued { .. } => write!(f, "Release PIN reissued"),
+            EscrowEventKind::DriverAssigned { driver_id, .. } => {
+                write!(f, "Driver {} assigned", &driver_id.to_string()[..8])
+            }
+            EscrowEventKind::DisputeRaised { .. } => write!(f, "Dispute raised"),
+            EscrowEventKind::EvidenceSubmitted { kind, .. } => write!(f, "Submitted {} as evidence", kind),
+            EscrowEventKind::VoteCast { seller_percent: Some(percent), .. } => {
+                write!(f, "Voted to give seller {}%", percent.normalize())
+            }
+            EscrowEventKind::VoteCast { vote, .. } => write!(
+                f,
+                "Voted to {}",
+                if *vote { "release to seller" } else { "refund buyer" }
+            ),
+            EscrowEventKind::ArbitratorsReplaced { replaced, .. } => {
+                write!(f, "Replaced {} arbitrator(s) who missed the vote", replaced.len())
+            }
+            EscrowEventKind::DisputeEscalated { reason: Some(reason), .. } => {
+                write!(f, "Dispute escalated to an admin: {}", reason)
+            }
+            EscrowEventKind::DisputeEscalated { reason: None, .. } => write!(f, "Dispute escalated to an admin"),
+            EscrowEventKind::DisputeDecided { decided_by: Some(_), decision } => {
+                write!(f, "Admin decided to {}", decision)
+            }
+            EscrowEventKind::DisputeDecided { decided_by: None, decision } => {
+                write!(f, "No decision in time; default outcome is to {}", decision)
+            }
+            EscrowEventKind::DecisionAppealed { panel, .. } => {
+                write!(f, "Decision appealed to a panel of {}", panel.len())
+            }
+            EscrowEventKind::AppealWindowClosed => write!(f, "Appeal window closed; decision is final"),
+            EscrowEventKind::Cancelled { .. } => write!(f, "Cancelled"),
+            EscrowEventKind::AutoRefunded => write!(f, "Refunded after expiry"),
+        }
+    }
+}
+
+/// Records `decision` on the escrow's dispute and pays out accordingly.
+fn settle_dispute(escrow: &mut Escrow, decision: DisputeDecision, at: DateTime<Utc>) {
+    match decision {
+        DisputeDecision::RefundToBuyer => escrow.state = EscrowState::Refunded,
+        DisputeDecision::ReleaseToSeller => {
+            escrow.state = EscrowState::Completed;
+            escrow.completed_at = Some(at);
+        }
+        DisputeDecision::Split { seller_percent } => {
+            escrow.settlement = Some(Settlement::split(escrow.outstanding_amount(), seller_percent));
+            escrow.state = EscrowState::Completed;
+            escrow.completed_at = Some(at);
+        }
+    }
+    if let Some(dispute) = escrow.dispute_resolution.as_mut() {
+        dispute.resolved_at = Some(at);
+        dispute.decision = Some(decision);
+    }
+}
+
+/// Rebuilds an escrow from its complete, ordered history.
+pub fn replay(events: &[EscrowEvent]) -> Result<Escrow, EscrowError> {
+    let (first, rest) = events.split_first()
+        .ok_or_else(|| EscrowError::ValidationError("Escrow has no history".to_string()))?;
+
+    let mut escrow = match &first.kind {
+        EscrowEventKind::Created { escrow } => escrow.as_ref().clone(),
+        _ => {
+            return Err(EscrowError::ValidationError(
+                "History does not start with creation".to_string(),
+            ))
+        }
+    };
+
+    for event in rest {
+        event.apply(&mut escrow)?;
+    }
+
+    Ok(escrow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::escrow;
+
+    /// Applies `kinds` to `escrow` as live transitions would, returning the
+    /// full history starting with creation.
+    fn run(escrow: &mut Escrow, kinds: Vec<EscrowEventKind>) -> Vec<EscrowEvent> {
+        let mut history = vec![EscrowEvent::created(escrow)];
+        for kind in kinds {
+            let event = EscrowEvent::new(escrow.id, kind);
+            event.apply(escrow).unwrap();
+            history.push(event);
+        }
+        history
+    }
+
+    #[test]
+    fn replay_rebuilds_the_live_state() {
+        let mut escrow = escrow();
+        let kinds = vec![
+            EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id },
+            EscrowEventKind::Released {
+                released_by: escrow.buyer_id,
+                voucher_id: None,
+                location: None,
+                totp_step: None,
+            },
+        ];
+        let history = run(&mut escrow, kinds);
+
+        let replayed = replay(&history).unwrap();
+        assert_eq!(replayed.state, EscrowState::Completed);
+        assert_eq!(replayed.funded_at, escrow.funded_at);
+        assert_eq!(replayed.completed_at, escrow.completed_at);
+    }
+
+    #[test]
+    fn replay_restores_the_last_accepted_code_step() {
+        let mut escrow = escrow();
+        let kinds = vec![
+            EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id },
+            EscrowEventKind::Released {
+                released_by: escrow.buyer_id,
+                voucher_id: None,
+                location: None,
+                totp_step: Some(57_000_000),
+            },
+        ];
+        let history = run(&mut escrow, kinds);
+
+        assert_eq!(escrow.totp_last_step, Some(57_000_000));
+        assert_eq!(replay(&history).unwrap().totp_last_step, Some(57_000_000));
+    }
+
+    #[test]
+    fn two_thirds_of_the_panel_decide_a_dispute() {
+        let mut escrow = escrow();
+        let panel = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        escrow.arbitrators = panel.to_vec();
+        let vote = |arbitrator_id, vote| EscrowEventKind::VoteCast { arbitrator_id, vote, seller_percent: None };
+        let kinds = vec![
+            EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id },
+            EscrowEventKind::DisputeRaised {
+                raised_by: escrow.buyer_id,
+                evidence_deadline: None,
+                voting_deadline: None,
+                panel: Vec::new(),
+                appeal_rules: None,
+            },
+            vote(panel[0], true),
+            vote(panel[1], false),
+        ];
+        run(&mut escrow, kinds);
+        assert_eq!(escrow.state, EscrowState::InDispute);
+
+        EscrowEvent::new(escrow.id, vote(panel[2], false)).apply(&mut escrow).unwrap();
+        assert_eq!(escrow.state, EscrowState::Refunded);
+        assert!(matches!(
+            escrow.dispute_resolution.unwrap().decision,
+            Some(DisputeDecision::RefundToBuyer)
+        ));
+    }
+
+    #[test]
+    fn creation_snapshot_carries_no_secrets() {
+        let escrow = escrow();
+        assert!(escrow.release_pin_hash.is_some());
+
+        let EscrowEventKind::Created { escrow: snapshot } = EscrowEvent::created(&escrow).kind else {
+            panic!("expected a creation event");
+        };
+        assert!(snapshot.release_pin_hash.is_none());
+    }
+
+    #[test]
+    fn replay_needs_a_creation_event_first() {
+        let escrow = escrow();
+        let kind = EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id };
+        let funded = EscrowEvent::new(escrow.id, kind);
+
+        assert!(replay(&[]).is_err());
+        assert!(replay(&[funded]).is_err());
+    }
+
+    #[test]
+    fn event_for_another_escrow_is_refused() {
+        let mut escrow = escrow();
+        let kind = EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id };
+        let other = EscrowEvent::new(Uuid::new_v4(), kind);
+
+        assert!(matches!(other.apply(&mut escrow), Err(EscrowError::ValidationError(_))));
+        assert_eq!(escrow.state, EscrowState::AwaitingSellerAcceptance);
+    }
+
+    #[test]
+    fn funding_is_attributed_to_the_funder() {
+        let buyer_id = Uuid::new_v4();
+        let kind = EscrowEventKind::Funded { amount: Decimal::from(1500), funded_by: buyer_id };
+        assert_eq!(kind.actor(), Some(buyer_id));
+
+        let recorded: EscrowEventKind = serde_json::from_str(r#"{"Funded":{"amount":"1500"}}"#).unwrap();
+        assert_eq!(recorded.actor(), None);
+        assert_eq!(serde_json::to_string(&recorded).unwrap(), r#"{"Funded":{"amount":"1500"}}"#);
+    }
+}
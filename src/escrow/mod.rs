@@ -1,5 +1,19 @@
+pub mod action;
+pub mod audit;
 pub mod contract;
+pub mod dispute;
 pub mod errors;
+pub mod events;
+pub mod permissions;
+pub mod pin;
+pub mod service;
+pub mod totp;
+pub mod voucher;
 
+pub use audit::{AuditIssue, AuditReport};
 pub use contract::EscrowContract;
-pub use errors::EscrowError;
\ No newline at end of file
+pub use dispute::DisputePolicy;
+pub use errors::EscrowError;
+pub use events::{EscrowEvent, EscrowEventKind};
+pub use permissions::Operation;
+pub use service::EscrowService;
\ No newline at end of file
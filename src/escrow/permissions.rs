@@ -0,0 +1,175 @@
+use crate::escrow::action::EscrowAction;
+use crate::escrow::errors::EscrowError;
+use crate::types::{User, UserType};
+use std::fmt;
+
+/// Something a user can ask the system to do. Which roles may do what is
+/// decided by `allowed` alone; whether a user may do it to a particular
+/// escrow (being its buyer, one of its arbitrators, ...) is checked by the
+/// contract afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    CreateEscrow,
+    Fund,
+    Release,
+    ReissuePin,
+    AssignDriver,
+    Cancel,
+    Dispute,
+    Vote,
+    RespondToTerms,
+    ConfirmDelivery,
+    SubmitEvidence,
+    DecideDispute,
+    Appeal,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Operation::CreateEscrow => "create escrows",
+            Operation::Fund => "fund escrows",
+            Operation::Release => "release funds",
+            Operation::ReissuePin => "reissue release PINs",
+            Operation::AssignDriver => "assign drivers",
+            Operation::Cancel => "cancel escrows",
+            Operation::Dispute => "raise disputes",
+            Operation::Vote => "vote on disputes",
+            Operation::RespondToTerms => "accept or reject escrow terms",
+            Operation::ConfirmDelivery => "confirm deliveries",
+            Operation::SubmitEvidence => "submit dispute evidence",
+            Operation::DecideDispute => "decide escalated disputes",
+            Operation::Appeal => "appeal dispute decisions",
+        };
+        f.write_str(text)
+    }
+}
+
+impl From<EscrowAction> for Operation {
+    fn from(action: EscrowAction) -> Self {
+        match action {
+            EscrowAction::Release { .. } | EscrowAction::ReleaseMilestone { .. } => Operation::Release,
+            EscrowAction::ReissuePin => Operation::ReissuePin,
+            EscrowAction::AssignDriver { .. } => Operation::AssignDriver,
+            EscrowAction::Cancel => Operation::Cancel,
+            EscrowAction::Dispute => Operation::Dispute,
+            EscrowAction::Vote { .. } | EscrowAction::VoteSplit { .. } => Operation::Vote,
+            EscrowAction::AcceptTerms | EscrowAction::RejectTerms => Operation::RespondToTerms,
+            EscrowAction::ConfirmDelivery { .. } => Operation::ConfirmDelivery,
+            EscrowAction::SubmitEvidence { .. } => Operation::SubmitEvidence,
+            EscrowAction::DecideDispute { .. } => Operation::DecideDispute,
+            EscrowAction::Appeal => Operation::Appeal,
+            EscrowAction::Fund { .. } => Operation::Fund,
+        }
+    }
+}
+
+/// The permission table. Admins can cancel any escrow that has not been
+/// funded yet and decide disputes escalated to them; drivers only ever
+/// submit PINs at delivery.
+pub fn allowed(role: UserType, operation: Operation) -> bool {
+    use Operation::*;
+
+    match role {
+        UserType::Buyer => matches!(
+            operation,
+            CreateEscrow
+                | Fund
+                | Release
+                | ConfirmDelivery
+                | ReissuePin
+                | AssignDriver
+                | Cancel
+                | Dispute
+                | SubmitEvidence
+                | Appeal
+        ),
+        UserType::Seller => matches!(operation, RespondToTerms | Dispute | SubmitEvidence | Appeal),
+        UserType::Driver => matches!(operation, Release),
+        UserType::Arbitrator => matches!(operation, Vote),
+        UserType::Admin => matches!(operation, Cancel | DecideDispute),
+    }
+}
+
+pub fn authorize(user: &User, operation: Operation) -> Result<(), EscrowError> {
+    if allowed(user.user_type, operation) {
+        Ok(())
+    } else {
+        Err(EscrowError::Forbidden {
+            user_id: user.id,
+            role: user.user_type,
+            operation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::user;
+    use rust_decimal::Decimal;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(UserType::Buyer, Operation::CreateEscrow, true)]
+    #[case(UserType::Buyer, Operation::Release, true)]
+    #[case(UserType::Buyer, Operation::Fund, true)]
+    #[case(UserType::Buyer, Operation::AssignDriver, true)]
+    #[case(UserType::Buyer, Operation::Vote, false)]
+    #[case(UserType::Buyer, Operation::RespondToTerms, false)]
+    #[case(UserType::Seller, Operation::RespondToTerms, true)]
+    #[case(UserType::Seller, Operation::Dispute, true)]
+    #[case(UserType::Seller, Operation::SubmitEvidence, true)]
+    #[case(UserType::Seller, Operation::Appeal, true)]
+    #[case(UserType::Seller, Operation::Release, false)]
+    #[case(UserType::Seller, Operation::Fund, false)]
+    #[case(UserType::Seller, Operation::CreateEscrow, false)]
+    #[case(UserType::Driver, Operation::Release, true)]
+    #[case(UserType::Driver, Operation::Cancel, false)]
+    #[case(UserType::Driver, Operation::AssignDriver, false)]
+    #[case(UserType::Arbitrator, Operation::Vote, true)]
+    #[case(UserType::Arbitrator, Operation::SubmitEvidence, false)]
+    #[case(UserType::Arbitrator, Operation::Release, false)]
+    #[case(UserType::Admin, Operation::Cancel, true)]
+    #[case(UserType::Admin, Operation::DecideDispute, true)]
+    #[case(UserType::Arbitrator, Operation::Appeal, false)]
+    #[case(UserType::Arbitrator, Operation::DecideDispute, false)]
+    #[case(UserType::Admin, Operation::Release, false)]
+    fn roles_may_do_what_the_table_says(#[case] role: UserType, #[case] operation: Operation, #[case] expected: bool) {
+        assert_eq!(allowed(role, operation), expected);
+    }
+
+    #[test]
+    fn only_arbitrators_vote() {
+        let roles = [UserType::Buyer, UserType::Seller, UserType::Driver, UserType::Arbitrator, UserType::Admin];
+        for role in roles {
+            assert_eq!(allowed(role, Operation::Vote), role == UserType::Arbitrator, "{:?}", role);
+        }
+    }
+
+    #[test]
+    fn refusal_names_the_user_role_and_operation() {
+        let seller = user(UserType::Seller);
+
+        let Err(EscrowError::Forbidden { user_id, role, operation }) = authorize(&seller, Operation::Release) else {
+            panic!("sellers must not release escrows");
+        };
+        assert_eq!((user_id, role, operation), (seller.id, UserType::Seller, Operation::Release));
+        authorize(&seller, Operation::Dispute).unwrap();
+    }
+
+    #[test]
+    fn signed_actions_map_to_their_operation() {
+        assert_eq!(Operation::from(EscrowAction::Release { location: None }), Operation::Release);
+        assert_eq!(Operation::from(EscrowAction::Vote { release_to_seller: false }), Operation::Vote);
+        assert_eq!(Operation::from(EscrowAction::VoteSplit { seller_percent: Decimal::from(40) }), Operation::Vote);
+        assert_eq!(Operation::from(EscrowAction::ReissuePin), Operation::ReissuePin);
+        assert_eq!(Operation::from(EscrowAction::Fund { amount: Decimal::from(40) }), Operation::Fund);
+        assert_eq!(Operation::from(EscrowAction::AcceptTerms), Operation::RespondToTerms);
+        assert_eq!(Operation::from(EscrowAction::RejectTerms), Operation::RespondToTerms);
+        assert_eq!(
+            Operation::from(EscrowAction::ReleaseMilestone { milestone: 1, location: None }),
+            Operation::Release
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::escrow::errors::EscrowError;
+use crate::storage::Storage;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::Duration;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How many wrong PINs an escrow tolerates before release is locked, and for
+/// how long. Each further lockout doubles the wait, up to `max_lockout_secs`.
+/// Reissuing a PIN is limited to `max_reissues` per escrow, at least
+/// `reissue_cooldown_secs` apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PinPolicy {
+    pub max_attempts: u32,
+    pub lockout_secs: i64,
+    pub max_lockout_secs: i64,
+    pub max_reissues: u32,
+    pub reissue_cooldown_secs: i64,
+}
+
+impl Default for PinPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            lockout_secs: 15 * 60,
+            max_lockout_secs: 24 * 60 * 60,
+            max_reissues: 3,
+            reissue_cooldown_secs: 10 * 60,
+        }
+    }
+}
+
+impl PinPolicy {
+    /// Length of the lockout that follows `previous_lockouts` earlier ones.
+    pub fn lockout_duration(&self, previous_lockouts: u32) -> Duration {
+        let factor = 1i64 << previous_lockouts.min(30);
+        Duration::seconds(self.lockout_secs.saturating_mul(factor).min(self.max_lockout_secs))
+    }
+}
+
+/// A new random six-digit release PIN.
+pub fn generate() -> String {
+    let mut rng = rand::thread_rng();
+    format!("{:06}", rng.gen_range(100000..999999))
+}
+
+/// Argon2id hash of `pin` with a fresh random salt, as a PHC string that
+/// carries the salt and parameters alongside the hash.
+pub fn hash(pin: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .expect("default Argon2 parameters accept any PIN")
+        .to_string()
+}
+
+/// Checks `pin` against a stored hash. The final comparison is constant
+/// time, so response timing reveals nothing about how close a guess was.
+pub fn verify(stored_hash: &str, pin: &str) -> bool {
+    PasswordHash::new(stored_hash)
+        .map(|parsed| Argon2::default().verify_password(pin.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+pub fn is_hash(value: &str) -> bool {
+    PasswordHash::new(value).is_ok()
+}
+
+/// Replaces release PINs stored in clear by earlier versions with their
+/// hash. Returns how many escrows were upgraded; the escrow version is left
+/// alone because no state changes.
+pub fn upgrade_legacy_pins(storage: &dyn Storage) -> Result<usize, EscrowError> {
+    let mut upgraded = 0;
+    for mut escrow in storage.list_escrows()? {
+        let Some(pin) = escrow.release_pin_hash.as_deref() else {
+            continue;
+        };
+        if is_hash(pin) {
+            continue;
+        }
+        // PIN hashes never go into the event log, so the audit leaves them
+        // out of its comparison and this rewrite needs no event.
+        escrow.release_pin_hash = Some(hash(pin));
+        let version = escrow.version;
+        storage.update_escrow(escrow, version)?;
+        upgraded += 1;
+    }
+    Ok(upgraded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EscrowRepository, MemoryStorage};
+    use crate::test_support::escrow;
+
+    #[test]
+    fn generated_pins_are_six_digits() {
+        for _ in 0..100 {
+            let pin = generate();
+            assert_eq!(pin.len(), 6);
+            assert!(pin.bytes().all(|b| b.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn hash_verifies_only_the_right_pin() {
+        let stored = hash("482913");
+
+        assert!(is_hash(&stored));
+        assert!(!stored.contains("482913"));
+        assert!(verify(&stored, "482913"));
+        assert!(!verify(&stored, "482914"));
+    }
+
+    #[test]
+    fn same_pin_hashes_differently_each_time() {
+        assert_ne!(hash("482913"), hash("482913"));
+    }
+
+    #[test]
+    fn malformed_hash_never_verifies() {
+        assert!(!is_hash("482913"));
+        assert!(!verify("482913", "482913"));
+    }
+
+    #[test]
+    fn legacy_plaintext_pins_are_upgraded_once() {
+        let storage = MemoryStorage::new();
+        let mut legacy = escrow();
+        legacy.release_pin_hash = Some("482913".to_string());
+        let current = escrow();
+        storage.create_escrow(legacy.clone()).unwrap();
+        storage.create_escrow(current.clone()).unwrap();
+
+        assert_eq!(upgrade_legacy_pins(&storage).unwrap(), 1);
+        let upgraded = storage.get_escrow(legacy.id).unwrap().unwrap();
+        assert!(verify(upgraded.release_pin_hash.as_deref().unwrap(), "482913"));
+        assert_eq!(upgraded.version, legacy.version);
+        assert_eq!(storage.get_escrow(current.id).unwrap().unwrap().release_pin_hash, current.release_pin_hash);
+
+        assert_eq!(upgrade_legacy_pins(&storage).unwrap(), 0);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_cap() {
+        let policy = PinPolicy {
+            lockout_secs: 60,
+            max_lockout_secs: 300,
+            ..PinPolicy::default()
+        };
+
+        let lockouts: Vec<i64> = (0..5).map(|n| policy.lockout_duration(n).num_seconds()).collect();
+        assert_eq!(lockouts, vec![60, 120, 240, 300, 300]);
+        assert_eq!(policy.lockout_duration(u32::MAX).num_seconds(), 300);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::escrow::audit::{self, AuditReport};
+use crate::escrow::errors::EscrowError;
+use crate::escrow::events::{self, EscrowEvent};
+use crate::storage::{Storage, StorageError};
+use crate::types::Escrow;
+use uuid::Uuid;
+
+/// How many times a transition is re-run against fresh state when another
+/// writer got there first.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Runs `EscrowContract` transitions against a storage backend, keeping the
+/// stored escrow and its append-only event history in step.
+pub struct EscrowService<'a> {
+    storage: &'a dyn Storage,
+}
+
+impl<'a> EscrowService<'a> {
+    pub fn new(storage: &'a dyn Storage) -> Self {
+        Self { storage }
+    }
+
+    pub fn create(&self, escrow: &Escrow) -> Result<EscrowEvent, EscrowError> {
+        self.storage.create_escrow(escrow.clone())?;
+        self.seal_and_store(EscrowEvent::created(escrow), |event| self.storage.append_event(event))
+    }
+
+    pub fn get(&self, escrow_id: Uuid) -> Result<Escrow, EscrowError> {
+        self.storage
+            .get_escrow(escrow_id)?
+            .ok_or(EscrowError::NotFound(escrow_id))
+    }
+
+    /// Loads the escrow, runs `transition` on it and persists both the new
+    /// state and the event the transition produced. If the escrow changed
+    /// underneath us the transition is re-run against the fresh copy, so it
+    /// must not have side effects beyond the escrow it is given.
+    pub fn apply<F>(&self, escrow_id: Uuid, mut transition: F) -> Result<Escrow, EscrowError>
+    where
+        F: FnMut(&mut Escrow) -> Result<EscrowEvent, EscrowError>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.try_apply(escrow_id, &mut transition) {
+                Err(EscrowError::ConcurrentModification(_)) if attempt < MAX_ATTEMPTS => {
+                    log::warn!("Escrow {} changed concurrently, retrying", escrow_id);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// A single compare-and-swap attempt; returns `ConcurrentModification`
+    /// if the stored escrow is no longer at the version that was loaded.
+    /// The escrow and its event are committed together, so neither is
+    /// stored without the other. A `Rejected` transition is persisted like
+    /// any other before its reason is returned.
+    pub fn try_apply<F>(&self, escrow_id: Uuid, transition: F) -> Result<Escrow, EscrowError>
+    where
+        F: FnOnce(&mut Escrow) -> Result<EscrowEvent, EscrowError>,
+    {
+        let mut escrow = self.get(escrow_id)?;
+        let expected_version = escrow.version;
+        let (event, rejection) = match transition(&mut escrow) {
+            Ok(event) => (event, None),
+            Err(EscrowError::Rejected { event, reason }) => (*event, Some(*reason)),
+            Err(e) => return Err(e),
+        };
+
+        self.seal_and_store(event, |event| {
+            self.storage.commit_escrow(escrow.clone(), expected_version, event)
+        })?;
+
+        match rejection {
+            Some(reason) => Err(reason),
+            None => Ok(escrow),
+        }
+    }
+
+    /// Every event recorded for the escrow, oldest first.
+    pub fn history(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, EscrowError> {
+        let events = self.storage.list_events(escrow_id)?;
+        if events.is_empty() {
+            return Err(EscrowError::NotFound(escrow_id));
+        }
+        Ok(events)
+    }
+
+    /// Rebuilds the escrow purely from its recorded history.
+    pub fn replay(&self, escrow_id: Uuid) -> Result<Escrow, EscrowError> {
+        events::replay(&self.history(escrow_id)?)
+    }
+
+    /// Checks the hash chain of the entire log and that every stored escrow
+    /// matches its replayed history.
+    pub fn verify(&self) -> Result<AuditReport, EscrowError> {
+        let log = self.storage.list_all_events()?;
+        let escrows = self.storage.list_escrows()?;
+        Ok(audit::verify(&log, &escrows))
+    }
+
+    /// Seals `event` onto the head of the log and hands it to `store`.
+    fn seal_and_store<F>(&self, mut event: EscrowEvent, store: F) -> Result<EscrowEvent, EscrowError>
+    where
+        F: Fn(EscrowEvent) -> Result<(), StorageError>,
+    {
+        // The escrow itself is protected by its version check, so losing the
+        // race for the next sequence number only means re-sealing against
+        // the new head of the log.
+        let mut attempt = 1;
+        loop {
+            let previous = self.storage.last_event()?;
+            audit::seal(&mut event, previous.as_ref());
+
+            match store(event.clone()) {
+                Ok(()) => return Ok(event),
+                Err(StorageError::SequenceConflict { .. }) if attempt < MAX_ATTEMPTS => {
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escrow::contract::EscrowContract;
+    use crate::escrow::events::EscrowEventKind;
+    use crate::storage::{
+        EscrowPage, EscrowQuery, EscrowRepository, EventRepository, MemoryStorage, TrustRepository,
+        UserRepository,
+    };
+    use crate::trust::UserTrustProfile;
+    use crate::test_support::escrow;
+    use crate::types::{EscrowState, User};
+    use std::cell::Cell;
+
+    /// Memory storage where another writer slips an event into the log just
+    /// before each of the next `races` appends, as a second process would.
+    struct RacingStorage {
+        inner: MemoryStorage,
+        races: Cell<usize>,
+    }
+
+    impl RacingStorage {
+        fn race(&self) -> Result<(), StorageError> {
+            if self.races.get() > 0 {
+                self.races.set(self.races.get() - 1);
+                let mut rival = EscrowEvent::new(Uuid::new_v4(), EscrowEventKind::AutoRefunded);
+                audit::seal(&mut rival, self.inner.last_event()?.as_ref());
+                self.inner.append_event(rival)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl EscrowRepository for RacingStorage {
+        fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError> {
+            self.inner.create_escrow(escrow)
+        }
+        fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError> {
+            self.inner.get_escrow(id)
+        }
+        fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError> {
+            self.inner.update_escrow(escrow, expected_version)
+        }
+        fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError> {
+            self.race()?;
+            self.inner.commit_escrow(escrow, expected_version, event)
+        }
+        fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError> {
+            self.inner.list_escrows()
+        }
+        fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError> {
+            self.inner.query_escrows(query)
+        }
+    }
+
+    impl UserRepository for RacingStorage {
+        fn create_user(&self, user: User) -> Result<(), StorageError> {
+            self.inner.create_user(user)
+        }
+        fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError> {
+            self.inner.get_user(id)
+        }
+        fn update_user(&self, user: User) -> Result<(), StorageError> {
+            self.inner.update_user(user)
+        }
+        fn list_users(&self) -> Result<Vec<User>, StorageError> {
+            self.inner.list_users()
+        }
+    }
+
+    impl TrustRepository for RacingStorage {
+        fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError> {
+            self.inner.save_trust_profiles(profiles)
+        }
+        fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError> {
+            self.inner.list_trust_profiles()
+        }
+    }
+
+    impl EventRepository for RacingStorage {
+        fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError> {
+            self.race()?;
+            self.inner.append_event(event)
+        }
+        fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError> {
+            self.inner.last_event()
+        }
+        fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError> {
+            self.inner.list_events(escrow_id)
+        }
+        fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+            self.inner.list_all_events()
+        }
+    }
+
+    fn accept(escrow: &mut Escrow) -> Result<EscrowEvent, EscrowError> {
+        let event = EscrowEvent::new(escrow.id, EscrowEventKind::TermsAccepted { accepted_by: escrow.seller_id });
+        event.apply(escrow)?;
+        Ok(event)
+    }
+
+    fn fund(escrow: &mut Escrow) -> Result<EscrowEvent, EscrowError> {
+        let kind = EscrowEventKind::Funded { amount: escrow.amount, funded_by: escrow.buyer_id };
+        let event = EscrowEvent::new(escrow.id, kind);
+        event.apply(escrow)?;
+        Ok(event)
+    }
+
+    #[test]
+    fn apply_reruns_the_transition_after_a_concurrent_update() {
+        let storage = MemoryStorage::new();
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        service.create(&escrow).unwrap();
+
+        let mut calls = 0;
+        let updated = service
+            .apply(escrow.id, |current| {
+                calls += 1;
+                if calls == 1 {
+                    // Another writer saves first, so this attempt is stale.
+                    let mut theirs = current.clone();
+                    theirs.version += 1;
+                    storage.update_escrow(theirs, current.version).unwrap();
+                }
+                accept(current)
+            })
+            .unwrap();
+
+        assert_eq!(calls, 2);
+        assert_eq!(updated.state, EscrowState::Created);
+        assert_eq!(updated.version, 2);
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 2);
+    }
+
+    #[test]
+    fn apply_gives_up_after_repeated_conflicts() {
+        let storage = MemoryStorage::new();
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        service.create(&escrow).unwrap();
+
+        let mut calls = 0;
+        let result = service.apply(escrow.id, |current| {
+            calls += 1;
+            let mut theirs = current.clone();
+            theirs.version += 1;
+            storage.update_escrow(theirs, current.version).unwrap();
+            accept(current)
+        });
+
+        assert!(matches!(result, Err(EscrowError::ConcurrentModification(id)) if id == escrow.id));
+        assert_eq!(calls, MAX_ATTEMPTS);
+        assert_eq!(storage.list_events(escrow.id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn event_is_resealed_when_another_writer_takes_its_sequence() {
+        let storage = RacingStorage { inner: MemoryStorage::new(), races: Cell::new(0) };
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        service.create(&escrow).unwrap();
+
+        storage.races.set(1);
+        service.apply(escrow.id, accept).unwrap();
+
+        let log = storage.list_all_events().unwrap();
+        assert_eq!(log.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(matches!(log[2].kind, EscrowEventKind::TermsAccepted { .. }));
+        assert!(audit::verify(&log, &[]).is_intact());
+    }
+
+    #[test]
+    fn escrow_is_not_saved_when_its_event_cannot_be_appended() {
+        let storage = RacingStorage { inner: MemoryStorage::new(), races: Cell::new(0) };
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        service.create(&escrow).unwrap();
+
+        storage.races.set(MAX_ATTEMPTS);
+        assert!(matches!(service.apply(escrow.id, accept), Err(EscrowError::StorageError(_))));
+
+        let stored = service.get(escrow.id).unwrap();
+        assert_eq!(stored.version, escrow.version);
+        assert_eq!(stored.state, escrow.state);
+        assert_eq!(service.history(escrow.id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn replay_matches_the_stored_escrow() {
+        let storage = MemoryStorage::new();
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        service.create(&escrow).unwrap();
+        service.apply(escrow.id, accept).unwrap();
+        let stored = service.apply(escrow.id, fund).unwrap();
+
+        let replayed = service.replay(escrow.id).unwrap();
+        assert_eq!(stored.state, EscrowState::Funded);
+        assert_eq!(replayed.state, stored.state);
+        assert_eq!(replayed.funded_at, stored.funded_at);
+        assert!(service.verify().unwrap().is_intact());
+        assert!(matches!(service.history(Uuid::new_v4()), Err(EscrowError::NotFound(_))));
+    }
+
+    #[test]
+    fn sequence_numbers_run_across_every_escrow() {
+        let storage = MemoryStorage::new();
+        let service = EscrowService::new(&storage);
+        let (first, second) = (escrow(), escrow());
+        service.create(&first).unwrap();
+        service.create(&second).unwrap();
+        service.apply(first.id, accept).unwrap();
+
+        let sequences: Vec<u64> = storage.list_all_events().unwrap().iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, [1, 2, 3]);
+        assert_eq!(service.history(first.id).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn failed_transition_records_nothing() {
+        let storage = MemoryStorage::new();
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        service.create(&escrow).unwrap();
+        service.apply(escrow.id, accept).unwrap();
+
+        let result = service.apply(escrow.id, EscrowContract::refund_expired);
+        assert!(matches!(result, Err(EscrowError::ValidationError(_))));
+        assert_eq!(service.get(escrow.id).unwrap().state, EscrowState::Created);
+        assert_eq!(service.history(escrow.id).unwrap().len(), 2);
+    }
+}
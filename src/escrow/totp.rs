@@ -0,0 +1,145 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+/// RFC 6238 parameters understood by common authenticator apps.
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from one step either side of now are accepted, to allow for clock
+/// drift between the buyer's phone and the server.
+const ALLOWED_SKEW: i64 = 1;
+const SECRET_BYTES: usize = 20;
+
+const ALPHABET: base32::Alphabet = base32::Alphabet::Rfc4648 { padding: false };
+
+/// A new random shared secret, base32-encoded for authenticator apps.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base32::encode(ALPHABET, &bytes)
+}
+
+/// `otpauth://` link the buyer's authenticator app can import, usually by
+/// scanning it as a QR code.
+pub fn provisioning_uri(secret: &str, escrow_id: Uuid) -> String {
+    format!(
+        "otpauth://totp/SpazaEscrow:{}?secret={}&issuer=SpazaEscrow&digits={}&period={}",
+        &escrow_id.to_string()[..8],
+        secret,
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+/// The code for the time step containing `at`, or `None` if the secret is
+/// not valid base32.
+pub fn code_at(secret: &str, at: DateTime<Utc>) -> Option<String> {
+    let key = base32::decode(ALPHABET, secret)?;
+    Some(code_for_step(&key, at.timestamp().div_euclid(STEP_SECS)))
+}
+
+/// Checks `code` against the steps around `now` in constant time and
+/// returns the step it belongs to. Steps up to and including `last_used`
+/// are refused, so each code can be accepted only once.
+pub fn verify(secret: &str, code: &str, now: DateTime<Utc>, last_used: Option<i64>) -> Option<i64> {
+    let key = base32::decode(ALPHABET, secret)?;
+    let step = now.timestamp().div_euclid(STEP_SECS);
+
+    let mut matched = None;
+    for candidate in step - ALLOWED_SKEW..=step + ALLOWED_SKEW {
+        let expected = code_for_step(&key, candidate);
+        let equal = bool::from(expected.as_bytes().ct_eq(code.trim().as_bytes()));
+        if equal && last_used.is_none_or(|last| candidate > last) {
+            matched = Some(candidate);
+        }
+    }
+    matched
+}
+
+fn code_for_step(key: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation, RFC 4226 section 5.3.
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rstest::rstest;
+
+    /// The SHA-1 seed from RFC 6238 appendix B, "12345678901234567890".
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, 0).unwrap()
+    }
+
+    // The RFC lists eight-digit codes; six-digit ones are their last six.
+    #[rstest]
+    #[case(59, "287082")]
+    #[case(1111111109, "081804")]
+    #[case(1111111111, "050471")]
+    #[case(1234567890, "005924")]
+    #[case(2000000000, "279037")]
+    #[case(20000000000, "353130")]
+    fn matches_rfc_6238_vectors(#[case] secs: i64, #[case] expected: &str) {
+        assert_eq!(code_at(RFC_SECRET, at(secs)).unwrap(), expected);
+        assert_eq!(verify(RFC_SECRET, expected, at(secs), None), Some(secs.div_euclid(STEP_SECS)));
+    }
+
+    #[test]
+    fn neighbouring_steps_are_accepted_for_clock_drift() {
+        let now = at(1234567890);
+        let step = 1234567890 / STEP_SECS;
+
+        let early = code_at(RFC_SECRET, at(1234567890 - STEP_SECS)).unwrap();
+        let late = code_at(RFC_SECRET, at(1234567890 + STEP_SECS)).unwrap();
+        let stale = code_at(RFC_SECRET, at(1234567890 - 2 * STEP_SECS)).unwrap();
+        assert_eq!(verify(RFC_SECRET, &early, now, None), Some(step - 1));
+        assert_eq!(verify(RFC_SECRET, &late, now, None), Some(step + 1));
+        assert_eq!(verify(RFC_SECRET, &stale, now, None), None);
+    }
+
+    #[test]
+    fn a_code_is_accepted_only_once() {
+        let now = at(1234567890);
+        let step = verify(RFC_SECRET, "005924", now, None).unwrap();
+
+        assert_eq!(verify(RFC_SECRET, "005924", now, Some(step)), None);
+        assert_eq!(verify(RFC_SECRET, "005924", now + chrono::Duration::seconds(STEP_SECS), Some(step)), None);
+
+        let next = code_at(RFC_SECRET, now + chrono::Duration::seconds(STEP_SECS)).unwrap();
+        assert_eq!(verify(RFC_SECRET, &next, now, Some(step)), Some(step + 1));
+    }
+
+    #[test]
+    fn wrong_code_or_secret_is_refused() {
+        let now = at(1234567890);
+
+        assert_eq!(verify(RFC_SECRET, "123456", now, None), None);
+        assert_eq!(verify("not base32!", "005924", now, None), None);
+        assert!(code_at("not base32!", now).is_none());
+    }
+
+    #[test]
+    fn generated_secrets_round_trip_through_base32() {
+        let secret = generate_secret();
+
+        assert_eq!(base32::decode(ALPHABET, &secret).unwrap().len(), SECRET_BYTES);
+        assert!(provisioning_uri(&secret, Uuid::new_v4()).contains(&format!("secret={}", secret)));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::escrow::action::MAX_CLOCK_SKEW_SECS;
+use crate::escrow::errors::EscrowError;
+use crate::types::Escrow;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+/// Prefix of the encoded string; bump the digit if the layout changes.
+const PREFIX: &str = "SPZV1:";
+/// Signed alongside the payload so a voucher signature can never be passed
+/// off as a signature over some other message.
+const SIGNING_CONTEXT: &[u8] = b"spaza-escrow/release-voucher/v1";
+const SIGNATURE_LEN: usize = 64;
+
+/// The buyer's signed instruction to release one escrow. It carries
+/// everything a driver needs to check it offline against the buyer's public
+/// key, and encodes to a short URL-safe string that fits in a QR code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseVoucher {
+    pub id: Uuid,
+    pub escrow_id: Uuid,
+    pub buyer_id: Uuid,
+    pub amount: Decimal,
+    pub currency: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub signature: Signature,
+}
+
+impl ReleaseVoucher {
+    /// Signs a voucher for `escrow` that stays valid for `valid_for`, but
+    /// never beyond the escrow's own expiry. Refused unless the escrow's
+    /// currency is a three-letter ISO code, the only kind the encoding holds.
+    pub fn issue(escrow: &Escrow, signing_key: &SigningKey, valid_for: Duration) -> Result<Self, EscrowError> {
+        check_currency(&escrow.currency)?;
+
+        // Whole seconds, since that is all the encoding keeps.
+        let whole_seconds = |t: DateTime<Utc>| Utc.timestamp_opt(t.timestamp(), 0).unwrap();
+        let issued_at = whole_seconds(Utc::now());
+        let expires_at = whole_seconds((issued_at + valid_for).min(escrow.expires_at));
+
+        let mut voucher = Self {
+            id: Uuid::new_v4(),
+            escrow_id: escrow.id,
+            buyer_id: escrow.buyer_id,
+            amount: escrow.amount,
+            currency: escrow.currency.clone(),
+            issued_at,
+            expires_at,
+            signature: Signature::from_bytes(&[0; SIGNATURE_LEN]),
+        };
+        voucher.signature = signing_key.sign(&voucher.signed_message());
+        Ok(voucher)
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = self.payload();
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, EscrowError> {
+        let invalid = |reason: &str| EscrowError::InvalidVoucher(reason.to_string());
+
+        let body = encoded
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| invalid("unrecognised voucher format"))?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(body)
+            .map_err(|_| invalid("voucher is not valid base64"))?;
+        if bytes.len() < SIGNATURE_LEN {
+            return Err(invalid("voucher is truncated"));
+        }
+        let (payload, signature) = bytes.split_at(bytes.len() - SIGNATURE_LEN);
+
+        let mut reader = Reader(payload);
+        let id = reader.uuid()?;
+        let escrow_id = reader.uuid()?;
+        let buyer_id = reader.uuid()?;
+        let amount = Decimal::deserialize(reader.array()?);
+        let currency_len = reader.take(1)?[0] as usize;
+        let currency = String::from_utf8(reader.take(currency_len)?.to_vec())
+            .map_err(|_| invalid("currency is not UTF-8"))?;
+        check_currency(&currency)?;
+        let issued_at = reader.timestamp()?;
+        let expires_at = reader.timestamp()?;
+        if !reader.0.is_empty() {
+            return Err(invalid("unexpected trailing data"));
+        }
+
+        Ok(Self {
+            id,
+            escrow_id,
+            buyer_id,
+            amount,
+            currency,
+            issued_at,
+            expires_at,
+            signature: Signature::from_slice(signature).map_err(|_| invalid("bad signature"))?,
+        })
+    }
+
+    /// Everything that can be checked without the escrow record: the
+    /// signature against the buyer's key and the validity window. A voucher
+    /// issued further ahead of `now` than clock skew explains is refused.
+    pub fn verify(&self, buyer_key: &VerifyingKey, now: DateTime<Utc>) -> Result<(), EscrowError> {
+        buyer_key
+            .verify(&self.signed_message(), &self.signature)
+            .map_err(|_| EscrowError::InvalidVoucher("signature does not match the buyer's key".to_string()))?;
+
+        if self.issued_at > now + Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+            return Err(EscrowError::InvalidVoucher(format!("issued in the future, at {}", self.issued_at)));
+        }
+        if now > self.expires_at {
+            return Err(EscrowError::InvalidVoucher(format!("expired at {}", self.expires_at)));
+        }
+        Ok(())
+    }
+
+    /// Checks that the voucher was issued for exactly this escrow's terms.
+    pub fn matches(&self, escrow: &Escrow) -> Result<(), EscrowError> {
+        let mismatch = |what: &str| EscrowError::InvalidVoucher(format!("{} does not match the escrow", what));
+
+        if self.escrow_id != escrow.id {
+            return Err(mismatch("escrow"));
+        }
+        if self.buyer_id != escrow.buyer_id {
+            return Err(mismatch("buyer"));
+        }
+        if self.amount != escrow.amount || self.currency != escrow.currency {
+            return Err(mismatch("amount"));
+        }
+        Ok(())
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(96);
+        bytes.extend_from_slice(self.id.as_bytes());
+        bytes.extend_from_slice(self.escrow_id.as_bytes());
+        bytes.extend_from_slice(self.buyer_id.as_bytes());
+        bytes.extend_from_slice(&self.amount.serialize());
+        bytes.push(self.currency.len() as u8);
+        bytes.extend_from_slice(self.currency.as_bytes());
+        bytes.extend_from_slice(&self.issued_at.timestamp().to_be_bytes());
+        bytes.extend_from_slice(&self.expires_at.timestamp().to_be_bytes());
+        bytes
+    }
+
+    fn signed_message(&self) -> Vec<u8> {
+        let mut message = SIGNING_CONTEXT.to_vec();
+        message.extend_from_slice(&self.payload());
+        message
+    }
+}
+
+/// The encoding gives the currency a one-byte length, so anything but a
+/// short ISO code would be cut short rather than signed as given.
+fn check_currency(currency: &str) -> Result<(), EscrowError> {
+    if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(EscrowError::InvalidVoucher(format!(
+            "currency {:?} is not a three-letter ISO code",
+            currency
+        )));
+    }
+    Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], EscrowError> {
+        if self.0.len() < len {
+            return Err(EscrowError::InvalidVoucher("voucher is truncated".to_string()));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], EscrowError> {
+        Ok(self.take(N)?.try_into().expect("length checked by take"))
+    }
+
+    fn uuid(&mut self) -> Result<Uuid, EscrowError> {
+        Ok(Uuid::from_bytes(self.array()?))
+    }
+
+    fn timestamp(&mut self) -> Result<DateTime<Utc>, EscrowError> {
+        let secs = i64::from_be_bytes(self.array()?);
+        Utc.timestamp_opt(secs, 0)
+            .single()
+            .ok_or_else(|| EscrowError::InvalidVoucher("bad timestamp".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity;
+    use crate::test_support::escrow;
+
+    #[test]
+    fn encode_then_decode_round_trips() {
+        let key = identity::generate_signing_key();
+        let voucher = ReleaseVoucher::issue(&escrow(), &key, Duration::hours(1)).unwrap();
+
+        let encoded = voucher.encode();
+        assert!(encoded.starts_with(PREFIX));
+        let decoded = ReleaseVoucher::decode(&encoded).unwrap();
+        assert_eq!(decoded, voucher);
+        decoded.verify(&key.verifying_key(), Utc::now()).unwrap();
+    }
+
+    #[test]
+    fn tampered_voucher_fails_verification() {
+        let key = identity::generate_signing_key();
+        let voucher = ReleaseVoucher::issue(&escrow(), &key, Duration::hours(1)).unwrap();
+        let mut bytes = URL_SAFE_NO_PAD.decode(&voucher.encode()[PREFIX.len()..]).unwrap();
+        // The amount's low word follows the three ids and its flags.
+        bytes[52] ^= 1;
+        let tampered = format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+
+        let decoded = ReleaseVoucher::decode(&tampered).unwrap();
+        assert_ne!(decoded.amount, voucher.amount);
+        assert!(matches!(decoded.verify(&key.verifying_key(), Utc::now()), Err(EscrowError::InvalidVoucher(_))));
+    }
+
+    #[test]
+    fn voucher_signed_by_someone_else_is_refused() {
+        let voucher = ReleaseVoucher::issue(&escrow(), &identity::generate_signing_key(), Duration::hours(1)).unwrap();
+        let other = identity::generate_signing_key();
+
+        assert!(voucher.verify(&other.verifying_key(), Utc::now()).is_err());
+    }
+
+    #[test]
+    fn expired_voucher_is_refused() {
+        let key = identity::generate_signing_key();
+        let voucher = ReleaseVoucher::issue(&escrow(), &key, Duration::hours(1)).unwrap();
+
+        assert!(voucher.verify(&key.verifying_key(), voucher.expires_at).is_ok());
+        assert!(voucher.verify(&key.verifying_key(), voucher.expires_at + Duration::seconds(1)).is_err());
+    }
+
+    #[test]
+    fn validity_never_outlasts_the_escrow() {
+        let escrow = escrow();
+        let voucher = ReleaseVoucher::issue(&escrow, &identity::generate_signing_key(), Duration::days(365)).unwrap();
+
+        assert!(voucher.expires_at <= escrow.expires_at);
+    }
+
+    #[test]
+    fn voucher_only_matches_the_escrow_it_was_issued_for() {
+        let escrow = escrow();
+        let voucher = ReleaseVoucher::issue(&escrow, &identity::generate_signing_key(), Duration::hours(1)).unwrap();
+        let mut raised = escrow.clone();
+        raised.amount += Decimal::ONE;
+
+        assert!(voucher.matches(&escrow).is_ok());
+        assert!(voucher.matches(&raised).is_err());
+    }
+
+    #[test]
+    fn malformed_strings_are_refused() {
+        let voucher = ReleaseVoucher::issue(&escrow(), &identity::generate_signing_key(), Duration::hours(1)).unwrap();
+        let encoded = voucher.encode();
+
+        for bad in ["", "SPZV2:abc", "SPZV1:!!!", &encoded[..encoded.len() - 10]] {
+            assert!(matches!(ReleaseVoucher::decode(bad), Err(EscrowError::InvalidVoucher(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn voucher_from_the_future_is_refused() {
+        let key = identity::generate_signing_key();
+        let voucher = ReleaseVoucher::issue(&escrow(), &key, Duration::hours(1)).unwrap();
+        let skew = Duration::seconds(MAX_CLOCK_SKEW_SECS);
+
+        assert!(voucher.verify(&key.verifying_key(), voucher.issued_at - skew).is_ok());
+        let early = voucher.issued_at - skew - Duration::seconds(1);
+        assert!(matches!(voucher.verify(&key.verifying_key(), early), Err(EscrowError::InvalidVoucher(_))));
+    }
+
+    #[test]
+    fn currency_must_be_a_three_letter_code() {
+        let key = identity::generate_signing_key();
+        for currency in ["", "ZA", "zar", "RANDS", &"X".repeat(300)] {
+            let mut escrow = escrow();
+            escrow.currency = currency.to_string();
+            assert!(matches!(
+                ReleaseVoucher::issue(&escrow, &key, Duration::hours(1)),
+                Err(EscrowError::InvalidVoucher(_))
+            ));
+        }
+    }
+}
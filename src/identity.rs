@@ -0,0 +1,80 @@
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Ed25519 keys identify users. The public half is stored hex-encoded on
+/// the user record; the secret half stays on the user's own device, which
+/// for the CLI is a key file.
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+pub fn encode_public_key(key: &VerifyingKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+pub fn decode_public_key(encoded: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = hex::decode(encoded.trim())
+        .map_err(|e| format!("Public key is not hex: {}", e))?
+        .try_into()
+        .map_err(|_| "Public key must be 32 bytes".to_string())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid public key: {}", e))
+}
+
+/// Writes the secret key hex-encoded to a new file, readable only by its
+/// owner on unix from the moment it exists. Fails with `AlreadyExists`
+/// rather than overwrite another key.
+pub fn write_signing_key(path: &Path, key: &SigningKey) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(hex::encode(key.to_bytes()).as_bytes())
+}
+
+pub fn read_signing_key(path: &Path) -> io::Result<SigningKey> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+    let bytes: [u8; 32] = hex::decode(fs::read_to_string(path)?.trim())
+        .map_err(|_| invalid("Key file is not hex"))?
+        .try_into()
+        .map_err(|_| invalid("Key file must hold a 32-byte key"))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn key_file_round_trips_and_is_never_overwritten() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path()).unwrap();
+        let path = dir.path().join("buyer.key");
+        let key = generate_signing_key();
+
+        write_signing_key(&path, &key).unwrap();
+        assert_eq!(read_signing_key(&path).unwrap().to_bytes(), key.to_bytes());
+
+        let err = write_signing_key(&path, &generate_signing_key()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read_signing_key(&path).unwrap().to_bytes(), key.to_bytes());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_is_private_to_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path()).unwrap();
+        let path = dir.path().join("buyer.key");
+
+        write_signing_key(&path, &generate_signing_key()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -4,6 +4,11 @@ pub mod storage;
 pub mod cli;
 pub mod api;
 pub mod trust;
+pub mod config;
+pub mod identity;
+
+#[cfg(test)]
+mod test_support;
 
 pub use types::*;
 pub use escrow::*;
\ No newline at end of file
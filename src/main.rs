@@ -1,21 +1,49 @@
+use chrono::{Duration, Utc};
 use clap::Parser;
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
 use spaza_safety_escrow::api::simulator::{MobileCarrier, SmsService};
 use spaza_safety_escrow::cli::commands::{
-    CancelArgs, Commands, CreateArgs, DemoArgs, DisputeArgs, FundArgs, GetArgs, ReleaseArgs,
-    SmsArgs, TrustArgs, VoteArgs,
+    AcceptArgs, AppealArgs, AssignDriverArgs, BackupArgs, CancelArgs, Commands, ConfirmArgs, CreateArgs, DemoArgs,
+    DecideArgs, DisputeArgs, EvidenceArgs, FundArgs, GetArgs, HistoryArgs, KeygenArgs, ListArgs, ListSort, RedeemArgs, RegisterArgs,
+    RejectArgs, ReleaseArgs, ResendPinArgs, RestoreArgs, SmsArgs, StorageBackend, SubmitEvidenceArgs, TrustArgs,
+    VerifyVoucherArgs, VoteArgs, VoucherArgs,
+};
+use spaza_safety_escrow::config::AppConfig;
+use spaza_safety_escrow::escrow::action::{self, EscrowAction, SignedAction};
+use spaza_safety_escrow::escrow::dispute::{self, DisputePolicy};
+use spaza_safety_escrow::escrow::permissions::{self, Operation};
+use spaza_safety_escrow::escrow::pin::{self, PinPolicy};
+use spaza_safety_escrow::escrow::totp;
+use spaza_safety_escrow::escrow::voucher::ReleaseVoucher;
+use spaza_safety_escrow::identity;
+use spaza_safety_escrow::escrow::{EscrowContract, EscrowError, EscrowEventKind, EscrowService};
+use spaza_safety_escrow::storage::backup;
+use spaza_safety_escrow::storage::{
+    reencrypt_all, EncryptedStorage, EscrowQuery, EscrowSortField, FileStorage, MemoryStorage, SortOrder,
+    SqliteStorage, Storage,
 };
-use spaza_safety_escrow::escrow::EscrowContract;
-use spaza_safety_escrow::storage::memory::MemoryStorage;
 use spaza_safety_escrow::trust::TrustManager;
-use spaza_safety_escrow::types::Escrow;
+use spaza_safety_escrow::types::{Escrow, EscrowState, LineItem, User, UserType};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 #[derive(Parser)]
 #[command(name = "spaza-escrow")]
 #[command(about = "Spaza Safety Escrow System", long_about = None)]
 struct Cli {
+    /// Directory where escrows and users are persisted between runs
+    #[arg(long, global = true, env = "SPAZA_DATA_DIR", default_value = "spaza-data")]
+    data_dir: PathBuf,
+
+    /// Storage backend used inside the data directory
+    #[arg(long, global = true, env = "SPAZA_BACKEND", value_enum, default_value = "file")]
+    backend: StorageBackend,
+
+    /// Local config holding the keys that encrypt personal data at rest
+    #[arg(long, global = true, env = "SPAZA_CONFIG", default_value = "spaza-config.json")]
+    config: PathBuf,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -23,79 +51,211 @@ struct Cli {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let storage = MemoryStorage::new();
-    let sms_service = SmsService::new(MobileCarrier::Safaricom);
-    let mut trust_manager = TrustManager::new();
-
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Create(args) => handle_create(&storage, &sms_service, &mut trust_manager, args),
-        Commands::Fund(args) => handle_fund(&storage, args),
-        Commands::Release(args) => handle_release(&storage, args),
-        Commands::Cancel(args) => handle_cancel(&storage, args),
-        Commands::Dispute(args) => handle_dispute(&storage, args),
-        Commands::Vote(args) => handle_vote(&storage, args),
-        Commands::List => handle_list(&storage),
-        Commands::Get(args) => handle_get(&storage, args),
+    let mut config = AppConfig::load_or_init(&cli.config)?;
+    let encrypted = EncryptedStorage::new(
+        open_storage(cli.backend, &cli.data_dir)?,
+        config.encryption.keyring()?,
+    );
+    let storage: &dyn Storage = &encrypted;
+
+    upgrade_legacy_pins_once(storage, cli.backend, &cli.data_dir)?;
+    let sms_service = SmsService::new(MobileCarrier::Safaricom);
+    let mut trust_manager = TrustManager::from_profiles(storage.list_trust_profiles()?);
+
+    let result = match cli.command {
+        Commands::Create(args) => handle_create(storage, &sms_service, &mut trust_manager, args),
+        Commands::Accept(args) => handle_accept(storage, &sms_service, args),
+        Commands::Reject(args) => handle_reject(storage, &sms_service, args),
+        Commands::Fund(args) => handle_fund(storage, args),
+        Commands::Release(args) => handle_release(storage, &sms_service, &config.pin, args),
+        Commands::Confirm(args) => handle_confirm(storage, args),
+        Commands::ResendPin(args) => handle_resend_pin(storage, &sms_service, &config.pin, args),
+        Commands::Register(args) => handle_register(storage, args),
+        Commands::Keygen(args) => handle_keygen(storage, args),
+        Commands::Voucher(args) => handle_voucher(storage, args),
+        Commands::VerifyVoucher(args) => handle_verify_voucher(args),
+        Commands::Redeem(args) => handle_redeem(storage, args),
+        Commands::AssignDriver(args) => handle_assign_driver(storage, args),
+        Commands::Cancel(args) => handle_cancel(storage, args),
+        Commands::Dispute(args) => handle_dispute(storage, &config.dispute, args),
+        Commands::SubmitEvidence(args) => handle_submit_evidence(storage, args),
+        Commands::Evidence(args) => handle_evidence(storage, args),
+        Commands::Vote(args) => handle_vote(storage, args),
+        Commands::Arbitrators => handle_arbitrators(storage),
+        Commands::Decide(args) => handle_decide(storage, args),
+        Commands::Appeal(args) => handle_appeal(storage, &config.dispute, args),
+        Commands::DisputeDeadlines => handle_dispute_deadlines(storage, &config.dispute),
+        Commands::RefundExpired => handle_refund_expired(storage),
+        Commands::List(args) => handle_list(storage, args),
+        Commands::Get(args) => handle_get(storage, args),
+        Commands::History(args) => handle_history(storage, args),
+        Commands::Verify => handle_verify(storage),
+        Commands::Backup(args) => handle_backup(encrypted.inner(), args),
+        Commands::Restore(args) => handle_restore(encrypted.inner(), cli.backend, &cli.data_dir, args),
+        Commands::RotateKey => handle_rotate_key(encrypted.inner(), &cli.config, &mut config),
         Commands::Trust(args) => handle_trust(&trust_manager, args),
-        Commands::Demo(args) => handle_demo(&storage, &sms_service, &mut trust_manager, args),
+        Commands::Demo(args) => handle_demo(storage, &sms_service, &mut trust_manager, args),
         Commands::Sms(args) => handle_sms(&sms_service, args),
-        Commands::Dashboard => handle_dashboard(&storage, &trust_manager),
+        Commands::Dashboard => handle_dashboard(storage, &trust_manager),
+    };
+
+    if result.is_ok() {
+        let profiles: Vec<_> = trust_manager.profiles().cloned().collect();
+        storage.save_trust_profiles(&profiles)?;
+    }
+    result
+}
+
+fn open_storage(
+    backend: StorageBackend,
+    data_dir: &Path,
+) -> Result<Box<dyn Storage>, Box<dyn std::error::Error>> {
+    let storage: Box<dyn Storage> = match backend {
+        StorageBackend::File => Box::new(FileStorage::open(data_dir)?),
+        StorageBackend::Sqlite => {
+            std::fs::create_dir_all(data_dir)?;
+            Box::new(SqliteStorage::open(data_dir.join("spaza.db"))?)
+        }
+        StorageBackend::Memory => Box::new(MemoryStorage::new()),
+    };
+    Ok(storage)
+}
+
+/// Left in the data directory once release PINs stored in clear by earlier
+/// versions have been hashed, so later runs skip the scan.
+const PINS_HASHED_MARKER: &str = ".pins-hashed";
+
+fn upgrade_legacy_pins_once(
+    storage: &dyn Storage,
+    backend: StorageBackend,
+    data_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let marker = data_dir.join(PINS_HASHED_MARKER);
+    if matches!(backend, StorageBackend::Memory) || marker.exists() {
+        return Ok(());
+    }
+
+    let upgraded = pin::upgrade_legacy_pins(storage)?;
+    if upgraded > 0 {
+        log::info!("Replaced {} plaintext release PIN(s) with hashes", upgraded);
     }
+    std::fs::write(&marker, Utc::now().to_rfc3339())?;
+    Ok(())
 }
 
 fn handle_create(
-    storage: &MemoryStorage,
+    storage: &dyn Storage,
     sms_service: &SmsService,
     trust_manager: &mut TrustManager,
     args: CreateArgs,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let amount = Decimal::from_f64(args.amount).ok_or("Invalid amount")?;
+    let amount = match args.amount {
+        Some(amount) => Decimal::from_f64(amount).ok_or("Invalid amount")?,
+        None => args.items.iter().map(LineItem::total).sum(),
+    };
+    let description = args.description.unwrap_or_else(|| match args.items.is_empty() {
+        true => "Monthly stock purchase".to_string(),
+        false => args
+            .items
+            .iter()
+            .map(|item| format!("{} x {}", item.quantity, item.sku))
+            .collect::<Vec<_>>()
+            .join(", "),
+    });
+    let buyer = storage
+        .get_user(args.buyer_id)?
+        .ok_or_else(|| format!("Unknown buyer {}; register them first", args.buyer_id))?;
+    permissions::authorize(&buyer, Operation::CreateEscrow)?;
 
     // Clone necessary values before moving
     let currency_clone = args.currency.clone();
     let seller_id_str = args.seller_id.clone();
 
-    let escrow = Escrow::new(
+    let (mut escrow, release_pin) = Escrow::new(
         amount,
         args.currency,
         args.buyer_id,
         args.seller_id,
-        args.description,
+        description,
         args.days,
     );
+    if !args.items.is_empty() {
+        escrow.set_line_items(args.items)?;
+    }
+    let totp_secret = args.totp.then(totp::generate_secret);
+    if let Some(secret) = &totp_secret {
+        escrow.use_totp(secret.clone());
+    }
+    let milestone_pins = if args.milestones.is_empty() {
+        Vec::new()
+    } else {
+        let parts = args.milestones.iter().map(|m| (m.description.clone(), m.amount)).collect();
+        escrow.split_into_milestones(parts)?
+    };
 
-    storage.create_escrow(escrow.clone())?;
+    EscrowService::new(storage).create(&escrow)?;
+
+    register_party(storage, escrow.seller_id, &args.seller_phone, UserType::Seller)?;
 
     trust_manager.register_user(args.buyer_id);
     let seller_uuid = Uuid::parse_str(&seller_id_str)?;
     trust_manager.register_user(seller_uuid);
 
     if args.with_sms {
-        if let Some(pin) = &escrow.release_pin {
+        if totp_secret.is_none() && milestone_pins.is_empty() {
             sms_service.send_pin_to_buyer(
                 &args.buyer_phone,
-                pin,
+                &release_pin,
                 &escrow.id.to_string(),
-                args.amount,
+                escrow.amount.to_f64().unwrap_or(0.0),
                 &currency_clone,
             )?;
-
-            sms_service.notify_seller_delivery(
-                &args.seller_phone,
+        }
+        for (milestone, pin) in escrow.milestones.iter().zip(&milestone_pins) {
+            sms_service.send_pin_to_buyer(
+                &args.buyer_phone,
+                pin,
                 &escrow.id.to_string(),
-                args.amount,
+                milestone.amount.to_f64().unwrap_or(0.0),
                 &currency_clone,
             )?;
         }
+
+        sms_service.request_seller_acceptance(
+            &args.seller_phone,
+            &escrow.id.to_string(),
+            escrow.amount.to_f64().unwrap_or(0.0),
+            &currency_clone,
+            &escrow.expires_at.format("%Y-%m-%d").to_string(),
+        )?;
     }
 
     println!("✅ Escrow created successfully!");
     println!("📋 ID: {}", escrow.id);
     println!("💰 Amount: {} {}", escrow.amount, currency_clone);
     println!("📅 Expires: {}", escrow.expires_at);
-    println!("🔐 Release PIN: {}", escrow.release_pin.unwrap());
+    println!("⏳ Waiting for the seller to accept the terms before funding");
+    match &totp_secret {
+        Some(secret) => {
+            println!("🔐 Release mode: one-time codes (TOTP)");
+            println!("   Add to the buyer's authenticator app (shown once):");
+            println!("   {}", totp::provisioning_uri(secret, escrow.id));
+        }
+        None if milestone_pins.is_empty() => {
+            println!("🔐 Release PIN: {} (shown once; only a hash is stored)", release_pin)
+        }
+        None => {
+            println!("🔐 Milestone PINs (shown once; only hashes are stored):");
+            for (milestone, pin) in escrow.milestones.iter().zip(&milestone_pins) {
+                println!(
+                    "   {}. {} — {} {}: {}",
+                    milestone.number, milestone.description, milestone.amount, currency_clone, pin
+                );
+            }
+        }
+    }
     println!(
         "📱 SMS notifications: {}",
         if args.with_sms { "SENT" } else { "NOT SENT" }
@@ -104,82 +264,619 @@ fn handle_create(
     Ok(())
 }
 
-fn handle_fund(storage: &MemoryStorage, args: FundArgs) -> Result<(), Box<dyn std::error::Error>> {
-    let mut escrow = storage
-        .get_escrow(args.escrow_id)?
-        .ok_or("Escrow not found")?;
+/// Stores the contact details of a party the first time they appear, so
+/// later notifications can reach them.
+fn register_party(
+    storage: &dyn Storage,
+    user_id: Uuid,
+    phone: &str,
+    user_type: UserType,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if storage.get_user(user_id)?.is_none() {
+        storage.create_user(User::new(user_id, "", phone, user_type))?;
+    }
+    Ok(())
+}
+
+fn handle_accept(
+    storage: &dyn Storage,
+    sms_service: &SmsService,
+    args: AcceptArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (signed, actor) = sign_action(storage, &args.key, EscrowAction::AcceptTerms, args.escrow_id, args.user_id)?;
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::accept_terms(escrow, &signed, &actor)
+    })?;
+    notify_buyer_of_response(storage, sms_service, &escrow, true)?;
+
+    println!("🤝 Terms accepted; the buyer can now fund the escrow.");
+    Ok(())
+}
+
+fn handle_reject(
+    storage: &dyn Storage,
+    sms_service: &SmsService,
+    args: RejectArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (signed, actor) = sign_action(storage, &args.key, EscrowAction::RejectTerms, args.escrow_id, args.user_id)?;
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::reject_terms(escrow, &signed, &actor)
+    })?;
+    notify_buyer_of_response(storage, sms_service, &escrow, false)?;
+
+    println!("🚫 Terms rejected; the escrow is cancelled.");
+    Ok(())
+}
+
+fn notify_buyer_of_response(
+    storage: &dyn Storage,
+    sms_service: &SmsService,
+    escrow: &Escrow,
+    accepted: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(buyer) = storage.get_user(escrow.buyer_id)? {
+        sms_service.notify_terms_response(&buyer.phone_number, &escrow.id.to_string(), accepted)?;
+    }
+    Ok(())
+}
 
+fn handle_fund(storage: &dyn Storage, args: FundArgs) -> Result<(), Box<dyn std::error::Error>> {
     let amount = Decimal::from_f64(args.amount).ok_or("Invalid amount")?;
+    let action = EscrowAction::Fund { amount };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.user_id)?;
 
-    EscrowContract::fund_escrow(&mut escrow, amount)?;
-    storage.update_escrow(escrow)?;
+    EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::fund_escrow(escrow, &signed, &actor)
+    })?;
 
     println!("✅ Escrow funded successfully!");
     Ok(())
 }
 
 fn handle_release(
-    storage: &MemoryStorage,
+    storage: &dyn Storage,
+    sms_service: &SmsService,
+    pin_policy: &PinPolicy,
     args: ReleaseArgs,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut escrow = storage
-        .get_escrow(args.escrow_id)?
-        .ok_or("Escrow not found")?;
+    let action = match args.milestone {
+        Some(milestone) => EscrowAction::ReleaseMilestone { milestone, location: args.gps },
+        None => EscrowAction::Release { location: args.gps },
+    };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.user_id)?;
+    let result = EscrowService::new(storage).apply(args.escrow_id, |escrow| match args.milestone {
+        Some(_) => EscrowContract::release_milestone(escrow, &signed, &actor, &args.pin, pin_policy),
+        None => EscrowContract::release_to_seller(escrow, &signed, &actor, &args.pin, pin_policy),
+    });
 
-    EscrowContract::release_to_seller(&mut escrow, args.user_id, &args.pin)?;
-    storage.update_escrow(escrow)?;
+    // The buyer is warned even when their driver entered the wrong PINs.
+    if let Err(EscrowError::TooManyPinAttempts { until }) = &result {
+        let buyer_id = EscrowService::new(storage).get(args.escrow_id)?.buyer_id;
+        if let Some(buyer) = storage.get_user(buyer_id)? {
+            sms_service.notify_pin_locked(
+                &buyer.phone_number,
+                &args.escrow_id.to_string(),
+                &until.format("%Y-%m-%d %H:%M UTC").to_string(),
+            )?;
+        }
+    }
+    let escrow = result?;
+
+    if let Some(number) = args.milestone {
+        let released = escrow.milestones.iter().filter(|m| m.is_released()).count();
+        println!(
+            "✅ Milestone {} released to seller ({} of {} deliveries done)",
+            number,
+            released,
+            escrow.milestones.len()
+        );
+        let handover = escrow.milestone(number).and_then(|m| m.handover.as_ref());
+        if let Some(location) = handover.and_then(|h| h.location) {
+            println!("📍 Handover recorded at {}", location);
+        }
+        return Ok(());
+    }
 
     println!("✅ Funds released to seller!");
+    if let Some(handover) = &escrow.handover {
+        let place = handover.location.map(|l| format!(" at {}", l)).unwrap_or_default();
+        println!("📍 Handover by {} recorded {}{}", handover.released_by, handover.at, place);
+    }
+    Ok(())
+}
+
+fn handle_confirm(storage: &dyn Storage, args: ConfirmArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let service = EscrowService::new(storage);
+    let escrow = service.get(args.escrow_id)?;
+    if let Some(unknown) = args.received.iter().find(|r| !escrow.line_items.iter().any(|i| i.sku == r.sku)) {
+        return Err(format!("Escrow has no line item {}", unknown.sku).into());
+    }
+    let received: Vec<u32> = escrow
+        .line_items
+        .iter()
+        .map(|item| {
+            args.received
+                .iter()
+                .rfind(|r| r.sku == item.sku)
+                .map_or(item.quantity, |r| r.quantity)
+        })
+        .collect();
+
+    let action = EscrowAction::ConfirmDelivery { received: action::received_digest(&received) };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.user_id)?;
+    let escrow = service.apply(args.escrow_id, |escrow| {
+        EscrowContract::confirm_delivery(escrow, &signed, &actor, &received)
+    })?;
+
+    println!("✅ Delivery confirmed");
+    for item in &escrow.line_items {
+        println!(
+            "   {:<16} {:>4} of {:<4} @ {}",
+            item.sku,
+            item.received.unwrap_or(0),
+            item.quantity,
+            item.unit_price
+        );
+    }
+    print_settlement(&escrow);
+    Ok(())
+}
+
+fn handle_resend_pin(
+    storage: &dyn Storage,
+    sms_service: &SmsService,
+    pin_policy: &PinPolicy,
+    args: ResendPinArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (signed, mut buyer) =
+        sign_action(storage, &args.key, EscrowAction::ReissuePin, args.escrow_id, args.user_id)?;
+    let phone = args.phone.clone().unwrap_or_else(|| buyer.phone_number.clone());
+
+    let new_pin = pin::generate();
+    let new_pin_hash = pin::hash(&new_pin);
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::reissue_pin(escrow, &signed, &buyer, new_pin_hash.clone(), pin_policy)
+    })?;
+
+    if buyer.phone_number != phone {
+        buyer.phone_number = phone.clone();
+        storage.update_user(buyer)?;
+    }
+
+    sms_service.send_pin_to_buyer(
+        &phone,
+        &new_pin,
+        &escrow.id.to_string(),
+        escrow.amount.to_f64().unwrap_or(0.0),
+        &escrow.currency,
+    )?;
+
+    println!("✅ New release PIN sent; the previous PIN no longer works.");
+    Ok(())
+}
+
+fn handle_register(storage: &dyn Storage, args: RegisterArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if storage.get_user(args.user_id)?.is_some() {
+        return Err(format!("User {} is already registered", args.user_id).into());
+    }
+    storage.create_user(User::new(args.user_id, args.name, args.phone, args.role))?;
+
+    println!("👤 Registered {:?} {}", args.role, args.user_id);
+    println!("   Run `keygen` next so the user can sign escrow actions.");
+    Ok(())
+}
+
+fn handle_keygen(storage: &dyn Storage, args: KeygenArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut user = storage
+        .get_user(args.user_id)?
+        .ok_or_else(|| format!("Unknown user {}", args.user_id))?;
+    if user.public_key.is_some() && !args.force {
+        return Err("User already has a key; pass --force to replace it".into());
+    }
+
+    let signing_key = identity::generate_signing_key();
+    identity::write_signing_key(&args.output, &signing_key).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => {
+            format!("{} already exists; move it away or choose another --output", args.output.display())
+        }
+        _ => e.to_string(),
+    })?;
+    user.public_key = Some(identity::encode_public_key(&signing_key.verifying_key()));
+    storage.update_user(user.clone())?;
+
+    println!("🔑 Key pair created for user {}", user.id);
+    println!("   Secret key: {} (keep it on the user's device)", args.output.display());
+    println!("   Public key: {}", user.public_key.unwrap_or_default());
+    Ok(())
+}
+
+fn handle_voucher(storage: &dyn Storage, args: VoucherArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let escrow = EscrowService::new(storage).get(args.escrow_id)?;
+    if !escrow.milestones.is_empty() || !escrow.line_items.is_empty() {
+        return Err("Milestone and itemised escrows cannot be released with a voucher".into());
+    }
+    let signing_key = identity::read_signing_key(&args.key)?;
+
+    let voucher = ReleaseVoucher::issue(&escrow, &signing_key, Duration::hours(args.valid_hours))?;
+
+    println!("🎟️  Release voucher for escrow {}", escrow.id);
+    println!("   Valid until: {}", voucher.expires_at);
+    println!("{}", voucher.encode());
+    Ok(())
+}
+
+fn handle_verify_voucher(args: VerifyVoucherArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let voucher = ReleaseVoucher::decode(&args.voucher)?;
+    let buyer_key = identity::decode_public_key(&args.public_key)?;
+    voucher.verify(&buyer_key, Utc::now())?;
+
+    println!("✅ Voucher signature valid");
+    println!("   Escrow: {}", voucher.escrow_id);
+    println!("   Buyer: {}", voucher.buyer_id);
+    println!("   Amount: {} {}", voucher.amount, voucher.currency);
+    println!("   Valid until: {}", voucher.expires_at);
+    Ok(())
+}
+
+fn handle_redeem(storage: &dyn Storage, args: RedeemArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let voucher = ReleaseVoucher::decode(&args.voucher)?;
+    let buyer_key = storage
+        .get_user(voucher.buyer_id)?
+        .and_then(|buyer| buyer.public_key)
+        .ok_or_else(|| format!("Buyer {} has no registered public key", voucher.buyer_id))?;
+    let buyer_key = identity::decode_public_key(&buyer_key)?;
+
+    EscrowService::new(storage).apply(voucher.escrow_id, |escrow| {
+        EscrowContract::redeem_voucher(escrow, &voucher, &buyer_key)
+    })?;
+
+    println!("✅ Voucher redeemed; funds released to seller!");
+    Ok(())
+}
+
+fn handle_assign_driver(
+    storage: &dyn Storage,
+    args: AssignDriverArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let driver = storage
+        .get_user(args.driver_id)?
+        .ok_or_else(|| format!("Unknown driver {}; register them first", args.driver_id))?;
+    let action = EscrowAction::AssignDriver { driver_id: driver.id };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.user_id)?;
+
+    EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::assign_driver(escrow, &signed, &actor, &driver)
+    })?;
+
+    println!("🚚 Driver {} may now release this escrow at delivery", driver.id);
     Ok(())
 }
 
 fn handle_cancel(
-    storage: &MemoryStorage,
+    storage: &dyn Storage,
     args: CancelArgs,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut escrow = storage
-        .get_escrow(args.escrow_id)?
-        .ok_or("Escrow not found")?;
-
-    EscrowContract::cancel_escrow(&mut escrow, args.user_id)?;
-    storage.update_escrow(escrow)?;
+    let (signed, actor) = sign_action(storage, &args.key, EscrowAction::Cancel, args.escrow_id, args.user_id)?;
+    EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::cancel_escrow(escrow, &signed, &actor)
+    })?;
 
     println!("✅ Escrow cancelled!");
     Ok(())
 }
 
 fn handle_dispute(
-    storage: &MemoryStorage,
+    storage: &dyn Storage,
+    dispute_policy: &DisputePolicy,
     args: DisputeArgs,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut escrow = storage
-        .get_escrow(args.escrow_id)?
-        .ok_or("Escrow not found")?;
-
-    EscrowContract::raise_dispute(&mut escrow, args.user_id)?;
-    storage.update_escrow(escrow)?;
+    let (signed, actor) = sign_action(storage, &args.key, EscrowAction::Dispute, args.escrow_id, args.user_id)?;
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        let panel = dispute::select_panel(storage, escrow, dispute_policy)?;
+        EscrowContract::raise_dispute(escrow, &signed, &actor, dispute_policy, panel)
+    })?;
 
     println!("⚠️  Dispute raised! Waiting for arbitrator votes.");
+    println!("⚖️  Panel: {}", escrow.arbitrators.iter().map(Uuid::to_string).collect::<Vec<_>>().join(", "));
+    if let Some(dispute) = &escrow.dispute_resolution {
+        if let Some(deadline) = dispute.evidence_deadline {
+            println!("📎 Both parties can submit evidence until {}", deadline.format("%Y-%m-%d %H:%M UTC"));
+        }
+        if let Some(deadline) = dispute.voting_deadline {
+            println!("🗳️  Arbitrators vote after that, until {}", deadline.format("%Y-%m-%d %H:%M UTC"));
+        }
+    }
+    Ok(())
+}
+
+fn handle_submit_evidence(
+    storage: &dyn Storage,
+    args: SubmitEvidenceArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = args.content.trim();
+    let action = EscrowAction::SubmitEvidence { evidence: action::evidence_digest(args.kind, content) };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.user_id)?;
+    EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::submit_evidence(escrow, &signed, &actor, args.kind, content)
+    })?;
+
+    println!("📎 Evidence submitted ({})", args.kind);
     Ok(())
 }
 
-fn handle_vote(storage: &MemoryStorage, args: VoteArgs) -> Result<(), Box<dyn std::error::Error>> {
-    let mut escrow = storage
-        .get_escrow(args.escrow_id)?
-        .ok_or("Escrow not found")?;
+/// What the arbitrators review before voting.
+fn handle_evidence(storage: &dyn Storage, args: EvidenceArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let escrow = EscrowService::new(storage).get(args.escrow_id)?;
+    let dispute = escrow
+        .dispute_resolution
+        .as_ref()
+        .ok_or_else(|| format!("Escrow {} has no dispute", escrow.id))?;
+
+    println!("⚠️  Dispute raised {}", dispute.raised_at.format("%Y-%m-%d %H:%M UTC"));
+    match dispute.evidence_deadline {
+        Some(deadline) if dispute.accepts_evidence_at(Utc::now()) => {
+            println!("📎 Evidence open until {}", deadline.format("%Y-%m-%d %H:%M UTC"))
+        }
+        _ => println!("📎 Evidence closed"),
+    }
+    if dispute.evidence.is_empty() {
+        println!("   No evidence submitted");
+    }
+    for evidence in &dispute.evidence {
+        let party = if evidence.submitted_by == escrow.buyer_id { "buyer" } else { "seller" };
+        println!(
+            "\n[{}] {} from the {}:",
+            evidence.submitted_at.format("%Y-%m-%d %H:%M"),
+            evidence.kind,
+            party
+        );
+        println!("   {}", evidence.content);
+    }
+    Ok(())
+}
 
-    EscrowContract::vote_on_dispute(&mut escrow, args.arbitrator_id, args.vote)?;
-    storage.update_escrow(escrow)?;
+fn handle_vote(storage: &dyn Storage, args: VoteArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let action = match args.split {
+        Some(seller_percent) => EscrowAction::VoteSplit { seller_percent },
+        None => EscrowAction::Vote { release_to_seller: args.vote },
+    };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.arbitrator_id)?;
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::vote_on_dispute(escrow, &signed, &actor)
+    })?;
 
     println!("✅ Vote recorded!");
+    if let Some(resolution) = &escrow.dispute_resolution {
+        match (&resolution.decision, resolution.appeal_deadline) {
+            (Some(decision), Some(deadline)) => {
+                println!("⚖️  Panel decided: {}", decision);
+                println!(
+                    "   Funds are held while the decision can be appealed, until {}",
+                    deadline.format("%Y-%m-%d %H:%M UTC")
+                );
+            }
+            (Some(decision), None) => {
+                dispute::record_outcome(storage, &escrow)?;
+                println!("⚖️  Dispute resolved: {}", decision);
+            }
+            (None, _) => {}
+        }
+    }
+    print_settlement(&escrow);
     Ok(())
 }
 
-fn handle_list(storage: &MemoryStorage) -> Result<(), Box<dyn std::error::Error>> {
-    let escrows = storage.list_escrows()?;
+fn handle_appeal(
+    storage: &dyn Storage,
+    dispute_policy: &DisputePolicy,
+    args: AppealArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (signed, actor) = sign_action(storage, &args.key, EscrowAction::Appeal, args.escrow_id, args.user_id)?;
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        let panel = dispute::select_appeal_panel(storage, escrow, dispute_policy)?;
+        EscrowContract::appeal_decision(escrow, &signed, &actor, dispute_policy, panel)
+    })?;
+
+    println!("📣 Decision appealed; the funds stay held until the appeal is decided.");
+    println!("⚖️  Appeal panel: {}", escrow.arbitrators.iter().map(Uuid::to_string).collect::<Vec<_>>().join(", "));
+    if let Some(deadline) = escrow.dispute_resolution.as_ref().and_then(|d| d.voting_deadline) {
+        println!("🗳️  Votes due by {}", deadline.format("%Y-%m-%d %H:%M UTC"));
+    }
+    Ok(())
+}
+
+fn handle_decide(storage: &dyn Storage, args: DecideArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let action = EscrowAction::DecideDispute { seller_percent: args.seller_percent };
+    let (signed, actor) = sign_action(storage, &args.key, action, args.escrow_id, args.user_id)?;
+    let escrow = EscrowService::new(storage).apply(args.escrow_id, |escrow| {
+        EscrowContract::decide_dispute(escrow, &signed, &actor)
+    })?;
+    dispute::record_outcome(storage, &escrow)?;
+
+    if let Some(decision) = escrow.dispute_resolution.as_ref().and_then(|d| d.decision.as_ref()) {
+        println!("⚖️  Dispute resolved: {}", decision);
+    }
+    print_settlement(&escrow);
+    Ok(())
+}
+
+/// Moves on every dispute whose deadline has passed. Meant to run
+/// regularly, from cron for instance, so no dispute is stuck waiting for
+/// arbitrators who never vote.
+fn handle_dispute_deadlines(
+    storage: &dyn Storage,
+    dispute_policy: &DisputePolicy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let now = Utc::now();
+    let service = EscrowService::new(storage);
+    let overdue: Vec<Escrow> = storage
+        .list_escrows()?
+        .into_iter()
+        .filter(|e| {
+            e.state == EscrowState::InDispute
+                && e.dispute_resolution.as_ref().is_some_and(|d| d.is_overdue_at(now))
+        })
+        .collect();
+
+    if overdue.is_empty() {
+        println!("No dispute is past its deadline");
+    }
+    for escrow in overdue {
+        let before = escrow.dispute_resolution.as_ref();
+        let escalated = before.is_some_and(|d| d.escalated_at.is_some());
+        let decided = before.is_some_and(|d| d.decision.is_some());
+        let replacements = if escalated || decided {
+            Vec::new()
+        } else {
+            dispute::select_replacements(storage, &escrow)?
+        };
+
+        let updated = service.apply(escrow.id, |escrow| {
+            EscrowContract::enforce_dispute_deadline(escrow, dispute_policy, replacements.clone())
+        })?;
+        let Some(resolution) = &updated.dispute_resolution else {
+            continue;
+        };
+        // The event says what the deadline actually led to; only arbitrators
+        // who were swapped out lose reputation for the missed vote.
+        let outcome = storage.list_events(updated.id)?.pop().map(|event| event.kind);
+
+        match outcome {
+            Some(EscrowEventKind::ArbitratorsReplaced { replaced, voting_deadline, .. }) => {
+                dispute::record_missed_votes(storage, &replaced)?;
+                println!(
+                    "🔄 {}: replaced {} arbitrator(s) who did not vote; voting closes {}",
+                    updated.id,
+                    replaced.len(),
+                    voting_deadline.format("%Y-%m-%d %H:%M UTC")
+                );
+            }
+            Some(EscrowEventKind::DisputeEscalated { escalation_deadline, reason }) => {
+                println!(
+                    "🚨 {}: escalated to an admin ({}), who must decide by {}",
+                    updated.id,
+                    reason.as_deref().unwrap_or("no reason recorded"),
+                    escalation_deadline.format("%Y-%m-%d %H:%M UTC")
+                );
+            }
+            _ => {
+                let Some(decision) = &resolution.decision else {
+                    continue;
+                };
+                dispute::record_outcome(storage, &updated)?;
+                if decided {
+                    println!("✅ {}: nobody appealed in time; final decision: {}", updated.id, decision);
+                } else {
+                    println!("⚖️  {}: no decision in time, so the default applies: {}", updated.id, decision);
+                }
+                print_settlement(&updated);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Refunds every funded escrow that expired before it was released. Meant
+/// to run from cron alongside `dispute-deadlines`.
+fn handle_refund_expired(storage: &dyn Storage) -> Result<(), Box<dyn std::error::Error>> {
+    let service = EscrowService::new(storage);
+    let expired: Vec<Escrow> = storage
+        .list_escrows()?
+        .into_iter()
+        .filter(|e| e.state == EscrowState::Funded && e.is_expired())
+        .collect();
+
+    if expired.is_empty() {
+        println!("No funded escrow is past its expiry");
+    }
+    for escrow in expired {
+        let refunded = service.apply(escrow.id, EscrowContract::refund_expired)?;
+        println!(
+            "↩️  {}: expired {}; refunded {} {} to the buyer",
+            refunded.id,
+            refunded.expires_at.format("%Y-%m-%d %H:%M UTC"),
+            refunded.outstanding_amount(),
+            refunded.currency
+        );
+    }
+    Ok(())
+}
+
+fn print_settlement(escrow: &Escrow) {
+    if let Some(settlement) = &escrow.settlement {
+        println!("💰 To seller: {} {}", settlement.to_seller, escrow.currency);
+        println!("↩️  Refunded to buyer: {} {}", settlement.to_buyer, escrow.currency);
+    }
+}
+
+fn handle_arbitrators(storage: &dyn Storage) -> Result<(), Box<dyn std::error::Error>> {
+    let arbitrators = dispute::registry(storage)?;
+    if arbitrators.is_empty() {
+        println!("No arbitrators registered yet; `register --role arbitrator` then `keygen`.");
+    }
+    for arbitrator in &arbitrators {
+        println!(
+            "{}  {:<20} reputation {:>5.1}  {} disputes",
+            arbitrator.id,
+            arbitrator.name,
+            arbitrator.trust_score.score,
+            arbitrator.trust_score.total_transactions
+        );
+    }
+    Ok(())
+}
+
+/// Looks up the acting user, checks their role may perform `action` at all
+/// and signs it with the key file against the escrow's current version. The
+/// contract repeats the role check and verifies the signature against the key
+/// on the returned user record.
+fn sign_action(
+    storage: &dyn Storage,
+    key_path: &Path,
+    action: EscrowAction,
+    escrow_id: Uuid,
+    actor_id: Uuid,
+) -> Result<(SignedAction, User), Box<dyn std::error::Error>> {
+    let actor = storage
+        .get_user(actor_id)?
+        .ok_or(EscrowError::Unauthorized(actor_id))?;
+    permissions::authorize(&actor, action.into())?;
+
+    let escrow_version = EscrowService::new(storage).get(escrow_id)?.version;
+    let signing_key = identity::read_signing_key(key_path)?;
+    Ok((SignedAction::sign(&signing_key, action, escrow_id, escrow_version, actor_id), actor))
+}
+
+fn handle_list(storage: &dyn Storage, args: ListArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let per_page = args.per_page.max(1);
+    let page = args.page.max(1);
 
-    println!("📋 Total escrows: {}", escrows.len());
-    for escrow in escrows {
+    let query = EscrowQuery {
+        buyer_id: args.buyer_id,
+        seller_id: args.seller_id,
+        state: args.state,
+        currency: args.currency,
+        created_from: args.created_from,
+        created_to: args.created_to,
+        expires_from: args.expires_from,
+        expires_to: args.expires_to,
+        sort_by: match args.sort {
+            ListSort::Created => EscrowSortField::CreatedAt,
+            ListSort::Expires => EscrowSortField::ExpiresAt,
+            ListSort::Amount => EscrowSortField::Amount,
+        },
+        order: if args.asc { SortOrder::Ascending } else { SortOrder::Descending },
+        offset: (page - 1) * per_page,
+        limit: Some(per_page),
+    };
+    let result = storage.query_escrows(&query)?;
+    let pages = result.total.div_ceil(per_page).max(1);
+
+    println!(
+        "📋 Total escrows: {} (page {} of {})",
+        result.total, page, pages
+    );
+    for escrow in result.escrows {
         println!("\n---");
         println!("ID: {}", escrow.id);
         println!("Amount: {} {}", escrow.amount, escrow.currency);
@@ -191,12 +888,168 @@ fn handle_list(storage: &MemoryStorage) -> Result<(), Box<dyn std::error::Error>
     Ok(())
 }
 
-fn handle_get(storage: &MemoryStorage, args: GetArgs) -> Result<(), Box<dyn std::error::Error>> {
-    let escrow = storage
-        .get_escrow(args.escrow_id)?
-        .ok_or("Escrow not found")?;
+fn handle_get(storage: &dyn Storage, args: GetArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let escrow = EscrowService::new(storage).get(args.escrow_id)?;
+
+    // PIN hashes and TOTP secrets stay out of the output; anyone can run
+    // `get`, including the driver standing at the door.
+    println!("{:#?}", escrow.redacted());
+    Ok(())
+}
+
+fn handle_history(
+    storage: &dyn Storage,
+    args: HistoryArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = EscrowService::new(storage);
+    let events = service.history(args.escrow_id)?;
+
+    println!("📜 History for escrow {}", args.escrow_id);
+    for event in &events {
+        let actor = event
+            .kind
+            .actor()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "system".to_string());
+        println!(
+            "  #{:<4} {}  {:<40} by {}  [{}]",
+            event.sequence,
+            event.occurred_at.format("%Y-%m-%d %H:%M:%S"),
+            event.kind.to_string(),
+            actor,
+            &event.hash[..event.hash.len().min(12)]
+        );
+    }
+
+    let replayed = service.replay(args.escrow_id)?;
+    println!("\nReplayed state: {:?}", replayed.state);
+    Ok(())
+}
+
+fn handle_verify(storage: &dyn Storage) -> Result<(), Box<dyn std::error::Error>> {
+    let report = EscrowService::new(storage).verify()?;
+
+    println!("🔍 Checked {} history records", report.records_checked);
+    if let Some(head) = &report.head_hash {
+        println!("🔗 Head hash: {}", head);
+    }
+
+    if report.is_intact() {
+        println!("✅ Audit log intact");
+        return Ok(());
+    }
+
+    for issue in &report.issues {
+        println!("❌ {}", issue);
+    }
+    Err(format!("Audit log verification failed with {} issue(s)", report.issues.len()).into())
+}
+
+fn handle_backup(storage: &dyn Storage, args: BackupArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = backup::export(storage)?;
+    let summary = backup::write_archive(&args.output, &contents)?;
 
-    println!("{:#?}", escrow);
+    println!("💾 Backup written to {}", args.output.display());
+    println!(
+        "   {} escrows, {} users, {} trust profiles, {} history records",
+        summary.escrows, summary.users, summary.trust_profiles, summary.events
+    );
+    Ok(())
+}
+
+/// Restores into a staging directory beside `data_dir` and only swaps it
+/// in once every record is written, so a failed restore leaves the store
+/// exactly as it was.
+fn handle_restore(
+    storage: &dyn Storage,
+    backend: StorageBackend,
+    data_dir: &Path,
+    args: RestoreArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = backup::read_archive(&args.input)?;
+    backup::check_empty(storage)?;
+
+    let restore_and_verify = |target: &dyn Storage| -> Result<_, Box<dyn std::error::Error>> {
+        let summary = backup::restore(target, &contents)?;
+        Ok((summary, EscrowService::new(target).verify()?))
+    };
+    let (summary, report) = match backend {
+        StorageBackend::Memory => restore_and_verify(storage)?,
+        StorageBackend::File | StorageBackend::Sqlite => {
+            let staging = sibling_dir(data_dir, "restoring");
+            if staging.exists() {
+                std::fs::remove_dir_all(&staging)?;
+            }
+            let restored = open_storage(backend, &staging).and_then(|staged| restore_and_verify(staged.as_ref()));
+            match restored {
+                Ok(restored) => {
+                    swap_in(&staging, data_dir)?;
+                    restored
+                }
+                Err(e) => {
+                    let _ = std::fs::remove_dir_all(&staging);
+                    return Err(e);
+                }
+            }
+        }
+    };
+
+    println!("♻️  Restored from {}", args.input.display());
+    println!(
+        "   {} escrows, {} users, {} trust profiles, {} history records",
+        summary.escrows, summary.users, summary.trust_profiles, summary.events
+    );
+
+    if report.is_intact() {
+        println!("✅ Audit log intact after restore");
+    } else {
+        println!("⚠️  Restored audit log has {} issue(s); run `verify`", report.issues.len());
+    }
+    Ok(())
+}
+
+/// `data_dir` with `suffix` appended to its last component.
+fn sibling_dir(data_dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = data_dir.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Moves the fully written `staging` directory into place as `data_dir`,
+/// putting the old directory back if the move fails.
+fn swap_in(staging: &Path, data_dir: &Path) -> std::io::Result<()> {
+    let displaced = sibling_dir(data_dir, "pre-restore");
+    if displaced.exists() {
+        std::fs::remove_dir_all(&displaced)?;
+    }
+    std::fs::rename(data_dir, &displaced)?;
+    if let Err(e) = std::fs::rename(staging, data_dir) {
+        std::fs::rename(&displaced, data_dir)?;
+        return Err(e);
+    }
+    std::fs::remove_dir_all(&displaced)
+}
+
+fn handle_rotate_key(
+    raw_storage: &dyn Storage,
+    config_path: &Path,
+    config: &mut AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let previous = config.encryption.active_key.clone();
+    let new_key = config.encryption.add_key().id.clone();
+    // Save before touching any data so the new key is never lost, even if
+    // re-encryption is interrupted halfway.
+    config.save(config_path)?;
+
+    let summary = reencrypt_all(raw_storage, &config.encryption.keyring()?)?;
+
+    println!("🔑 Rotated encryption key {} → {}", previous, new_key);
+    println!("   Re-encrypted {} escrows and {} users", summary.escrows, summary.users);
+    println!(
+        "   {} retired key(s) kept in the config so existing backups can still be restored",
+        config.encryption.retired_keys()
+    );
     Ok(())
 }
 
@@ -225,7 +1078,7 @@ fn handle_sms(sms_service: &SmsService, args: SmsArgs) -> Result<(), Box<dyn std
 }
 
 fn handle_demo(
-    storage: &MemoryStorage,
+    storage: &dyn Storage,
     sms_service: &SmsService,
     trust_manager: &mut TrustManager,
     _args: DemoArgs,
@@ -238,6 +1091,13 @@ fn handle_demo(
 
     let buyer_id = Uuid::new_v4();
     let seller_id = Uuid::new_v4();
+    // Keys would normally live on each party's phone.
+    let buyer_key = identity::generate_signing_key();
+    let mut buyer = User::new(buyer_id, "Spaza Owner", "+27123456789", UserType::Buyer);
+    buyer.public_key = Some(identity::encode_public_key(&buyer_key.verifying_key()));
+    let seller_key = identity::generate_signing_key();
+    let mut seller = User::new(seller_id, "Wholesaler", "+27876543210", UserType::Seller);
+    seller.public_key = Some(identity::encode_public_key(&seller_key.verifying_key()));
 
     println!("\n📋 Scenario: Spaza shop buying stock from wholesaler");
     println!("👨‍💼 Buyer (Spaza Owner): {}", buyer_id);
@@ -248,7 +1108,7 @@ fn handle_demo(
 
     println!("\n1️⃣ Creating escrow...");
     let amount = Decimal::new(150000, 2);
-    let escrow = Escrow::new(
+    let (escrow, pin) = Escrow::new(
         amount,
         "ZAR".to_string(),
         buyer_id,
@@ -257,35 +1117,43 @@ fn handle_demo(
         7,
     );
 
-    storage.create_escrow(escrow.clone())?;
+    let service = EscrowService::new(storage);
+    service.create(&escrow)?;
     println!("   ✅ Escrow created: {}", escrow.id);
 
     trust_manager.register_user(buyer_id);
     trust_manager.register_user(seller_id);
 
     println!("\n2️⃣ Sending SMS notifications...");
-    let pin_clone = escrow.release_pin.clone();
-    if let Some(pin) = &pin_clone {
-        sms_service.send_pin_to_buyer(
-            "+27123456789",
-            pin,
-            &escrow.id.to_string(),
-            1500.0,
-            "ZAR",
-        )?;
-        sms_service.notify_seller_delivery(
-            "+27876543210",
-            &escrow.id.to_string(),
-            1500.0,
-            "ZAR",
-        )?;
-    }
+    sms_service.send_pin_to_buyer(
+        "+27123456789",
+        &pin,
+        &escrow.id.to_string(),
+        1500.0,
+        "ZAR",
+    )?;
+    sms_service.request_seller_acceptance(
+        "+27876543210",
+        &escrow.id.to_string(),
+        1500.0,
+        "ZAR",
+        &escrow.expires_at.format("%Y-%m-%d").to_string(),
+    )?;
 
-    println!("\n3️⃣ Funding escrow...");
-    let mut escrow = storage.get_escrow(escrow.id)?.unwrap();
-    EscrowContract::fund_escrow(&mut escrow, amount)?;
-    storage.update_escrow(escrow.clone())?;
+    println!("\n3️⃣ Seller accepts the terms, buyer funds escrow...");
+    let signed = SignedAction::sign(&seller_key, EscrowAction::AcceptTerms, escrow.id, escrow.version, seller_id);
+    service.apply(escrow.id, |escrow| EscrowContract::accept_terms(escrow, &signed, &seller))?;
+    println!("   ✅ Terms accepted");
+    let escrow = service.get(escrow.id)?;
+    let signed = SignedAction::sign(&buyer_key, EscrowAction::Fund { amount }, escrow.id, escrow.version, buyer_id);
+    let escrow = service.apply(escrow.id, |escrow| EscrowContract::fund_escrow(escrow, &signed, &buyer))?;
     println!("   ✅ Escrow funded");
+    sms_service.notify_seller_delivery(
+        "+27876543210",
+        &escrow.id.to_string(),
+        1500.0,
+        "ZAR",
+    )?;
 
     println!("\n4️⃣ Updating trust scores...");
     trust_manager.record_transaction(buyer_id, amount, true, false)?;
@@ -298,19 +1166,19 @@ fn handle_demo(
     println!("   Seller trust: {:.1}/100", seller_trust.trust_score);
 
     println!("\n5️⃣ Releasing funds with PIN...");
-    let mut escrow = storage.get_escrow(escrow.id)?.unwrap();
-    if let Some(pin) = pin_clone {
-        EscrowContract::release_to_seller(&mut escrow, buyer_id, &pin)?;
-        storage.update_escrow(escrow.clone())?;
-        println!("   ✅ Funds released to seller");
-
-        sms_service.notify_payment_released(
-            "+27876543210",
-            1500.0,
-            "ZAR",
-            &escrow.id.to_string(),
-        )?;
-    }
+    let release = EscrowAction::Release { location: None };
+    let signed = SignedAction::sign(&buyer_key, release, escrow.id, escrow.version, buyer_id);
+    let escrow = service.apply(escrow.id, |escrow| {
+        EscrowContract::release_to_seller(escrow, &signed, &buyer, &pin, &PinPolicy::default())
+    })?;
+    println!("   ✅ Funds released to seller");
+
+    sms_service.notify_payment_released(
+        "+27876543210",
+        1500.0,
+        "ZAR",
+        &escrow.id.to_string(),
+    )?;
 
     println!("\n6️⃣ Final state:");
     println!("   Escrow State: {:?}", escrow.state);
@@ -335,7 +1203,7 @@ fn handle_demo(
 }
 
 fn handle_dashboard(
-    storage: &MemoryStorage,
+    storage: &dyn Storage,
     trust_manager: &TrustManager,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use colored::*;
@@ -348,7 +1216,8 @@ fn handle_dashboard(
         .filter(|e| {
             matches!(
                 e.state,
-                spaza_safety_escrow::types::EscrowState::Created
+                spaza_safety_escrow::types::EscrowState::AwaitingSellerAcceptance
+                    | spaza_safety_escrow::types::EscrowState::Created
                     | spaza_safety_escrow::types::EscrowState::Funded
             )
         })
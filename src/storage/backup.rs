@@ -0,0 +1,273 @@
+use crate::escrow::events::EscrowEvent;
+use crate::storage::errors::StorageError;
+use crate::storage::repository::Storage;
+use crate::trust::UserTrustProfile;
+use crate::types::{Escrow, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+pub const BACKUP_FORMAT: &str = "spaza-escrow-backup";
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Everything needed to rebuild a store: escrows (including their dispute
+/// data), users, trust profiles and the full event log.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupContents {
+    pub escrows: Vec<Escrow>,
+    pub users: Vec<User>,
+    pub trust_profiles: Vec<UserTrustProfile>,
+    pub events: Vec<EscrowEvent>,
+}
+
+/// On-disk archive. The checksum is a SHA-256 over the exact bytes of
+/// `contents` as written, so any change to the file is caught on restore.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupArchive {
+    pub format: String,
+    pub format_version: u32,
+    pub created_at: DateTime<Utc>,
+    pub checksum: String,
+    pub contents: Box<RawValue>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BackupSummary {
+    pub escrows: usize,
+    pub users: usize,
+    pub trust_profiles: usize,
+    pub events: usize,
+}
+
+impl BackupContents {
+    pub fn summary(&self) -> BackupSummary {
+        BackupSummary {
+            escrows: self.escrows.len(),
+            users: self.users.len(),
+            trust_profiles: self.trust_profiles.len(),
+            events: self.events.len(),
+        }
+    }
+}
+
+/// Reads every record out of `storage`.
+pub fn export(storage: &dyn Storage) -> Result<BackupContents, StorageError> {
+    Ok(BackupContents {
+        escrows: storage.list_escrows()?,
+        users: storage.list_users()?,
+        trust_profiles: storage.list_trust_profiles()?,
+        events: storage.list_all_events()?,
+    })
+}
+
+/// Writes `contents` into an empty `storage`. Every record is checked
+/// before the first write, so an inconsistent archive is refused without
+/// touching the store. Events are appended in their original order so
+/// sequence numbers and hashes are preserved exactly.
+pub fn restore(storage: &dyn Storage, contents: &BackupContents) -> Result<BackupSummary, StorageError> {
+    check_empty(storage)?;
+    validate(contents)?;
+
+    for user in &contents.users {
+        storage.create_user(user.clone())?;
+    }
+    storage.save_trust_profiles(&contents.trust_profiles)?;
+    for escrow in &contents.escrows {
+        storage.create_escrow(escrow.clone())?;
+    }
+
+    let mut events = contents.events.clone();
+    events.sort_by_key(|e| e.sequence);
+    for event in events {
+        storage.append_event(event)?;
+    }
+
+    Ok(contents.summary())
+}
+
+/// A restore replaces a store rather than merging into it, so the target
+/// must not hold any escrows, users or history yet.
+pub fn check_empty(storage: &dyn Storage) -> Result<(), StorageError> {
+    if !storage.list_escrows()?.is_empty()
+        || !storage.list_users()?.is_empty()
+        || storage.last_event()?.is_some()
+    {
+        return Err(StorageError::InvalidBackup(
+            "Refusing to restore into a store that already holds data".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the archive hangs together: no escrow or user appears twice, the
+/// event log runs from sequence 1 without gaps, and every event belongs to
+/// an escrow in the archive.
+fn validate(contents: &BackupContents) -> Result<(), StorageError> {
+    let mut escrow_ids = HashSet::new();
+    if let Some(escrow) = contents.escrows.iter().find(|e| !escrow_ids.insert(e.id)) {
+        return Err(StorageError::InvalidBackup(format!("Escrow {} appears twice", escrow.id)));
+    }
+    let mut user_ids = HashSet::new();
+    if let Some(user) = contents.users.iter().find(|u| !user_ids.insert(u.id)) {
+        return Err(StorageError::InvalidBackup(format!("User {} appears twice", user.id)));
+    }
+
+    let mut sequences: Vec<u64> = contents.events.iter().map(|e| e.sequence).collect();
+    sequences.sort_unstable();
+    if let Some((expected, found)) = (1..).zip(sequences).find(|(expected, found)| expected != found) {
+        return Err(StorageError::InvalidBackup(format!(
+            "History records out of sequence: expected {}, found {}",
+            expected, found
+        )));
+    }
+    if let Some(event) = contents.events.iter().find(|e| !escrow_ids.contains(&e.escrow_id)) {
+        return Err(StorageError::InvalidBackup(format!(
+            "History record {} belongs to escrow {}, which is not in the archive",
+            event.sequence, event.escrow_id
+        )));
+    }
+    Ok(())
+}
+
+pub fn write_archive(path: &Path, contents: &BackupContents) -> Result<BackupSummary, StorageError> {
+    let raw = serde_json::to_string(contents)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+    let checksum = hex::encode(Sha256::digest(raw.as_bytes()));
+    let contents_raw = RawValue::from_string(raw)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+    let archive = BackupArchive {
+        format: BACKUP_FORMAT.to_string(),
+        format_version: BACKUP_FORMAT_VERSION,
+        created_at: Utc::now(),
+        checksum,
+        contents: contents_raw,
+    };
+    let bytes = serde_json::to_vec(&archive)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+    fs::write(path, bytes)
+        .map_err(|e| StorageError::Io(format!("Cannot write {}: {}", path.display(), e)))?;
+
+    Ok(contents.summary())
+}
+
+/// Loads an archive, checking its format, version and checksum.
+pub fn read_archive(path: &Path) -> Result<BackupContents, StorageError> {
+    let bytes = fs::read(path)
+        .map_err(|e| StorageError::Io(format!("Cannot read {}: {}", path.display(), e)))?;
+    let archive: BackupArchive = serde_json::from_slice(&bytes)
+        .map_err(|e| StorageError::InvalidBackup(format!("Not a backup archive: {}", e)))?;
+
+    if archive.format != BACKUP_FORMAT {
+        return Err(StorageError::InvalidBackup(format!(
+            "Unknown archive format: {}",
+            archive.format
+        )));
+    }
+    if archive.format_version > BACKUP_FORMAT_VERSION {
+        return Err(StorageError::InvalidBackup(format!(
+            "Archive version {} is newer than supported version {}",
+            archive.format_version, BACKUP_FORMAT_VERSION
+        )));
+    }
+
+    let raw = archive.contents.get();
+    let checksum = hex::encode(Sha256::digest(raw.as_bytes()));
+    if checksum != archive.checksum {
+        return Err(StorageError::InvalidBackup(
+            "Checksum mismatch; the archive is corrupt or was modified".to_string(),
+        ));
+    }
+
+    serde_json::from_str(raw).map_err(|e| StorageError::InvalidBackup(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escrow::events::EscrowEventKind;
+    use crate::escrow::EscrowService;
+    use crate::storage::{EventRepository, MemoryStorage, UserRepository};
+    use crate::test_support::{escrow, user};
+    use crate::types::UserType;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    /// A store holding one accepted escrow, its buyer and its history.
+    fn populated() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        let service = EscrowService::new(&storage);
+        let escrow = escrow();
+        let buyer = User { id: escrow.buyer_id, ..user(UserType::Buyer) };
+        storage.create_user(buyer).unwrap();
+        service.create(&escrow).unwrap();
+        service
+            .apply(escrow.id, |escrow| {
+                let accepted = EscrowEventKind::TermsAccepted { accepted_by: escrow.seller_id };
+                let event = EscrowEvent::new(escrow.id, accepted);
+                event.apply(escrow)?;
+                Ok(event)
+            })
+            .unwrap();
+        storage
+    }
+
+    fn archive_path() -> PathBuf {
+        std::env::temp_dir().join(format!("spaza-backup-{}.json", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn archive_restores_to_an_identical_store() {
+        let source = populated();
+        let path = archive_path();
+        write_archive(&path, &export(&source).unwrap()).unwrap();
+
+        let target = MemoryStorage::new();
+        let summary = restore(&target, &read_archive(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((summary.escrows, summary.users, summary.events), (1, 1, 2));
+        assert_eq!(target.list_all_events().unwrap()[1].hash, source.list_all_events().unwrap()[1].hash);
+        assert!(EscrowService::new(&target).verify().unwrap().is_intact());
+    }
+
+    #[test]
+    fn modified_archive_fails_the_checksum() {
+        let path = archive_path();
+        write_archive(&path, &export(&populated()).unwrap()).unwrap();
+        let tampered = fs::read_to_string(&path).unwrap().replace("\"ZAR\"", "\"USD\"");
+        fs::write(&path, tampered).unwrap();
+
+        let result = read_archive(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(StorageError::InvalidBackup(reason)) if reason.contains("Checksum")));
+    }
+
+    #[test]
+    fn restore_refuses_a_store_that_holds_data() {
+        let contents = export(&populated()).unwrap();
+
+        assert!(matches!(restore(&populated(), &contents), Err(StorageError::InvalidBackup(_))));
+    }
+
+    #[test]
+    fn inconsistent_archive_is_refused_before_anything_is_written() {
+        let contents = export(&populated()).unwrap();
+        let mut duplicate = contents.clone();
+        duplicate.escrows.push(duplicate.escrows[0].clone());
+        let mut gap = contents.clone();
+        gap.events.remove(0);
+        let mut orphan = contents.clone();
+        orphan.escrows.clear();
+
+        for broken in [duplicate, gap, orphan] {
+            let target = MemoryStorage::new();
+            assert!(matches!(restore(&target, &broken), Err(StorageError::InvalidBackup(_))));
+            assert!(check_empty(&target).is_ok());
+        }
+    }
+}
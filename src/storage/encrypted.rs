@@ -0,0 +1,302 @@
+use crate::escrow::events::EscrowEvent;
+use crate::storage::encryption::Keyring;
+use crate::storage::errors::StorageError;
+use crate::storage::query::{EscrowPage, EscrowQuery};
+use crate::storage::repository::{
+    EscrowRepository, EventRepository, Storage, TrustRepository, UserRepository,
+};
+use crate::trust::UserTrustProfile;
+use crate::types::{Escrow, User};
+use uuid::Uuid;
+
+/// Wraps a backend so personal data is encrypted before it is written and
+/// decrypted when it is read. Covers the escrow's release and milestone PIN
+/// hashes and TOTP secret and the user's phone number; everything else is
+/// passed through untouched.
+pub struct EncryptedStorage {
+    inner: Box<dyn Storage>,
+    keyring: Keyring,
+}
+
+/// Records rewritten by `reencrypt_all`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReencryptSummary {
+    pub escrows: usize,
+    pub users: usize,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Box<dyn Storage>, keyring: Keyring) -> Self {
+        Self { inner, keyring }
+    }
+
+    /// The underlying backend, holding ciphertext. Backups are taken from
+    /// here so personal data never leaves the store in the clear.
+    pub fn inner(&self) -> &dyn Storage {
+        self.inner.as_ref()
+    }
+
+    fn seal_escrow(&self, mut escrow: Escrow) -> Result<Escrow, StorageError> {
+        for value in escrow.secrets_mut() {
+            *value = self.keyring.encrypt(value)?;
+        }
+        Ok(escrow)
+    }
+
+    fn open_escrow(&self, mut escrow: Escrow) -> Result<Escrow, StorageError> {
+        for value in escrow.secrets_mut() {
+            *value = self.keyring.decrypt(value)?;
+        }
+        Ok(escrow)
+    }
+
+    fn seal_user(&self, mut user: User) -> Result<User, StorageError> {
+        user.phone_number = self.keyring.encrypt(&user.phone_number)?;
+        Ok(user)
+    }
+
+    fn open_user(&self, mut user: User) -> Result<User, StorageError> {
+        user.phone_number = self.keyring.decrypt(&user.phone_number)?;
+        Ok(user)
+    }
+}
+
+impl EscrowRepository for EncryptedStorage {
+    fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError> {
+        self.inner.create_escrow(self.seal_escrow(escrow)?)
+    }
+
+    fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError> {
+        self.inner
+            .get_escrow(id)?
+            .map(|escrow| self.open_escrow(escrow))
+            .transpose()
+    }
+
+    fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError> {
+        self.inner.update_escrow(self.seal_escrow(escrow)?, expected_version)
+    }
+
+    fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError> {
+        self.inner.commit_escrow(self.seal_escrow(escrow)?, expected_version, event)
+    }
+
+    fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError> {
+        self.inner
+            .list_escrows()?
+            .into_iter()
+            .map(|escrow| self.open_escrow(escrow))
+            .collect()
+    }
+
+    fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError> {
+        let page = self.inner.query_escrows(query)?;
+        Ok(EscrowPage {
+            escrows: page
+                .escrows
+                .into_iter()
+                .map(|escrow| self.open_escrow(escrow))
+                .collect::<Result<_, _>>()?,
+            total: page.total,
+        })
+    }
+}
+
+impl UserRepository for EncryptedStorage {
+    fn create_user(&self, user: User) -> Result<(), StorageError> {
+        self.inner.create_user(self.seal_user(user)?)
+    }
+
+    fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError> {
+        self.inner.get_user(id)?.map(|user| self.open_user(user)).transpose()
+    }
+
+    fn update_user(&self, user: User) -> Result<(), StorageError> {
+        self.inner.update_user(self.seal_user(user)?)
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, StorageError> {
+        self.inner
+            .list_users()?
+            .into_iter()
+            .map(|user| self.open_user(user))
+            .collect()
+    }
+}
+
+impl TrustRepository for EncryptedStorage {
+    fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError> {
+        self.inner.save_trust_profiles(profiles)
+    }
+
+    fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError> {
+        self.inner.list_trust_profiles()
+    }
+}
+
+// Events never carry the PIN or phone numbers (see `EscrowEvent::created`),
+// so the log is stored as-is and its hashes stay verifiable without a key.
+impl EventRepository for EncryptedStorage {
+    fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError> {
+        self.inner.append_event(event)
+    }
+
+    fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError> {
+        self.inner.last_event()
+    }
+
+    fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError> {
+        self.inner.list_events(escrow_id)
+    }
+
+    fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+        self.inner.list_all_events()
+    }
+}
+
+/// Rewrites every encrypted field in the raw `storage` that is not yet under
+/// `keyring`'s active key, including values stored before encryption was
+/// enabled. Escrows keep their version since nothing about their state
+/// changes.
+pub fn reencrypt_all(storage: &dyn Storage, keyring: &Keyring) -> Result<ReencryptSummary, StorageError> {
+    let mut summary = ReencryptSummary::default();
+
+    for mut escrow in storage.list_escrows()? {
+        let mut changed = false;
+        for value in escrow.secrets_mut() {
+            if !keyring.is_current(value) {
+                *value = keyring.encrypt(&keyring.decrypt(value)?)?;
+                changed = true;
+            }
+        }
+        if changed {
+            let version = escrow.version;
+            storage.update_escrow(escrow, version)?;
+            summary.escrows += 1;
+        }
+    }
+
+    for mut user in storage.list_users()? {
+        if keyring.is_current(&user.phone_number) {
+            continue;
+        }
+        user.phone_number = keyring.encrypt(&keyring.decrypt(&user.phone_number)?)?;
+        storage.update_user(user)?;
+        summary.users += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_support::{escrow, user};
+    use crate::types::UserType;
+    use std::sync::Arc;
+
+    /// Lets a test keep a handle on the backend it hands to
+    /// `EncryptedStorage`, to look at what was actually written.
+    struct Shared(Arc<MemoryStorage>);
+
+    impl EscrowRepository for Shared {
+        fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError> {
+            self.0.create_escrow(escrow)
+        }
+        fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError> {
+            self.0.get_escrow(id)
+        }
+        fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError> {
+            self.0.update_escrow(escrow, expected_version)
+        }
+        fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError> {
+            self.0.commit_escrow(escrow, expected_version, event)
+        }
+        fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError> {
+            self.0.list_escrows()
+        }
+        fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError> {
+            self.0.query_escrows(query)
+        }
+    }
+
+    impl UserRepository for Shared {
+        fn create_user(&self, user: User) -> Result<(), StorageError> {
+            self.0.create_user(user)
+        }
+        fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError> {
+            self.0.get_user(id)
+        }
+        fn update_user(&self, user: User) -> Result<(), StorageError> {
+            self.0.update_user(user)
+        }
+        fn list_users(&self) -> Result<Vec<User>, StorageError> {
+            self.0.list_users()
+        }
+    }
+
+    impl TrustRepository for Shared {
+        fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError> {
+            self.0.save_trust_profiles(profiles)
+        }
+        fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError> {
+            self.0.list_trust_profiles()
+        }
+    }
+
+    impl EventRepository for Shared {
+        fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError> {
+            self.0.append_event(event)
+        }
+        fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError> {
+            self.0.last_event()
+        }
+        fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError> {
+            self.0.list_events(escrow_id)
+        }
+        fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+            self.0.list_all_events()
+        }
+    }
+
+    fn keyring(id: &str) -> Keyring {
+        Keyring::new(id, &[(id.to_string(), Keyring::generate_key())]).unwrap()
+    }
+
+    #[test]
+    fn secrets_are_ciphertext_at_rest_and_clear_through_the_wrapper() {
+        let raw = Arc::new(MemoryStorage::new());
+        let storage = EncryptedStorage::new(Box::new(Shared(raw.clone())), keyring("k1"));
+        let escrow = escrow();
+        let user = User { id: escrow.buyer_id, ..user(UserType::Buyer) };
+        storage.create_escrow(escrow.clone()).unwrap();
+        storage.create_user(user).unwrap();
+
+        let at_rest = raw.get_escrow(escrow.id).unwrap().unwrap();
+        assert!(at_rest.release_pin_hash.as_deref().unwrap().starts_with("enc:v1:k1:"));
+        assert!(raw.get_user(escrow.buyer_id).unwrap().unwrap().phone_number.starts_with("enc:v1:"));
+
+        let opened = storage.get_escrow(escrow.id).unwrap().unwrap();
+        assert_eq!(opened.release_pin_hash, escrow.release_pin_hash);
+        assert_eq!(storage.get_user(escrow.buyer_id).unwrap().unwrap().phone_number, "+27820000000");
+    }
+
+    #[test]
+    fn reencrypt_moves_everything_to_the_active_key() {
+        let raw = MemoryStorage::new();
+        let escrow = escrow();
+        raw.create_escrow(escrow.clone()).unwrap();
+        raw.create_user(User { id: escrow.buyer_id, ..user(UserType::Buyer) }).unwrap();
+        let ring = keyring("k2");
+
+        let summary = reencrypt_all(&raw, &ring).unwrap();
+        assert_eq!((summary.escrows, summary.users), (1, 1));
+        let stored = raw.get_escrow(escrow.id).unwrap().unwrap();
+        assert!(ring.is_current(stored.release_pin_hash.as_deref().unwrap()));
+        assert_eq!(stored.version, escrow.version);
+
+        let again = reencrypt_all(&raw, &ring).unwrap();
+        assert_eq!((again.escrows, again.users), (0, 0));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::storage::errors::StorageError;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::collections::HashMap;
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM keys by id. Values are always encrypted with the active key;
+/// any key in the ring can decrypt, which is what makes rotation possible.
+#[derive(Clone)]
+pub struct Keyring {
+    active_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    /// `keys` are (id, base64-encoded 32-byte key) pairs.
+    pub fn new(active_id: &str, keys: &[(String, String)]) -> Result<Self, StorageError> {
+        let mut ring = HashMap::new();
+        for (id, encoded) in keys {
+            let bytes = BASE64
+                .decode(encoded)
+                .map_err(|e| StorageError::Encryption(format!("Key {} is not base64: {}", id, e)))?;
+            if bytes.len() != 32 {
+                return Err(StorageError::Encryption(format!(
+                    "Key {} must be 32 bytes, got {}",
+                    id,
+                    bytes.len()
+                )));
+            }
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes));
+            ring.insert(id.clone(), cipher);
+        }
+
+        if !ring.contains_key(active_id) {
+            return Err(StorageError::Encryption(format!(
+                "Active key {} is not configured",
+                active_id
+            )));
+        }
+
+        Ok(Self {
+            active_id: active_id.to_string(),
+            keys: ring,
+        })
+    }
+
+    /// A fresh random key, base64-encoded for the config file.
+    pub fn generate_key() -> String {
+        BASE64.encode(Aes256Gcm::generate_key(OsRng))
+    }
+
+    pub fn active_id(&self) -> &str {
+        &self.active_id
+    }
+
+    /// `enc:v1:<key id>:<base64 nonce || ciphertext>`
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, StorageError> {
+        let cipher = &self.keys[&self.active_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| StorageError::Encryption("Encryption failed".to_string()))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}:{}", PREFIX, self.active_id, BASE64.encode(payload)))
+    }
+
+    /// Decrypts a value written by `encrypt`. Values without the `enc:`
+    /// prefix predate encryption and are returned unchanged.
+    pub fn decrypt(&self, value: &str) -> Result<String, StorageError> {
+        let Some(rest) = value.strip_prefix(PREFIX) else {
+            return Ok(value.to_string());
+        };
+
+        let (key_id, encoded) = rest
+            .split_once(':')
+            .ok_or_else(|| StorageError::Encryption("Malformed encrypted value".to_string()))?;
+        let cipher = self.keys.get(key_id).ok_or_else(|| {
+            StorageError::Encryption(format!("Value was encrypted with unknown key {}", key_id))
+        })?;
+
+        let payload = BASE64
+            .decode(encoded)
+            .map_err(|_| StorageError::Encryption("Malformed encrypted value".to_string()))?;
+        if payload.len() < NONCE_LEN {
+            return Err(StorageError::Encryption("Malformed encrypted value".to_string()));
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| StorageError::Encryption(format!("Cannot decrypt value with key {}", key_id)))?;
+        String::from_utf8(plaintext)
+            .map_err(|_| StorageError::Encryption("Decrypted value is not UTF-8".to_string()))
+    }
+
+    /// True if `value` is already encrypted with the active key.
+    pub fn is_current(&self, value: &str) -> bool {
+        value
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .is_some_and(|(key_id, _)| key_id == self.active_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(active: &str, ids: &[&str]) -> Keyring {
+        let keys: Vec<(String, String)> = ids.iter().map(|id| (id.to_string(), Keyring::generate_key())).collect();
+        Keyring::new(active, &keys).unwrap()
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_round_trips() {
+        let ring = keyring("k1", &["k1"]);
+        let sealed = ring.encrypt("+27820000000").unwrap();
+
+        assert!(sealed.starts_with("enc:v1:k1:"));
+        assert!(!sealed.contains("27820000000"));
+        assert_eq!(ring.decrypt(&sealed).unwrap(), "+27820000000");
+    }
+
+    #[test]
+    fn same_value_encrypts_differently_each_time() {
+        let ring = keyring("k1", &["k1"]);
+
+        assert_ne!(ring.encrypt("1234").unwrap(), ring.encrypt("1234").unwrap());
+    }
+
+    #[test]
+    fn plaintext_from_before_encryption_passes_through() {
+        assert_eq!(keyring("k1", &["k1"]).decrypt("+27820000000").unwrap(), "+27820000000");
+    }
+
+    #[test]
+    fn old_key_still_decrypts_after_rotation() {
+        let keys = vec![("old".to_string(), Keyring::generate_key()), ("new".to_string(), Keyring::generate_key())];
+        let before = Keyring::new("old", &keys).unwrap();
+        let after = Keyring::new("new", &keys).unwrap();
+        let sealed = before.encrypt("secret").unwrap();
+
+        assert_eq!(after.decrypt(&sealed).unwrap(), "secret");
+        assert!(!after.is_current(&sealed));
+        assert!(after.is_current(&after.encrypt("secret").unwrap()));
+    }
+
+    #[test]
+    fn tampered_or_unknown_key_values_are_refused() {
+        let ring = keyring("k1", &["k1"]);
+        let sealed = ring.encrypt("secret").unwrap();
+        let (head, body) = sealed.rsplit_once(':').unwrap();
+        let mut payload = BASE64.decode(body).unwrap();
+        *payload.last_mut().unwrap() ^= 1;
+        let tampered = format!("{}:{}", head, BASE64.encode(payload));
+
+        assert!(matches!(ring.decrypt(&tampered), Err(StorageError::Encryption(_))));
+        assert!(matches!(
+            keyring("k2", &["k2"]).decrypt(&sealed),
+            Err(StorageError::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes_and_include_the_active_one() {
+        let short = vec![("k1".to_string(), BASE64.encode([0u8; 16]))];
+        let good = vec![("k1".to_string(), Keyring::generate_key())];
+
+        assert!(Keyring::new("k1", &short).is_err());
+        assert!(Keyring::new("k2", &good).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use crate::escrow::errors::EscrowError;
+use std::sync::PoisonError;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Record already exists: {0}")]
+    AlreadyExists(Uuid),
+
+    #[error("Record not found: {0}")]
+    NotFound(Uuid),
+
+    #[error("Version conflict on {id}: expected {expected}, found {found}")]
+    VersionConflict { id: Uuid, expected: u64, found: u64 },
+
+    #[error("Event sequence conflict: expected {expected}, got {found}")]
+    SequenceConflict { expected: u64, found: u64 },
+
+    #[error("Lock error: {0}")]
+    Lock(String),
+
+    #[error("I/O error: {0}")]
+    Io(String),
+
+    #[error("Serialization error: {0}")]
+    Serialization(String),
+
+    #[error("Database error: {0}")]
+    Database(String),
+
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
+
+impl<T> From<PoisonError<T>> for StorageError {
+    fn from(e: PoisonError<T>) -> Self {
+        StorageError::Lock(e.to_string())
+    }
+}
+
+impl From<StorageError> for EscrowError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::VersionConflict { id, .. } => EscrowError::ConcurrentModification(id),
+            other => EscrowError::StorageError(other.to_string()),
+        }
+    }
+}
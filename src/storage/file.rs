@@ -0,0 +1,443 @@
+use crate::escrow::events::EscrowEvent;
+use crate::storage::errors::StorageError;
+use crate::storage::query::{EscrowPage, EscrowQuery};
+use crate::storage::repository::{
+    check_next_sequence, check_version, EscrowRepository, EventRepository, TrustRepository,
+    UserRepository,
+};
+use crate::trust::UserTrustProfile;
+use crate::types::{Escrow, User};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+const ESCROWS_FILE: &str = "escrows.json";
+const USERS_FILE: &str = "users.json";
+const EVENTS_FILE: &str = "events.jsonl";
+const TRUST_FILE: &str = "trust_profiles.json";
+const LOCK_FILE: &str = ".lock";
+
+/// JSON-file backed storage so state survives between CLI invocations.
+///
+/// Each collection lives in its own file inside `data_dir` and is rewritten
+/// atomically (write to a temp file, then rename) on every change. Escrow
+/// events are kept as JSON lines in a separate file that is only appended to.
+/// Every read-check-write holds an exclusive lock on a `.lock` file in
+/// `data_dir`, so separate CLI processes cannot interleave their updates.
+pub struct FileStorage {
+    data_dir: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl FileStorage {
+    pub fn open(data_dir: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let data_dir = data_dir.into();
+        fs::create_dir_all(&data_dir).map_err(|e| {
+            StorageError::Io(format!("Cannot create data dir {}: {}", data_dir.display(), e))
+        })?;
+
+        Ok(Self {
+            data_dir,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Serialises writers: the mutex between threads of this process, the
+    /// file lock between processes. Both are released when the guard drops.
+    fn lock(&self) -> Result<WriteGuard<'_>, StorageError> {
+        let thread = self.write_lock.lock()?;
+
+        let path = self.data_dir.join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| StorageError::Io(format!("Cannot open {}: {}", path.display(), e)))?;
+        file.lock()
+            .map_err(|e| StorageError::Io(format!("Cannot lock {}: {}", path.display(), e)))?;
+
+        Ok(WriteGuard { _thread: thread, _file: file })
+    }
+
+    fn modify<T, F>(&self, file: &str, f: F) -> Result<(), StorageError>
+    where
+        T: DeserializeOwned + Serialize + Default,
+        F: FnOnce(&mut T) -> Result<(), StorageError>,
+    {
+        let _guard = self.lock()?;
+
+        let mut collection = self.load(file)?;
+        f(&mut collection)?;
+        self.save(file, &collection)
+    }
+
+    fn load<T: DeserializeOwned + Default>(&self, file: &str) -> Result<T, StorageError> {
+        let path = self.data_dir.join(file);
+        if !path.exists() {
+            return Ok(T::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| StorageError::Io(format!("Cannot read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&contents).map_err(|e| {
+            StorageError::Serialization(format!("Corrupt data in {}: {}", path.display(), e))
+        })
+    }
+
+    fn load_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+        let path = self.data_dir.join(EVENTS_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| StorageError::Io(format!("Cannot read {}: {}", path.display(), e)))?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    StorageError::Serialization(format!(
+                        "Corrupt event on line {} of {}: {}",
+                        i + 1,
+                        path.display(),
+                        e
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    fn save<T: Serialize>(&self, file: &str, collection: &T) -> Result<(), StorageError> {
+        let tmp_path = self.stage(file, collection)?;
+        self.swap_in(file, &tmp_path)
+    }
+
+    /// Writes `collection` next to `file` without replacing it yet.
+    fn stage<T: Serialize>(&self, file: &str, collection: &T) -> Result<PathBuf, StorageError> {
+        let tmp_path = self.data_dir.join(file).with_extension("json.tmp");
+
+        let contents = serde_json::to_string_pretty(collection)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        fs::write(&tmp_path, contents)
+            .map_err(|e| StorageError::Io(format!("Cannot write {}: {}", tmp_path.display(), e)))?;
+        Ok(tmp_path)
+    }
+
+    fn swap_in(&self, file: &str, tmp_path: &Path) -> Result<(), StorageError> {
+        let path = self.data_dir.join(file);
+        fs::rename(tmp_path, &path)
+            .map_err(|e| StorageError::Io(format!("Cannot replace {}: {}", path.display(), e)))
+    }
+
+    fn write_event(&self, event: &EscrowEvent) -> Result<(), StorageError> {
+        let mut line = serde_json::to_string(event)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        line.push('\n');
+
+        let path = self.data_dir.join(EVENTS_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| StorageError::Io(format!("Cannot open {}: {}", path.display(), e)))?;
+        file.write_all(line.as_bytes())
+            .map_err(|e| StorageError::Io(format!("Cannot append to {}: {}", path.display(), e)))
+    }
+}
+
+struct WriteGuard<'a> {
+    _thread: MutexGuard<'a, ()>,
+    _file: File,
+}
+
+impl EscrowRepository for FileStorage {
+    fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError> {
+        self.modify(ESCROWS_FILE, |escrows: &mut HashMap<Uuid, Escrow>| {
+            if escrows.contains_key(&escrow.id) {
+                return Err(StorageError::AlreadyExists(escrow.id));
+            }
+            escrows.insert(escrow.id, escrow);
+            Ok(())
+        })
+    }
+
+    fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError> {
+        let escrows: HashMap<Uuid, Escrow> = self.load(ESCROWS_FILE)?;
+        Ok(escrows.get(&id).cloned())
+    }
+
+    fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError> {
+        self.modify(ESCROWS_FILE, |escrows: &mut HashMap<Uuid, Escrow>| {
+            let stored = escrows.get(&escrow.id).ok_or(StorageError::NotFound(escrow.id))?;
+            check_version(stored, expected_version)?;
+            escrows.insert(escrow.id, escrow);
+            Ok(())
+        })
+    }
+
+    fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError> {
+        let _guard = self.lock()?;
+
+        let mut escrows: HashMap<Uuid, Escrow> = self.load(ESCROWS_FILE)?;
+        let stored = escrows.get(&escrow.id).ok_or(StorageError::NotFound(escrow.id))?;
+        check_version(stored, expected_version)?;
+        check_next_sequence(self.load_events()?.last(), &event)?;
+
+        // The new escrows file is staged before the event is appended and
+        // only swapped in after, so a failed append leaves the escrow as it
+        // was.
+        escrows.insert(escrow.id, escrow);
+        let tmp_path = self.stage(ESCROWS_FILE, &escrows)?;
+        if let Err(e) = self.write_event(&event) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        self.swap_in(ESCROWS_FILE, &tmp_path)
+    }
+
+    fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError> {
+        let escrows: HashMap<Uuid, Escrow> = self.load(ESCROWS_FILE)?;
+        Ok(escrows.into_values().collect())
+    }
+
+    fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError> {
+        let escrows: HashMap<Uuid, Escrow> = self.load(ESCROWS_FILE)?;
+        Ok(query.apply(escrows.into_values()))
+    }
+}
+
+impl UserRepository for FileStorage {
+    fn create_user(&self, user: User) -> Result<(), StorageError> {
+        self.modify(USERS_FILE, |users: &mut HashMap<Uuid, User>| {
+            if users.contains_key(&user.id) {
+                return Err(StorageError::AlreadyExists(user.id));
+            }
+            users.insert(user.id, user);
+            Ok(())
+        })
+    }
+
+    fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError> {
+        let users: HashMap<Uuid, User> = self.load(USERS_FILE)?;
+        Ok(users.get(&id).cloned())
+    }
+
+    fn update_user(&self, user: User) -> Result<(), StorageError> {
+        self.modify(USERS_FILE, |users: &mut HashMap<Uuid, User>| {
+            if !users.contains_key(&user.id) {
+                return Err(StorageError::NotFound(user.id));
+            }
+            users.insert(user.id, user);
+            Ok(())
+        })
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, StorageError> {
+        let users: HashMap<Uuid, User> = self.load(USERS_FILE)?;
+        Ok(users.into_values().collect())
+    }
+}
+
+impl TrustRepository for FileStorage {
+    fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError> {
+        self.modify(TRUST_FILE, |stored: &mut HashMap<Uuid, UserTrustProfile>| {
+            for profile in profiles {
+                stored.insert(profile.user_id, profile.clone());
+            }
+            Ok(())
+        })
+    }
+
+    fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError> {
+        let stored: HashMap<Uuid, UserTrustProfile> = self.load(TRUST_FILE)?;
+        Ok(stored.into_values().collect())
+    }
+}
+
+impl EventRepository for FileStorage {
+    fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError> {
+        let _guard = self.lock()?;
+
+        let events = self.load_events()?;
+        check_next_sequence(events.last(), &event)?;
+        self.write_event(&event)
+    }
+
+    fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError> {
+        Ok(self.load_events()?.pop())
+    }
+
+    fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError> {
+        Ok(self
+            .load_events()?
+            .into_iter()
+            .filter(|e| e.escrow_id == escrow_id)
+            .collect())
+    }
+
+    fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+        self.load_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{escrow, user, TempDir};
+    use crate::types::UserType;
+
+    #[test]
+    fn state_survives_reopening_the_data_dir() {
+        let dir = TempDir::new();
+        let escrow = escrow();
+        let user = user(UserType::Buyer);
+        {
+            let storage = FileStorage::open(dir.path()).unwrap();
+            storage.create_escrow(escrow.clone()).unwrap();
+            storage.create_user(user.clone()).unwrap();
+        }
+
+        let reopened = FileStorage::open(dir.path()).unwrap();
+        let stored = reopened.get_escrow(escrow.id).unwrap().unwrap();
+        assert_eq!(stored.amount, escrow.amount);
+        assert_eq!(stored.buyer_id, escrow.buyer_id);
+        assert_eq!(reopened.get_user(user.id).unwrap().unwrap().phone_number, "+27820000000");
+        assert_eq!(reopened.list_escrows().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn empty_data_dir_reads_as_empty() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+
+        assert!(storage.list_escrows().unwrap().is_empty());
+        assert!(storage.get_escrow(Uuid::new_v4()).unwrap().is_none());
+    }
+
+    #[test]
+    fn update_replaces_the_stored_escrow() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+        let mut escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        escrow.description = "Bread and milk".to_string();
+        escrow.version = 1;
+        storage.update_escrow(escrow.clone(), 0).unwrap();
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().description, "Bread and milk");
+        assert_eq!(storage.list_escrows().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn duplicate_escrow_is_refused() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        assert!(matches!(
+            storage.create_escrow(escrow.clone()),
+            Err(StorageError::AlreadyExists(id)) if id == escrow.id
+        ));
+    }
+
+    #[test]
+    fn corrupt_file_is_reported_not_treated_as_empty() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+        fs::write(dir.path().join(ESCROWS_FILE), "{ not json").unwrap();
+
+        assert!(matches!(storage.list_escrows(), Err(StorageError::Serialization(_))));
+    }
+
+    #[test]
+    fn stale_update_is_a_version_conflict() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        let mut newer = escrow.clone();
+        newer.version = 1;
+        storage.update_escrow(newer, 0).unwrap();
+
+        assert!(matches!(
+            storage.update_escrow(escrow.clone(), 0),
+            Err(StorageError::VersionConflict { expected: 0, found: 1, .. })
+        ));
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 1);
+    }
+
+    #[test]
+    fn commit_stores_neither_escrow_nor_event_when_the_append_fails() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+        let mut created = EscrowEvent::created(&escrow);
+        created.sequence = 1;
+        storage.append_event(created).unwrap();
+
+        let mut newer = escrow.clone();
+        newer.version = 1;
+        let mut event = EscrowEvent::created(&escrow);
+        event.sequence = 3;
+        assert!(matches!(
+            storage.commit_escrow(newer.clone(), 0, event),
+            Err(StorageError::SequenceConflict { expected: 2, found: 3 })
+        ));
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 0);
+        assert_eq!(storage.list_all_events().unwrap().len(), 1);
+
+        let mut event = EscrowEvent::created(&escrow);
+        event.sequence = 2;
+        storage.commit_escrow(newer, 0, event).unwrap();
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 1);
+        assert_eq!(storage.list_all_events().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn out_of_order_event_is_a_sequence_conflict() {
+        let dir = TempDir::new();
+        let storage = FileStorage::open(dir.path()).unwrap();
+        let mut event = EscrowEvent::created(&escrow());
+        event.sequence = 2;
+
+        assert!(matches!(
+            storage.append_event(event),
+            Err(StorageError::SequenceConflict { expected: 1, found: 2 })
+        ));
+    }
+
+    #[test]
+    fn separate_handles_on_one_dir_do_not_lose_writes() {
+        let dir = TempDir::new();
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let path = dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    let storage = FileStorage::open(path).unwrap();
+                    storage.create_user(user(UserType::Buyer)).unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.list_users().unwrap().len(), 8);
+    }
+}
@@ -1,63 +1,275 @@
+use crate::escrow::events::EscrowEvent;
+use crate::storage::errors::StorageError;
+use crate::storage::query::{EscrowPage, EscrowQuery};
+use crate::storage::repository::{
+    check_next_sequence, check_version, EscrowRepository, EventRepository, TrustRepository,
+    UserRepository,
+};
+use crate::trust::UserTrustProfile;
 use crate::types::{Escrow, User};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
 use uuid::Uuid;
 
 pub struct MemoryStorage {
     escrows: RwLock<HashMap<Uuid, Escrow>>,
+    // Escrow ids per buyer and per seller; parties never change after
+    // creation, so these only grow in `create_escrow`.
+    party_index: RwLock<PartyIndex>,
     users: RwLock<HashMap<Uuid, User>>,
+    events: RwLock<Vec<EscrowEvent>>,
+    trust_profiles: RwLock<HashMap<Uuid, UserTrustProfile>>,
+}
+
+#[derive(Default)]
+struct PartyIndex {
+    by_buyer: HashMap<Uuid, HashSet<Uuid>>,
+    by_seller: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemoryStorage {
     pub fn new() -> Self {
         Self {
             escrows: RwLock::new(HashMap::new()),
+            party_index: RwLock::new(PartyIndex::default()),
             users: RwLock::new(HashMap::new()),
+            events: RwLock::new(Vec::new()),
+            trust_profiles: RwLock::new(HashMap::new()),
         }
     }
-    
-    pub fn create_escrow(&self, escrow: Escrow) -> Result<(), String> {
-        let mut escrows = self.escrows.write()
-            .map_err(|e| format!("Lock error: {}", e))?;
+}
+
+impl EscrowRepository for MemoryStorage {
+    fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError> {
+        let mut escrows = self.escrows.write()?;
+        
+        if escrows.contains_key(&escrow.id) {
+            return Err(StorageError::AlreadyExists(escrow.id));
+        }
+        
+        let mut index = self.party_index.write()?;
+        index.by_buyer.entry(escrow.buyer_id).or_default().insert(escrow.id);
+        index.by_seller.entry(escrow.seller_id).or_default().insert(escrow.id);
         
         escrows.insert(escrow.id, escrow);
         Ok(())
     }
     
-    pub fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, String> {
-        let escrows = self.escrows.read()
-            .map_err(|e| format!("Lock error: {}", e))?;
+    fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError> {
+        let escrows = self.escrows.read()?;
         
         Ok(escrows.get(&id).cloned())
     }
     
-    pub fn update_escrow(&self, escrow: Escrow) -> Result<(), String> {
-        let mut escrows = self.escrows.write()
-            .map_err(|e| format!("Lock error: {}", e))?;
+    fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError> {
+        let mut escrows = self.escrows.write()?;
+        
+        let stored = escrows.get(&escrow.id).ok_or(StorageError::NotFound(escrow.id))?;
+        check_version(stored, expected_version)?;
+        
+        escrows.insert(escrow.id, escrow);
+        Ok(())
+    }
+    
+    fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError> {
+        let mut escrows = self.escrows.write()?;
+        let mut events = self.events.write()?;
+        
+        let stored = escrows.get(&escrow.id).ok_or(StorageError::NotFound(escrow.id))?;
+        check_version(stored, expected_version)?;
+        check_next_sequence(events.last(), &event)?;
         
         escrows.insert(escrow.id, escrow);
+        events.push(event);
         Ok(())
     }
     
-    pub fn get_user(&self, id: Uuid) -> Result<Option<User>, String> {
-        let users = self.users.read()
-            .map_err(|e| format!("Lock error: {}", e))?;
+    fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError> {
+        let escrows = self.escrows.read()?;
+        
+        Ok(escrows.values().cloned().collect())
+    }
+    
+    fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError> {
+        let escrows = self.escrows.read()?;
+        let index = self.party_index.read()?;
+        
+        let candidates = match (query.buyer_id, query.seller_id) {
+            (Some(buyer_id), _) => index.by_buyer.get(&buyer_id),
+            (None, Some(seller_id)) => index.by_seller.get(&seller_id),
+            (None, None) => return Ok(query.apply(escrows.values().cloned())),
+        };
+        
+        let selected = candidates
+            .into_iter()
+            .flatten()
+            .filter_map(|id| escrows.get(id).cloned());
+        Ok(query.apply(selected))
+    }
+}
+
+impl UserRepository for MemoryStorage {
+    fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError> {
+        let users = self.users.read()?;
         
         Ok(users.get(&id).cloned())
     }
     
-    pub fn create_user(&self, user: User) -> Result<(), String> {
-        let mut users = self.users.write()
-            .map_err(|e| format!("Lock error: {}", e))?;
+    fn create_user(&self, user: User) -> Result<(), StorageError> {
+        let mut users = self.users.write()?;
+        
+        if users.contains_key(&user.id) {
+            return Err(StorageError::AlreadyExists(user.id));
+        }
         
         users.insert(user.id, user);
         Ok(())
     }
     
-    pub fn list_escrows(&self) -> Result<Vec<Escrow>, String> {
-        let escrows = self.escrows.read()
-            .map_err(|e| format!("Lock error: {}", e))?;
+    fn update_user(&self, user: User) -> Result<(), StorageError> {
+        let mut users = self.users.write()?;
         
-        Ok(escrows.values().cloned().collect())
+        if !users.contains_key(&user.id) {
+            return Err(StorageError::NotFound(user.id));
+        }
+        
+        users.insert(user.id, user);
+        Ok(())
+    }
+    
+    fn list_users(&self) -> Result<Vec<User>, StorageError> {
+        let users = self.users.read()?;
+        
+        Ok(users.values().cloned().collect())
+    }
+}
+
+impl TrustRepository for MemoryStorage {
+    fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError> {
+        let mut stored = self.trust_profiles.write()?;
+        
+        for profile in profiles {
+            stored.insert(profile.user_id, profile.clone());
+        }
+        Ok(())
+    }
+    
+    fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError> {
+        let stored = self.trust_profiles.read()?;
+        
+        Ok(stored.values().cloned().collect())
+    }
+}
+
+impl EventRepository for MemoryStorage {
+    fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError> {
+        let mut events = self.events.write()?;
+        
+        check_next_sequence(events.last(), &event)?;
+        events.push(event);
+        Ok(())
+    }
+    
+    fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError> {
+        let events = self.events.read()?;
+        
+        Ok(events.last().cloned())
+    }
+    
+    fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError> {
+        let events = self.events.read()?;
+        
+        Ok(events.iter().filter(|e| e.escrow_id == escrow_id).cloned().collect())
+    }
+    
+    fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+        let events = self.events.read()?;
+        
+        Ok(events.clone())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use crate::test_support::{escrow, escrow_between, user};
+    use crate::types::UserType;
+
+    #[test]
+    fn works_behind_the_storage_trait() {
+        let storage: Box<dyn Storage> = Box::new(MemoryStorage::new());
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        let stored = storage.get_escrow(escrow.id).unwrap().unwrap();
+        assert_eq!(stored.id, escrow.id);
+        assert_eq!(storage.list_escrows().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn records_are_created_once() {
+        let storage = MemoryStorage::new();
+        let escrow = escrow();
+        let user = user(UserType::Seller);
+        storage.create_escrow(escrow.clone()).unwrap();
+        storage.create_user(user.clone()).unwrap();
+
+        assert!(matches!(storage.create_escrow(escrow), Err(StorageError::AlreadyExists(_))));
+        assert!(matches!(storage.create_user(user), Err(StorageError::AlreadyExists(_))));
+    }
+
+    #[test]
+    fn out_of_order_event_is_a_sequence_conflict() {
+        let storage = MemoryStorage::new();
+        let escrow = escrow();
+        let mut event = EscrowEvent::created(&escrow);
+        event.sequence = 2;
+
+        assert!(matches!(
+            storage.append_event(event),
+            Err(StorageError::SequenceConflict { expected: 1, found: 2 })
+        ));
+        assert!(storage.last_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn stale_update_is_a_version_conflict() {
+        let storage = MemoryStorage::new();
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        let mut newer = escrow.clone();
+        newer.version = 1;
+        storage.update_escrow(newer, 0).unwrap();
+
+        assert!(matches!(
+            storage.update_escrow(escrow.clone(), 0),
+            Err(StorageError::VersionConflict { expected: 0, found: 1, .. })
+        ));
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 1);
+    }
+
+    #[test]
+    fn party_index_only_returns_that_partys_escrows() {
+        let storage = MemoryStorage::new();
+        let buyer_id = Uuid::new_v4();
+        let mine = escrow_between(buyer_id, Uuid::new_v4());
+        storage.create_escrow(mine.clone()).unwrap();
+        storage.create_escrow(escrow()).unwrap();
+
+        let query = EscrowQuery { buyer_id: Some(buyer_id), ..EscrowQuery::new() };
+        let page = storage.query_escrows(&query).unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.escrows[0].id, mine.id);
+
+        let by_seller = EscrowQuery { seller_id: Some(mine.seller_id), ..EscrowQuery::new() };
+        assert_eq!(storage.query_escrows(&by_seller).unwrap().escrows[0].id, mine.id);
+    }
+}
@@ -0,0 +1,360 @@
+use rusqlite::{params, Connection};
+
+/// A single forward-only schema change. Versions must be strictly increasing
+/// and a migration must never be edited once released; add a new one instead.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create escrows and users",
+        sql: "
+            CREATE TABLE escrows (
+                id TEXT PRIMARY KEY,
+                amount TEXT NOT NULL,
+                currency TEXT NOT NULL,
+                buyer_id TEXT NOT NULL,
+                seller_id TEXT NOT NULL,
+                description TEXT NOT NULL,
+                state TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL,
+                funded_at TEXT,
+                completed_at TEXT,
+                release_pin TEXT,
+                arbitrators TEXT NOT NULL
+            );
+
+            CREATE TABLE users (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                phone_number TEXT NOT NULL,
+                user_type TEXT NOT NULL,
+                trust_score TEXT NOT NULL,
+                total_transactions INTEGER NOT NULL,
+                successful_transactions INTEGER NOT NULL,
+                dispute_rate TEXT NOT NULL,
+                trust_updated_at TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+        ",
+    },
+    Migration {
+        version: 2,
+        description: "create dispute resolutions and votes",
+        sql: "
+            CREATE TABLE dispute_resolutions (
+                escrow_id TEXT PRIMARY KEY REFERENCES escrows(id) ON DELETE CASCADE,
+                raised_by TEXT NOT NULL,
+                raised_at TEXT NOT NULL,
+                resolved_at TEXT,
+                decision TEXT
+            );
+
+            CREATE TABLE votes (
+                escrow_id TEXT NOT NULL REFERENCES escrows(id) ON DELETE CASCADE,
+                arbitrator_id TEXT NOT NULL,
+                vote INTEGER NOT NULL,
+                voted_at TEXT NOT NULL,
+                PRIMARY KEY (escrow_id, arbitrator_id)
+            );
+        ",
+    },
+    Migration {
+        version: 3,
+        description: "create append-only escrow event log",
+        sql: "
+            CREATE TABLE escrow_events (
+                sequence INTEGER PRIMARY KEY,
+                escrow_id TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                actor TEXT,
+                occurred_at TEXT NOT NULL,
+                payload TEXT NOT NULL
+            );
+
+            CREATE INDEX idx_escrow_events_escrow ON escrow_events (escrow_id, sequence);
+
+            CREATE TRIGGER escrow_events_no_update BEFORE UPDATE ON escrow_events
+            BEGIN
+                SELECT RAISE(ABORT, 'escrow_events is append-only');
+            END;
+
+            CREATE TRIGGER escrow_events_no_delete BEFORE DELETE ON escrow_events
+            BEGIN
+                SELECT RAISE(ABORT, 'escrow_events is append-only');
+            END;
+        ",
+    },
+    Migration {
+        version: 4,
+        description: "add escrow version for optimistic concurrency",
+        sql: "ALTER TABLE escrows ADD COLUMN version INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        version: 5,
+        description: "index escrows by party, state and dates",
+        sql: "
+            CREATE INDEX idx_escrows_buyer ON escrows (buyer_id, state, created_at);
+            CREATE INDEX idx_escrows_seller ON escrows (seller_id, state, created_at);
+            CREATE INDEX idx_escrows_state ON escrows (state, created_at);
+            CREATE INDEX idx_escrows_created ON escrows (created_at);
+            CREATE INDEX idx_escrows_expires ON escrows (expires_at);
+        ",
+    },
+    Migration {
+        version: 6,
+        description: "create trust profiles",
+        sql: "
+            CREATE TABLE trust_profiles (
+                user_id TEXT PRIMARY KEY,
+                trust_score REAL NOT NULL,
+                total_transactions INTEGER NOT NULL,
+                successful_transactions INTEGER NOT NULL,
+                disputed_transactions INTEGER NOT NULL,
+                total_amount_transacted TEXT NOT NULL,
+                last_active TEXT NOT NULL,
+                join_date TEXT NOT NULL
+            );
+        ",
+    },
+    Migration {
+        version: 7,
+        description: "track failed release PIN attempts",
+        sql: "
+            ALTER TABLE escrows ADD COLUMN failed_pin_attempts INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE escrows ADD COLUMN pin_lockouts INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE escrows ADD COLUMN pin_locked_until TEXT;
+        ",
+    },
+    Migration {
+        version: 8,
+        description: "track release PIN reissues",
+        sql: "
+            ALTER TABLE escrows ADD COLUMN pin_reissues INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE escrows ADD COLUMN pin_last_reissued_at TEXT;
+        ",
+    },
+    Migration {
+        version: 9,
+        description: "add TOTP release mode",
+        sql: "
+            ALTER TABLE escrows ADD COLUMN release_mode TEXT NOT NULL DEFAULT 'Pin';
+            ALTER TABLE escrows ADD COLUMN totp_secret TEXT;
+        ",
+    },
+    Migration {
+        version: 10,
+        description: "add user public keys",
+        sql: "ALTER TABLE users ADD COLUMN public_key TEXT;",
+    },
+    Migration {
+        version: 11,
+        description: "add assigned drivers and delivery handovers",
+        sql: "
+            ALTER TABLE escrows ADD COLUMN driver_id TEXT;
+            ALTER TABLE escrows ADD COLUMN handover_by TEXT;
+            ALTER TABLE escrows ADD COLUMN handover_at TEXT;
+            ALTER TABLE escrows ADD COLUMN handover_latitude REAL;
+            ALTER TABLE escrows ADD COLUMN handover_longitude REAL;
+        ",
+    },
+    Migration {
+        version: 12,
+        description: "create milestones",
+        sql: "
+            CREATE TABLE milestones (
+                escrow_id TEXT NOT NULL REFERENCES escrows(id) ON DELETE CASCADE,
+                number INTEGER NOT NULL,
+                description TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                pin_hash TEXT,
+                released_by TEXT,
+                released_at TEXT,
+                latitude REAL,
+                longitude REAL,
+                PRIMARY KEY (escrow_id, number)
+            );
+        ",
+    },
+    Migration {
+        version: 13,
+        description: "create line items and settlements",
+        sql: "
+            CREATE TABLE line_items (
+                escrow_id TEXT NOT NULL REFERENCES escrows(id) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                sku TEXT NOT NULL,
+                quantity INTEGER NOT NULL,
+                unit_price TEXT NOT NULL,
+                received INTEGER,
+                PRIMARY KEY (escrow_id, position)
+            );
+
+            ALTER TABLE escrows ADD COLUMN settlement_to_seller TEXT;
+            ALTER TABLE escrows ADD COLUMN settlement_to_buyer TEXT;
+        ",
+    },
+    Migration {
+        version: 14,
+        description: "add split shares to votes",
+        sql: "ALTER TABLE votes ADD COLUMN seller_percent TEXT;",
+    },
+    Migration {
+        version: 15,
+        description: "create dispute evidence",
+        sql: "
+            ALTER TABLE dispute_resolutions ADD COLUMN evidence_deadline TEXT;
+
+            CREATE TABLE evidence (
+                escrow_id TEXT NOT NULL REFERENCES escrows(id) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                submitted_by TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                content TEXT NOT NULL,
+                submitted_at TEXT NOT NULL,
+                PRIMARY KEY (escrow_id, position)
+            );
+        ",
+    },
+    Migration {
+        version: 16,
+        description: "add dispute deadlines and escalation",
+        sql: "
+            ALTER TABLE dispute_resolutions ADD COLUMN voting_deadline TEXT;
+            ALTER TABLE dispute_resolutions ADD COLUMN replaced TEXT NOT NULL DEFAULT '[]';
+            ALTER TABLE dispute_resolutions ADD COLUMN replacement_rounds INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE dispute_resolutions ADD COLUMN escalated_at TEXT;
+            ALTER TABLE dispute_resolutions ADD COLUMN escalation_deadline TEXT;
+        ",
+    },
+    Migration {
+        version: 17,
+        description: "add dispute appeals",
+        sql: "
+            ALTER TABLE dispute_resolutions ADD COLUMN appeal_rules TEXT;
+            ALTER TABLE dispute_resolutions ADD COLUMN appeal_deadline TEXT;
+            ALTER TABLE dispute_resolutions ADD COLUMN appeals TEXT NOT NULL DEFAULT '[]';
+        ",
+    },
+    Migration {
+        version: 18,
+        description: "store timestamps written before nanosecond precision at nanoseconds",
+        // Only columns that existed while timestamps were written at
+        // microseconds need widening. The event log keeps its text as
+        // written: it is append-only and events are read from their payload.
+        sql: "
+            UPDATE escrows SET created_at = substr(created_at, 1, 26) || '000Z' WHERE created_at GLOB '????-??-??T??:??:??.??????Z';
+            UPDATE escrows SET expires_at = substr(expires_at, 1, 26) || '000Z' WHERE expires_at GLOB '????-??-??T??:??:??.??????Z';
+            UPDATE escrows SET funded_at = substr(funded_at, 1, 26) || '000Z' WHERE funded_at GLOB '????-??-??T??:??:??.??????Z';
+            UPDATE escrows SET completed_at = substr(completed_at, 1, 26) || '000Z' WHERE completed_at GLOB '????-??-??T??:??:??.??????Z';
+
+            UPDATE users SET trust_updated_at = substr(trust_updated_at, 1, 26) || '000Z' WHERE trust_updated_at GLOB '????-??-??T??:??:??.??????Z';
+            UPDATE users SET created_at = substr(created_at, 1, 26) || '000Z' WHERE created_at GLOB '????-??-??T??:??:??.??????Z';
+
+            UPDATE dispute_resolutions SET raised_at = substr(raised_at, 1, 26) || '000Z' WHERE raised_at GLOB '????-??-??T??:??:??.??????Z';
+            UPDATE dispute_resolutions SET resolved_at = substr(resolved_at, 1, 26) || '000Z' WHERE resolved_at GLOB '????-??-??T??:??:??.??????Z';
+
+            UPDATE votes SET voted_at = substr(voted_at, 1, 26) || '000Z' WHERE voted_at GLOB '????-??-??T??:??:??.??????Z';
+        ",
+    },
+    Migration {
+        version: 19,
+        description: "remember the last accepted TOTP step",
+        sql: "ALTER TABLE escrows ADD COLUMN totp_last_step INTEGER;",
+    },
+];
+
+/// Applies every migration newer than the database's recorded version, each
+/// in its own transaction, and returns the resulting schema version.
+pub fn run(conn: &mut Connection) -> rusqlite::Result<u32> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        );",
+    )?;
+
+    let applied = current_version(conn)?;
+    let mut current = applied;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?1, ?2, ?3)",
+            params![
+                migration.version,
+                migration.description,
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )?;
+        tx.commit()?;
+
+        log::info!("Applied migration {}: {}", migration.version, migration.description);
+        current = migration.version;
+    }
+
+    Ok(current)
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        [],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_strictly_increase() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+    }
+
+    #[test]
+    fn run_brings_a_new_database_up_to_date_and_is_idempotent() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let latest = MIGRATIONS.last().unwrap().version;
+
+        assert_eq!(run(&mut conn).unwrap(), latest);
+        assert_eq!(run(&mut conn).unwrap(), latest);
+        assert_eq!(current_version(&conn).unwrap(), latest);
+
+        let applied: u32 = conn
+            .query_row("SELECT COUNT(*) FROM schema_migrations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(applied as usize, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn microsecond_timestamps_are_widened_to_nanoseconds() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO users (id, name, phone_number, user_type, trust_score, total_transactions,
+                successful_transactions, dispute_rate, trust_updated_at, created_at)
+             VALUES ('u', 'Thandi', '+27820000000', 'Buyer', '50', 0, 0, '0',
+                '2026-01-02T03:04:05.123456Z', '2026-01-02T03:04:05.123456789Z')",
+            [],
+        )
+        .unwrap();
+
+        let widen = MIGRATIONS.iter().find(|m| m.version == 18).unwrap();
+        conn.execute_batch(widen.sql).unwrap();
+
+        let (updated, created): (String, String) = conn
+            .query_row("SELECT trust_updated_at, created_at FROM users", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(updated, "2026-01-02T03:04:05.123456000Z");
+        assert_eq!(created, "2026-01-02T03:04:05.123456789Z");
+    }
+}
@@ -1,2 +1,21 @@
+pub mod backup;
+pub mod encrypted;
+pub mod encryption;
+pub mod errors;
+pub mod file;
 pub mod memory;
-pub use memory::MemoryStorage;
\ No newline at end of file
+pub mod migrations;
+pub mod query;
+pub mod repository;
+pub mod sqlite;
+
+pub use encrypted::{reencrypt_all, EncryptedStorage, ReencryptSummary};
+pub use encryption::Keyring;
+pub use errors::StorageError;
+pub use file::FileStorage;
+pub use memory::MemoryStorage;
+pub use query::{EscrowPage, EscrowQuery, EscrowSortField, SortOrder};
+pub use repository::{
+    EscrowRepository, EventRepository, Storage, TrustRepository, UserRepository,
+};
+pub use sqlite::SqliteStorage;
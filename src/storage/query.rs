@@ -0,0 +1,184 @@
+use crate::types::{Escrow, EscrowState};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EscrowSortField {
+    #[default]
+    CreatedAt,
+    ExpiresAt,
+    Amount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    #[default]
+    Descending,
+}
+
+/// Filters, ordering and paging for `EscrowRepository::query_escrows`.
+/// Every filter is optional; date ranges are inclusive on both ends.
+#[derive(Debug, Clone, Default)]
+pub struct EscrowQuery {
+    pub buyer_id: Option<Uuid>,
+    pub seller_id: Option<Uuid>,
+    pub state: Option<EscrowState>,
+    pub currency: Option<String>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub expires_from: Option<DateTime<Utc>>,
+    pub expires_to: Option<DateTime<Utc>>,
+    pub sort_by: EscrowSortField,
+    pub order: SortOrder,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// One page of results plus the number of escrows matching the filters.
+#[derive(Debug, Clone)]
+pub struct EscrowPage {
+    pub escrows: Vec<Escrow>,
+    pub total: usize,
+}
+
+impl EscrowQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn matches(&self, escrow: &Escrow) -> bool {
+        self.buyer_id.is_none_or(|id| escrow.buyer_id == id)
+            && self.seller_id.is_none_or(|id| escrow.seller_id == id)
+            && self.state.as_ref().is_none_or(|state| &escrow.state == state)
+            && self
+                .currency
+                .as_ref()
+                .is_none_or(|currency| escrow.currency.eq_ignore_ascii_case(currency))
+            && self.created_from.is_none_or(|from| escrow.created_at >= from)
+            && self.created_to.is_none_or(|to| escrow.created_at <= to)
+            && self.expires_from.is_none_or(|from| escrow.expires_at >= from)
+            && self.expires_to.is_none_or(|to| escrow.expires_at <= to)
+    }
+
+    /// Filters, sorts and pages an in-memory set of escrows. Backends that
+    /// cannot push the query down to an index use this directly.
+    pub fn apply(&self, escrows: impl IntoIterator<Item = Escrow>) -> EscrowPage {
+        let mut matching: Vec<Escrow> = escrows.into_iter().filter(|e| self.matches(e)).collect();
+
+        matching.sort_by(|a, b| {
+            let ordering = match self.sort_by {
+                EscrowSortField::CreatedAt => a.created_at.cmp(&b.created_at),
+                EscrowSortField::ExpiresAt => a.expires_at.cmp(&b.expires_at),
+                EscrowSortField::Amount => a.amount.cmp(&b.amount),
+            }
+            .then_with(|| a.id.cmp(&b.id));
+
+            match self.order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            }
+        });
+
+        let total = matching.len();
+        let escrows = matching
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        EscrowPage { escrows, total }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::escrow;
+    use chrono::Duration;
+    use rstest::rstest;
+    use rust_decimal::Decimal;
+
+    /// Four escrows a day apart, oldest first, for two buyers; the third is
+    /// funded and in USD.
+    fn escrows() -> Vec<Escrow> {
+        let base = escrow();
+        let other_buyer = Uuid::new_v4();
+        (0..4)
+            .map(|i| {
+                let mut escrow = base.clone();
+                escrow.id = Uuid::new_v4();
+                escrow.amount = Decimal::from(100 * (4 - i));
+                escrow.created_at = base.created_at + Duration::days(i);
+                escrow.expires_at = escrow.created_at + Duration::days(30);
+                if i % 2 == 1 {
+                    escrow.buyer_id = other_buyer;
+                }
+                if i == 2 {
+                    escrow.state = EscrowState::Funded;
+                    escrow.currency = "USD".to_string();
+                }
+                escrow
+            })
+            .collect()
+    }
+
+    fn amounts(page: &EscrowPage) -> Vec<Decimal> {
+        page.escrows.iter().map(|e| e.amount).collect()
+    }
+
+    #[test]
+    fn empty_query_returns_everything_newest_first() {
+        let page = EscrowQuery::new().apply(escrows());
+
+        assert_eq!(page.total, 4);
+        let expected: Vec<Decimal> = [100, 200, 300, 400].into_iter().map(Decimal::from).collect();
+        assert_eq!(amounts(&page), expected);
+    }
+
+    #[rstest]
+    #[case::state(EscrowQuery { state: Some(EscrowState::Funded), ..EscrowQuery::new() }, 1)]
+    #[case::currency_ignores_case(EscrowQuery { currency: Some("usd".to_string()), ..EscrowQuery::new() }, 1)]
+    #[case::nothing_matches(EscrowQuery { state: Some(EscrowState::Refunded), ..EscrowQuery::new() }, 0)]
+    fn filters_narrow_the_total(#[case] query: EscrowQuery, #[case] expected: usize) {
+        assert_eq!(query.apply(escrows()).total, expected);
+    }
+
+    #[test]
+    fn party_filter_matches_only_that_buyer() {
+        let escrows = escrows();
+        let buyer_id = escrows[0].buyer_id;
+        let query = EscrowQuery { buyer_id: Some(buyer_id), ..EscrowQuery::new() };
+
+        let page = query.apply(escrows);
+        assert_eq!(page.total, 2);
+        assert!(page.escrows.iter().all(|e| e.buyer_id == buyer_id));
+    }
+
+    #[test]
+    fn date_ranges_include_both_ends() {
+        let escrows = escrows();
+        let query = EscrowQuery {
+            created_from: Some(escrows[1].created_at),
+            created_to: Some(escrows[2].created_at),
+            ..EscrowQuery::new()
+        };
+
+        assert_eq!(amounts(&query.apply(escrows)), vec![Decimal::from(200), Decimal::from(300)]);
+    }
+
+    #[test]
+    fn sorts_and_pages_after_filtering() {
+        let query = EscrowQuery {
+            sort_by: EscrowSortField::Amount,
+            order: SortOrder::Ascending,
+            offset: 1,
+            limit: Some(2),
+            ..EscrowQuery::new()
+        };
+
+        let page = query.apply(escrows());
+        assert_eq!(page.total, 4);
+        assert_eq!(amounts(&page), vec![Decimal::from(200), Decimal::from(300)]);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::escrow::events::EscrowEvent;
+use crate::storage::errors::StorageError;
+use crate::storage::query::{EscrowPage, EscrowQuery};
+use crate::trust::UserTrustProfile;
+use crate::types::{Escrow, User};
+use uuid::Uuid;
+
+pub trait EscrowRepository {
+    fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError>;
+
+    fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError>;
+
+    /// Compare-and-swap: stores `escrow` only if the stored copy is still at
+    /// `expected_version`, otherwise returns `VersionConflict`.
+    fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError>;
+
+    /// `update_escrow` and `EventRepository::append_event` as one write:
+    /// `escrow` and the `event` that produced it are both stored, or neither
+    /// is and the version or sequence conflict is returned.
+    fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError>;
+
+    fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError>;
+
+    fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError>;
+}
+
+pub trait UserRepository {
+    fn create_user(&self, user: User) -> Result<(), StorageError>;
+
+    fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError>;
+
+    /// Replaces an existing user; `NotFound` if there is none.
+    fn update_user(&self, user: User) -> Result<(), StorageError>;
+
+    fn list_users(&self) -> Result<Vec<User>, StorageError>;
+}
+
+pub trait TrustRepository {
+    /// Inserts or replaces each profile, keyed by user id.
+    fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError>;
+
+    fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError>;
+}
+
+/// Append-only log of escrow events. Stored events are never modified.
+pub trait EventRepository {
+    /// Appends `event`, which must carry the sequence number directly after
+    /// the last stored event; otherwise `SequenceConflict` is returned.
+    fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError>;
+
+    fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError>;
+
+    /// Events for one escrow, oldest first.
+    fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError>;
+
+    /// Every stored event in sequence order.
+    fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError>;
+}
+
+pub(crate) fn check_version(stored: &Escrow, expected_version: u64) -> Result<(), StorageError> {
+    if stored.version != expected_version {
+        return Err(StorageError::VersionConflict {
+            id: stored.id,
+            expected: expected_version,
+            found: stored.version,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_next_sequence(
+    last: Option<&EscrowEvent>,
+    event: &EscrowEvent,
+) -> Result<(), StorageError> {
+    let expected = last.map(|e| e.sequence + 1).unwrap_or(1);
+    if event.sequence != expected {
+        return Err(StorageError::SequenceConflict {
+            expected,
+            found: event.sequence,
+        });
+    }
+    Ok(())
+}
+
+/// A complete storage backend. Implemented automatically for anything that
+/// provides every repository, so handlers can take `&dyn Storage`.
+pub trait Storage: EscrowRepository + UserRepository + EventRepository + TrustRepository {}
+
+impl<T> Storage for T where T: EscrowRepository + UserRepository + EventRepository + TrustRepository {}
@@ -0,0 +1,1051 @@
+use crate::escrow::events::EscrowEvent;
+use crate::storage::errors::StorageError;
+use crate::storage::migrations;
+use crate::storage::query::{EscrowPage, EscrowQuery, EscrowSortField, SortOrder};
+use crate::storage::repository::{
+    check_next_sequence, EscrowRepository, EventRepository, TrustRepository, UserRepository,
+};
+use crate::trust::UserTrustProfile;
+use crate::types::{
+    DisputeResolution, Escrow, Evidence, GpsLocation, Handover, LineItem, Milestone, PinAttempts, Settlement,
+    TrustScore, User, Vote,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const ESCROW_COLUMNS: &str = "e.id, e.amount, e.currency, e.buyer_id, e.seller_id, e.description, \
+    e.state, e.created_at, e.expires_at, e.funded_at, e.completed_at, e.release_pin, e.arbitrators, \
+    d.raised_by, d.raised_at, d.resolved_at, d.decision, e.version, \
+    e.failed_pin_attempts, e.pin_lockouts, e.pin_locked_until, e.pin_reissues, e.pin_last_reissued_at, \
+    e.release_mode, e.totp_secret, e.driver_id, e.handover_by, e.handover_at, e.handover_latitude, \
+    e.handover_longitude, e.settlement_to_seller, e.settlement_to_buyer, d.evidence_deadline, \
+    d.voting_deadline, d.replaced, d.replacement_rounds, d.escalated_at, d.escalation_deadline, \
+    d.appeal_rules, d.appeal_deadline, d.appeals, e.totp_last_step";
+
+const USER_COLUMNS: &str = "id, name, phone_number, user_type, trust_score, total_transactions, \
+    successful_transactions, dispute_rate, trust_updated_at, created_at, public_key";
+
+/// Embedded SQL backend. Escrows, users, trust profiles, disputes, votes and
+/// the escrow event log live in their own tables so the database can be queried directly for
+/// reporting.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, StorageError> {
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        migrations::run(&mut conn)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn schema_version(&self) -> Result<u32, StorageError> {
+        let conn = self.conn.lock()?;
+        Ok(migrations::current_version(&conn)?)
+    }
+
+    fn load_escrows(
+        conn: &Connection,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Escrow>, StorageError> {
+        let sql = format!(
+            "SELECT {} FROM escrows e LEFT JOIN dispute_resolutions d ON d.escrow_id = e.id {}",
+            ESCROW_COLUMNS, filter
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let mut escrows = stmt
+            .query_map(params, escrow_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for escrow in escrows.iter_mut() {
+            if let Some(dispute) = escrow.dispute_resolution.as_mut() {
+                dispute.votes = Self::load_votes(conn, escrow.id)?;
+                dispute.evidence = Self::load_evidence(conn, escrow.id)?;
+            }
+            escrow.milestones = Self::load_milestones(conn, escrow.id)?;
+            escrow.line_items = Self::load_line_items(conn, escrow.id)?;
+        }
+
+        Ok(escrows)
+    }
+
+    fn load_line_items(conn: &Connection, escrow_id: Uuid) -> Result<Vec<LineItem>, StorageError> {
+        let mut stmt = conn.prepare(
+            "SELECT sku, quantity, unit_price, received FROM line_items WHERE escrow_id = ?1 ORDER BY position",
+        )?;
+        let items = stmt
+            .query_map(params![escrow_id.to_string()], |row| {
+                Ok(LineItem {
+                    sku: row.get(0)?,
+                    quantity: row.get(1)?,
+                    unit_price: decimal_col(row, 2)?,
+                    received: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(items)
+    }
+
+    fn load_milestones(conn: &Connection, escrow_id: Uuid) -> Result<Vec<Milestone>, StorageError> {
+        let mut stmt = conn.prepare(
+            "SELECT number, description, amount, pin_hash, released_by, released_at, latitude, longitude
+             FROM milestones WHERE escrow_id = ?1 ORDER BY number",
+        )?;
+        let milestones = stmt
+            .query_map(params![escrow_id.to_string()], |row| {
+                Ok(Milestone {
+                    number: row.get(0)?,
+                    description: row.get(1)?,
+                    amount: decimal_col(row, 2)?,
+                    pin_hash: row.get(3)?,
+                    handover: handover_cols(row, 4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(milestones)
+    }
+
+    fn load_votes(conn: &Connection, escrow_id: Uuid) -> Result<Vec<Vote>, StorageError> {
+        let mut stmt = conn.prepare(
+            "SELECT arbitrator_id, vote, voted_at, seller_percent FROM votes
+             WHERE escrow_id = ?1 ORDER BY voted_at",
+        )?;
+        let votes = stmt
+            .query_map(params![escrow_id.to_string()], |row| {
+                Ok(Vote {
+                    arbitrator_id: uuid_col(row, 0)?,
+                    vote: row.get(1)?,
+                    voted_at: timestamp_col(row, 2)?,
+                    seller_percent: optional_decimal_col(row, 3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(votes)
+    }
+
+    fn load_evidence(conn: &Connection, escrow_id: Uuid) -> Result<Vec<Evidence>, StorageError> {
+        let mut stmt = conn.prepare(
+            "SELECT submitted_by, kind, content, submitted_at FROM evidence
+             WHERE escrow_id = ?1 ORDER BY position",
+        )?;
+        let evidence = stmt
+            .query_map(params![escrow_id.to_string()], |row| {
+                Ok(Evidence {
+                    submitted_by: uuid_col(row, 0)?,
+                    kind: enum_col(row, 1)?,
+                    content: row.get(2)?,
+                    submitted_at: timestamp_col(row, 3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(evidence)
+    }
+
+    fn write_escrow(tx: &Transaction, escrow: &Escrow) -> Result<(), StorageError> {
+        let handover = escrow.handover.as_ref();
+        let location = handover.and_then(|h| h.location);
+        tx.execute(
+            "INSERT INTO escrows (id, amount, currency, buyer_id, seller_id, description, state,
+                created_at, expires_at, funded_at, completed_at, release_pin, arbitrators, version,
+                failed_pin_attempts, pin_lockouts, pin_locked_until, pin_reissues, pin_last_reissued_at,
+                release_mode, totp_secret, driver_id, handover_by, handover_at, handover_latitude,
+                handover_longitude, settlement_to_seller, settlement_to_buyer, totp_last_step)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29)
+             ON CONFLICT(id) DO UPDATE SET
+                amount = excluded.amount,
+                currency = excluded.currency,
+                buyer_id = excluded.buyer_id,
+                seller_id = excluded.seller_id,
+                description = excluded.description,
+                state = excluded.state,
+                created_at = excluded.created_at,
+                expires_at = excluded.expires_at,
+                funded_at = excluded.funded_at,
+                completed_at = excluded.completed_at,
+                release_pin = excluded.release_pin,
+                arbitrators = excluded.arbitrators,
+                version = excluded.version,
+                failed_pin_attempts = excluded.failed_pin_attempts,
+                pin_lockouts = excluded.pin_lockouts,
+                pin_locked_until = excluded.pin_locked_until,
+                pin_reissues = excluded.pin_reissues,
+                pin_last_reissued_at = excluded.pin_last_reissued_at,
+                release_mode = excluded.release_mode,
+                totp_secret = excluded.totp_secret,
+                driver_id = excluded.driver_id,
+                handover_by = excluded.handover_by,
+                handover_at = excluded.handover_at,
+                handover_latitude = excluded.handover_latitude,
+                handover_longitude = excluded.handover_longitude,
+                settlement_to_seller = excluded.settlement_to_seller,
+                settlement_to_buyer = excluded.settlement_to_buyer,
+                totp_last_step = excluded.totp_last_step",
+            params![
+                escrow.id.to_string(),
+                escrow.amount.to_string(),
+                escrow.currency,
+                escrow.buyer_id.to_string(),
+                escrow.seller_id.to_string(),
+                escrow.description,
+                enum_to_sql(&escrow.state)?,
+                timestamp_to_sql(&escrow.created_at),
+                timestamp_to_sql(&escrow.expires_at),
+                escrow.funded_at.as_ref().map(timestamp_to_sql),
+                escrow.completed_at.as_ref().map(timestamp_to_sql),
+                escrow.release_pin_hash,
+                json_to_sql(&escrow.arbitrators)?,
+                escrow.version,
+                escrow.pin_attempts.failed,
+                escrow.pin_attempts.lockouts,
+                escrow.pin_attempts.locked_until.as_ref().map(timestamp_to_sql),
+                escrow.pin_attempts.reissues,
+                escrow.pin_attempts.last_reissued_at.as_ref().map(timestamp_to_sql),
+                enum_to_sql(&escrow.release_mode)?,
+                escrow.totp_secret,
+                escrow.driver_id.map(|id| id.to_string()),
+                handover.map(|h| h.released_by.to_string()),
+                handover.map(|h| timestamp_to_sql(&h.at)),
+                location.map(|l| l.latitude),
+                location.map(|l| l.longitude),
+                escrow.settlement.as_ref().map(|s| s.to_seller.to_string()),
+                escrow.settlement.as_ref().map(|s| s.to_buyer.to_string()),
+                escrow.totp_last_step,
+            ],
+        )?;
+
+        tx.execute(
+            "DELETE FROM line_items WHERE escrow_id = ?1",
+            params![escrow.id.to_string()],
+        )?;
+        for (position, item) in escrow.line_items.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO line_items (escrow_id, position, sku, quantity, unit_price, received)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    escrow.id.to_string(),
+                    position,
+                    item.sku,
+                    item.quantity,
+                    item.unit_price.to_string(),
+                    item.received,
+                ],
+            )?;
+        }
+
+        tx.execute(
+            "DELETE FROM milestones WHERE escrow_id = ?1",
+            params![escrow.id.to_string()],
+        )?;
+        for milestone in &escrow.milestones {
+            let handover = milestone.handover.as_ref();
+            let location = handover.and_then(|h| h.location);
+            tx.execute(
+                "INSERT INTO milestones (escrow_id, number, description, amount, pin_hash, released_by,
+                    released_at, latitude, longitude)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    escrow.id.to_string(),
+                    milestone.number,
+                    milestone.description,
+                    milestone.amount.to_string(),
+                    milestone.pin_hash,
+                    handover.map(|h| h.released_by.to_string()),
+                    handover.map(|h| timestamp_to_sql(&h.at)),
+                    location.map(|l| l.latitude),
+                    location.map(|l| l.longitude),
+                ],
+            )?;
+        }
+
+        tx.execute(
+            "DELETE FROM dispute_resolutions WHERE escrow_id = ?1",
+            params![escrow.id.to_string()],
+        )?;
+        tx.execute(
+            "DELETE FROM votes WHERE escrow_id = ?1",
+            params![escrow.id.to_string()],
+        )?;
+        tx.execute(
+            "DELETE FROM evidence WHERE escrow_id = ?1",
+            params![escrow.id.to_string()],
+        )?;
+
+        if let Some(dispute) = &escrow.dispute_resolution {
+            tx.execute(
+                "INSERT INTO dispute_resolutions
+                    (escrow_id, raised_by, raised_at, resolved_at, decision, evidence_deadline,
+                     voting_deadline, replaced, replacement_rounds, escalated_at, escalation_deadline,
+                     appeal_rules, appeal_deadline, appeals)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    escrow.id.to_string(),
+                    dispute.raised_by.to_string(),
+                    timestamp_to_sql(&dispute.raised_at),
+                    dispute.resolved_at.as_ref().map(timestamp_to_sql),
+                    dispute.decision.as_ref().map(enum_to_sql).transpose()?,
+                    dispute.evidence_deadline.as_ref().map(timestamp_to_sql),
+                    dispute.voting_deadline.as_ref().map(timestamp_to_sql),
+                    json_to_sql(&dispute.replaced)?,
+                    dispute.replacement_rounds,
+                    dispute.escalated_at.as_ref().map(timestamp_to_sql),
+                    dispute.escalation_deadline.as_ref().map(timestamp_to_sql),
+                    dispute.appeal_rules.as_ref().map(json_to_sql).transpose()?,
+                    dispute.appeal_deadline.as_ref().map(timestamp_to_sql),
+                    json_to_sql(&dispute.appeals)?,
+                ],
+            )?;
+
+            for vote in &dispute.votes {
+                tx.execute(
+                    "INSERT INTO votes (escrow_id, arbitrator_id, vote, voted_at, seller_percent)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        escrow.id.to_string(),
+                        vote.arbitrator_id.to_string(),
+                        vote.vote,
+                        timestamp_to_sql(&vote.voted_at),
+                        vote.seller_percent.map(|p| p.to_string()),
+                    ],
+                )?;
+            }
+
+            for (position, evidence) in dispute.evidence.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO evidence (escrow_id, position, submitted_by, kind, content, submitted_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        escrow.id.to_string(),
+                        position,
+                        evidence.submitted_by.to_string(),
+                        enum_to_sql(&evidence.kind)?,
+                        evidence.content,
+                        timestamp_to_sql(&evidence.submitted_at),
+                    ],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_events(
+        conn: &Connection,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<EscrowEvent>, StorageError> {
+        let sql = format!("SELECT payload FROM escrow_events {} ORDER BY sequence", filter);
+        let mut stmt = conn.prepare(&sql)?;
+        let events = stmt
+            .query_map(params, |row| json_col(row, 0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(events)
+    }
+
+    fn check_version(tx: &Transaction, id: Uuid, expected_version: u64) -> Result<(), StorageError> {
+        let found = Self::stored_version(tx, id)?.ok_or(StorageError::NotFound(id))?;
+        if found != expected_version {
+            return Err(StorageError::VersionConflict {
+                id,
+                expected: expected_version,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Appends `event` if it carries the next sequence number.
+    fn insert_event(tx: &Transaction, event: &EscrowEvent) -> Result<(), StorageError> {
+        let last = tx
+            .query_row(
+                "SELECT payload FROM escrow_events ORDER BY sequence DESC LIMIT 1",
+                [],
+                |row| json_col::<EscrowEvent>(row, 0),
+            )
+            .optional()?;
+        check_next_sequence(last.as_ref(), event)?;
+
+        tx.execute(
+            "INSERT INTO escrow_events (sequence, escrow_id, kind, actor, occurred_at, payload)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                event.sequence,
+                event.escrow_id.to_string(),
+                enum_name(&event.kind)?,
+                event.kind.actor().map(|id| id.to_string()),
+                timestamp_to_sql(&event.occurred_at),
+                json_to_sql(event)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn stored_version(tx: &Transaction, id: Uuid) -> Result<Option<u64>, StorageError> {
+        let version = tx
+            .query_row(
+                "SELECT version FROM escrows WHERE id = ?1",
+                params![id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(version)
+    }
+}
+
+impl EscrowRepository for SqliteStorage {
+    fn create_escrow(&self, escrow: Escrow) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock()?;
+        let tx = conn.transaction()?;
+
+        if Self::stored_version(&tx, escrow.id)?.is_some() {
+            return Err(StorageError::AlreadyExists(escrow.id));
+        }
+
+        Self::write_escrow(&tx, &escrow)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_escrow(&self, id: Uuid) -> Result<Option<Escrow>, StorageError> {
+        let conn = self.conn.lock()?;
+        let mut escrows = Self::load_escrows(&conn, "WHERE e.id = ?1", &[&id.to_string()])?;
+        Ok(escrows.pop())
+    }
+
+    fn update_escrow(&self, escrow: Escrow, expected_version: u64) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock()?;
+        // Take the write lock up front so another process cannot slip in
+        // between the version check and the write.
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        Self::check_version(&tx, escrow.id, expected_version)?;
+        Self::write_escrow(&tx, &escrow)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn commit_escrow(&self, escrow: Escrow, expected_version: u64, event: EscrowEvent) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        Self::check_version(&tx, escrow.id, expected_version)?;
+        Self::write_escrow(&tx, &escrow)?;
+        Self::insert_event(&tx, &event)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list_escrows(&self) -> Result<Vec<Escrow>, StorageError> {
+        let conn = self.conn.lock()?;
+        Self::load_escrows(&conn, "ORDER BY e.created_at", &[])
+    }
+
+    fn query_escrows(&self, query: &EscrowQuery) -> Result<EscrowPage, StorageError> {
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut values: Vec<String> = Vec::new();
+
+        if let Some(buyer_id) = query.buyer_id {
+            conditions.push("e.buyer_id = ?");
+            values.push(buyer_id.to_string());
+        }
+        if let Some(seller_id) = query.seller_id {
+            conditions.push("e.seller_id = ?");
+            values.push(seller_id.to_string());
+        }
+        if let Some(state) = &query.state {
+            conditions.push("e.state = ?");
+            values.push(enum_to_sql(state)?);
+        }
+        if let Some(currency) = &query.currency {
+            conditions.push("e.currency = ? COLLATE NOCASE");
+            values.push(currency.clone());
+        }
+        let ranges = [
+            ("e.created_at >= ?", query.created_from),
+            ("e.created_at <= ?", query.created_to),
+            ("e.expires_at >= ?", query.expires_from),
+            ("e.expires_at <= ?", query.expires_to),
+        ];
+        for (condition, bound) in ranges {
+            if let Some(ts) = bound {
+                conditions.push(condition);
+                values.push(timestamp_to_sql(&ts));
+            }
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let sort_column = match query.sort_by {
+            EscrowSortField::CreatedAt => "e.created_at",
+            EscrowSortField::ExpiresAt => "e.expires_at",
+            EscrowSortField::Amount => "CAST(e.amount AS REAL)",
+        };
+        let direction = match query.order {
+            SortOrder::Ascending => "ASC",
+            SortOrder::Descending => "DESC",
+        };
+        let limit = query.limit.map(|l| l as i64).unwrap_or(-1);
+
+        let params: Vec<&dyn rusqlite::ToSql> =
+            values.iter().map(|v| v as &dyn rusqlite::ToSql).collect();
+
+        let conn = self.conn.lock()?;
+        let total: usize = conn.query_row(
+            &format!("SELECT COUNT(*) FROM escrows e {}", where_clause),
+            params.as_slice(),
+            |row| row.get(0),
+        )?;
+        let escrows = Self::load_escrows(
+            &conn,
+            &format!(
+                "{} ORDER BY {} {}, e.id {} LIMIT {} OFFSET {}",
+                where_clause, sort_column, direction, direction, limit, query.offset
+            ),
+            &params,
+        )?;
+
+        Ok(EscrowPage { escrows, total })
+    }
+}
+
+impl UserRepository for SqliteStorage {
+    fn create_user(&self, user: User) -> Result<(), StorageError> {
+        let conn = self.conn.lock()?;
+        let inserted = conn.execute(
+            "INSERT INTO users (id, name, phone_number, user_type, trust_score, total_transactions,
+                successful_transactions, dispute_rate, trust_updated_at, created_at, public_key)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+             ON CONFLICT(id) DO NOTHING",
+            params![
+                user.id.to_string(),
+                user.name,
+                user.phone_number,
+                enum_to_sql(&user.user_type)?,
+                user.trust_score.score.to_string(),
+                user.trust_score.total_transactions,
+                user.trust_score.successful_transactions,
+                user.trust_score.dispute_rate.to_string(),
+                timestamp_to_sql(&user.trust_score.last_updated),
+                timestamp_to_sql(&user.created_at),
+                user.public_key,
+            ],
+        )?;
+
+        if inserted == 0 {
+            return Err(StorageError::AlreadyExists(user.id));
+        }
+        Ok(())
+    }
+
+    fn get_user(&self, id: Uuid) -> Result<Option<User>, StorageError> {
+        let conn = self.conn.lock()?;
+        let user = conn
+            .query_row(
+                &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+                params![id.to_string()],
+                user_from_row,
+            )
+            .optional()?;
+
+        Ok(user)
+    }
+
+    fn update_user(&self, user: User) -> Result<(), StorageError> {
+        let conn = self.conn.lock()?;
+        let updated = conn.execute(
+            "UPDATE users SET name = ?2, phone_number = ?3, user_type = ?4, trust_score = ?5,
+                total_transactions = ?6, successful_transactions = ?7, dispute_rate = ?8,
+                trust_updated_at = ?9, created_at = ?10, public_key = ?11
+             WHERE id = ?1",
+            params![
+                user.id.to_string(),
+                user.name,
+                user.phone_number,
+                enum_to_sql(&user.user_type)?,
+                user.trust_score.score.to_string(),
+                user.trust_score.total_transactions,
+                user.trust_score.successful_transactions,
+                user.trust_score.dispute_rate.to_string(),
+                timestamp_to_sql(&user.trust_score.last_updated),
+                timestamp_to_sql(&user.created_at),
+                user.public_key,
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(StorageError::NotFound(user.id));
+        }
+        Ok(())
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, StorageError> {
+        let conn = self.conn.lock()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM users ORDER BY created_at", USER_COLUMNS))?;
+        let users = stmt
+            .query_map([], user_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(users)
+    }
+}
+
+impl TrustRepository for SqliteStorage {
+    fn save_trust_profiles(&self, profiles: &[UserTrustProfile]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock()?;
+        let tx = conn.transaction()?;
+
+        for profile in profiles {
+            tx.execute(
+                "INSERT INTO trust_profiles (user_id, trust_score, total_transactions,
+                    successful_transactions, disputed_transactions, total_amount_transacted,
+                    last_active, join_date)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT(user_id) DO UPDATE SET
+                    trust_score = excluded.trust_score,
+                    total_transactions = excluded.total_transactions,
+                    successful_transactions = excluded.successful_transactions,
+                    disputed_transactions = excluded.disputed_transactions,
+                    total_amount_transacted = excluded.total_amount_transacted,
+                    last_active = excluded.last_active,
+                    join_date = excluded.join_date",
+                params![
+                    profile.user_id.to_string(),
+                    profile.trust_score,
+                    profile.total_transactions,
+                    profile.successful_transactions,
+                    profile.disputed_transactions,
+                    profile.total_amount_transacted.to_string(),
+                    timestamp_to_sql(&profile.last_active),
+                    timestamp_to_sql(&profile.join_date),
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list_trust_profiles(&self) -> Result<Vec<UserTrustProfile>, StorageError> {
+        let conn = self.conn.lock()?;
+        let mut stmt = conn.prepare(
+            "SELECT user_id, trust_score, total_transactions, successful_transactions,
+                disputed_transactions, total_amount_transacted, last_active, join_date
+             FROM trust_profiles",
+        )?;
+        let profiles = stmt
+            .query_map([], |row| {
+                Ok(UserTrustProfile {
+                    user_id: uuid_col(row, 0)?,
+                    trust_score: row.get(1)?,
+                    total_transactions: row.get(2)?,
+                    successful_transactions: row.get(3)?,
+                    disputed_transactions: row.get(4)?,
+                    total_amount_transacted: decimal_col(row, 5)?,
+                    last_active: timestamp_col(row, 6)?,
+                    join_date: timestamp_col(row, 7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(profiles)
+    }
+}
+
+impl EventRepository for SqliteStorage {
+    fn append_event(&self, event: EscrowEvent) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock()?;
+        let tx = conn.transaction()?;
+
+        Self::insert_event(&tx, &event)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn last_event(&self) -> Result<Option<EscrowEvent>, StorageError> {
+        let conn = self.conn.lock()?;
+        let mut events = Self::load_events(
+            &conn,
+            "WHERE sequence = (SELECT MAX(sequence) FROM escrow_events)",
+            &[],
+        )?;
+        Ok(events.pop())
+    }
+
+    fn list_events(&self, escrow_id: Uuid) -> Result<Vec<EscrowEvent>, StorageError> {
+        let conn = self.conn.lock()?;
+        Self::load_events(&conn, "WHERE escrow_id = ?1", &[&escrow_id.to_string()])
+    }
+
+    fn list_all_events(&self) -> Result<Vec<EscrowEvent>, StorageError> {
+        let conn = self.conn.lock()?;
+        Self::load_events(&conn, "", &[])
+    }
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: uuid_col(row, 0)?,
+        name: row.get(1)?,
+        phone_number: row.get(2)?,
+        user_type: enum_col(row, 3)?,
+        trust_score: TrustScore {
+            score: decimal_col(row, 4)?,
+            total_transactions: row.get(5)?,
+            successful_transactions: row.get(6)?,
+            dispute_rate: decimal_col(row, 7)?,
+            last_updated: timestamp_col(row, 8)?,
+        },
+        created_at: timestamp_col(row, 9)?,
+        public_key: row.get(10)?,
+    })
+}
+
+fn escrow_from_row(row: &Row) -> rusqlite::Result<Escrow> {
+    let dispute_resolution = match row.get::<_, Option<String>>(13)? {
+        Some(_) => Some(DisputeResolution {
+            raised_by: uuid_col(row, 13)?,
+            raised_at: timestamp_col(row, 14)?,
+            votes: Vec::new(),
+            resolved_at: optional_timestamp_col(row, 15)?,
+            decision: row
+                .get::<_, Option<String>>(16)?
+                .map(|_| enum_col(row, 16))
+                .transpose()?,
+            evidence_deadline: optional_timestamp_col(row, 32)?,
+            evidence: Vec::new(),
+            voting_deadline: optional_timestamp_col(row, 33)?,
+            replaced: json_col(row, 34)?,
+            replacement_rounds: row.get(35)?,
+            escalated_at: optional_timestamp_col(row, 36)?,
+            escalation_deadline: optional_timestamp_col(row, 37)?,
+            appeal_rules: row
+                .get::<_, Option<String>>(38)?
+                .map(|_| json_col(row, 38))
+                .transpose()?,
+            appeal_deadline: optional_timestamp_col(row, 39)?,
+            appeals: json_col(row, 40)?,
+        }),
+        None => None,
+    };
+
+    Ok(Escrow {
+        id: uuid_col(row, 0)?,
+        amount: decimal_col(row, 1)?,
+        currency: row.get(2)?,
+        buyer_id: uuid_col(row, 3)?,
+        seller_id: uuid_col(row, 4)?,
+        description: row.get(5)?,
+        state: enum_col(row, 6)?,
+        created_at: timestamp_col(row, 7)?,
+        expires_at: timestamp_col(row, 8)?,
+        funded_at: optional_timestamp_col(row, 9)?,
+        completed_at: optional_timestamp_col(row, 10)?,
+        release_pin_hash: row.get(11)?,
+        release_mode: enum_col(row, 23)?,
+        totp_secret: row.get(24)?,
+        totp_last_step: row.get(41)?,
+        arbitrators: json_col(row, 12)?,
+        dispute_resolution,
+        pin_attempts: PinAttempts {
+            failed: row.get(18)?,
+            lockouts: row.get(19)?,
+            locked_until: optional_timestamp_col(row, 20)?,
+            reissues: row.get(21)?,
+            last_reissued_at: optional_timestamp_col(row, 22)?,
+        },
+        driver_id: optional_uuid_col(row, 25)?,
+        handover: handover_cols(row, 26)?,
+        milestones: Vec::new(),
+        line_items: Vec::new(),
+        settlement: match row.get::<_, Option<String>>(30)? {
+            Some(_) => Some(Settlement {
+                to_seller: decimal_col(row, 30)?,
+                to_buyer: decimal_col(row, 31)?,
+            }),
+            None => None,
+        },
+        version: row.get(17)?,
+    })
+}
+
+/// A handover stored as released-by, released-at, latitude and longitude
+/// columns starting at `idx`.
+fn handover_cols(row: &Row, idx: usize) -> rusqlite::Result<Option<Handover>> {
+    if row.get::<_, Option<String>>(idx)?.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Handover {
+        released_by: uuid_col(row, idx)?,
+        at: timestamp_col(row, idx + 1)?,
+        location: match (row.get(idx + 2)?, row.get(idx + 3)?) {
+            (Some(latitude), Some(longitude)) => Some(GpsLocation { latitude, longitude }),
+            _ => None,
+        },
+    }))
+}
+
+// Timestamps are stored as fixed-width UTC RFC 3339 strings so that they
+// sort correctly as text, at full precision so stored escrows compare equal
+// to those replayed from the event log.
+fn timestamp_to_sql(ts: &DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn enum_to_sql<T: Serialize>(value: &T) -> Result<String, StorageError> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => Ok(s),
+        Ok(other) => Ok(other.to_string()),
+        Err(e) => Err(StorageError::Serialization(e.to_string())),
+    }
+}
+
+/// The variant name of a serialized enum, whether or not it carries data.
+fn enum_name<T: Serialize>(value: &T) -> Result<String, StorageError> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => Ok(s),
+        Ok(serde_json::Value::Object(map)) => Ok(map.keys().next().cloned().unwrap_or_default()),
+        Ok(other) => Ok(other.to_string()),
+        Err(e) => Err(StorageError::Serialization(e.to_string())),
+    }
+}
+
+fn json_to_sql<T: Serialize>(value: &T) -> Result<String, StorageError> {
+    serde_json::to_string(value).map_err(|e| StorageError::Serialization(e.to_string()))
+}
+
+fn conversion_error(
+    idx: usize,
+    e: impl std::error::Error + Send + Sync + 'static,
+) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e))
+}
+
+fn uuid_col(row: &Row, idx: usize) -> rusqlite::Result<Uuid> {
+    let s: String = row.get(idx)?;
+    Uuid::parse_str(&s).map_err(|e| conversion_error(idx, e))
+}
+
+fn optional_uuid_col(row: &Row, idx: usize) -> rusqlite::Result<Option<Uuid>> {
+    match row.get::<_, Option<String>>(idx)? {
+        Some(_) => uuid_col(row, idx).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn decimal_col(row: &Row, idx: usize) -> rusqlite::Result<Decimal> {
+    let s: String = row.get(idx)?;
+    Decimal::from_str(&s).map_err(|e| conversion_error(idx, e))
+}
+
+fn optional_decimal_col(row: &Row, idx: usize) -> rusqlite::Result<Option<Decimal>> {
+    match row.get::<_, Option<String>>(idx)? {
+        Some(_) => decimal_col(row, idx).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn timestamp_col(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let s: String = row.get(idx)?;
+    DateTime::parse_from_rfc3339(&s)
+        .map(|ts| ts.with_timezone(&Utc))
+        .map_err(|e| conversion_error(idx, e))
+}
+
+fn optional_timestamp_col(row: &Row, idx: usize) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    match row.get::<_, Option<String>>(idx)? {
+        Some(_) => timestamp_col(row, idx).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn enum_col<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    let s: String = row.get(idx)?;
+    let value = serde_json::from_str(&s).unwrap_or(serde_json::Value::String(s));
+    serde_json::from_value(value).map_err(|e| conversion_error(idx, e))
+}
+
+fn json_col<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    let s: String = row.get(idx)?;
+    serde_json::from_str(&s).map_err(|e| conversion_error(idx, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{escrow, user};
+    use crate::types::{EscrowState, UserType};
+
+    #[test]
+    fn escrow_round_trips_with_its_child_rows() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let mut escrow = escrow();
+        let arbitrator_id = Uuid::new_v4();
+        escrow.state = EscrowState::InDispute;
+        escrow.arbitrators = vec![arbitrator_id];
+        escrow.dispute_resolution = Some(DisputeResolution {
+            raised_by: escrow.buyer_id,
+            raised_at: Utc::now(),
+            votes: vec![Vote { arbitrator_id, vote: true, voted_at: Utc::now(), seller_percent: None }],
+            resolved_at: None,
+            decision: None,
+            evidence_deadline: None,
+            evidence: Vec::new(),
+            voting_deadline: None,
+            replaced: Vec::new(),
+            replacement_rounds: 0,
+            escalated_at: None,
+            escalation_deadline: None,
+            appeal_rules: None,
+            appeal_deadline: None,
+            appeals: Vec::new(),
+        });
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        let stored = storage.get_escrow(escrow.id).unwrap().unwrap();
+        assert_eq!(stored.amount, escrow.amount);
+        assert_eq!(stored.created_at, escrow.created_at);
+        assert_eq!(stored.expires_at, escrow.expires_at);
+        assert_eq!(stored.state, EscrowState::InDispute);
+        assert_eq!(stored.arbitrators, [arbitrator_id]);
+        let votes = &stored.dispute_resolution.unwrap().votes;
+        assert_eq!(votes.len(), 1);
+        assert!(votes[0].vote && votes[0].arbitrator_id == arbitrator_id);
+    }
+
+    #[test]
+    fn user_round_trips() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let user = user(UserType::Arbitrator);
+        storage.create_user(user.clone()).unwrap();
+
+        let stored = storage.get_user(user.id).unwrap().unwrap();
+        assert!(matches!(stored.user_type, UserType::Arbitrator));
+        assert_eq!(stored.phone_number, user.phone_number);
+        assert_eq!(stored.created_at, user.created_at);
+        assert_eq!(stored.trust_score.score, user.trust_score.score);
+    }
+
+    #[test]
+    fn schema_is_migrated_on_open() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        assert_eq!(storage.schema_version().unwrap(), migrations::MIGRATIONS.last().unwrap().version);
+    }
+
+    #[test]
+    fn stale_update_is_a_version_conflict() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        let mut newer = escrow.clone();
+        newer.version = 1;
+        storage.update_escrow(newer, 0).unwrap();
+
+        assert!(matches!(
+            storage.update_escrow(escrow.clone(), 0),
+            Err(StorageError::VersionConflict { expected: 0, found: 1, .. })
+        ));
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 1);
+    }
+
+    #[test]
+    fn commit_stores_neither_escrow_nor_event_when_the_append_fails() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let escrow = escrow();
+        storage.create_escrow(escrow.clone()).unwrap();
+        let mut created = EscrowEvent::created(&escrow);
+        created.sequence = 1;
+        storage.append_event(created).unwrap();
+
+        let mut newer = escrow.clone();
+        newer.version = 1;
+        let mut event = EscrowEvent::created(&escrow);
+        event.sequence = 3;
+        assert!(matches!(
+            storage.commit_escrow(newer.clone(), 0, event),
+            Err(StorageError::SequenceConflict { expected: 2, found: 3 })
+        ));
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 0);
+        assert_eq!(storage.list_all_events().unwrap().len(), 1);
+
+        let mut event = EscrowEvent::created(&escrow);
+        event.sequence = 2;
+        storage.commit_escrow(newer, 0, event).unwrap();
+        assert_eq!(storage.get_escrow(escrow.id).unwrap().unwrap().version, 1);
+        assert_eq!(storage.list_all_events().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn indexed_query_agrees_with_in_memory_filtering() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let base = escrow();
+        for i in 0..4i64 {
+            let mut escrow = base.clone();
+            escrow.id = Uuid::new_v4();
+            escrow.amount = Decimal::from(100 * (i + 1));
+            escrow.created_at = base.created_at + chrono::Duration::hours(i);
+            if i >= 2 {
+                escrow.state = EscrowState::Funded;
+            }
+            storage.create_escrow(escrow).unwrap();
+        }
+
+        let query = EscrowQuery {
+            buyer_id: Some(base.buyer_id),
+            state: Some(EscrowState::Funded),
+            sort_by: EscrowSortField::Amount,
+            order: SortOrder::Descending,
+            limit: Some(1),
+            ..EscrowQuery::new()
+        };
+        let indexed = storage.query_escrows(&query).unwrap();
+        let filtered = query.apply(storage.list_escrows().unwrap());
+
+        assert_eq!(indexed.total, 2);
+        assert_eq!(indexed.total, filtered.total);
+        assert_eq!(indexed.escrows[0].amount, Decimal::from(400));
+        assert_eq!(indexed.escrows[0].id, filtered.escrows[0].id);
+    }
+
+    #[test]
+    fn milestones_round_trip() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let mut escrow = escrow();
+        escrow
+            .split_into_milestones(vec![
+                ("First load".to_string(), Decimal::from(1000)),
+                ("Second load".to_string(), Decimal::from(500)),
+            ])
+            .unwrap();
+        storage.create_escrow(escrow.clone()).unwrap();
+
+        let stored = storage.get_escrow(escrow.id).unwrap().unwrap();
+        assert_eq!(stored.milestones, escrow.milestones);
+        assert!(stored.release_pin_hash.is_none());
+    }
+}
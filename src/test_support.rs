@@ -0,0 +1,45 @@
+//! Fixtures shared by the unit tests.
+
+use crate::types::{Escrow, User, UserType};
+use rust_decimal::Decimal;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// An escrow for 1500 ZAR between two parties nobody else trades with.
+pub fn escrow() -> Escrow {
+    escrow_between(Uuid::new_v4(), Uuid::new_v4())
+}
+
+pub fn escrow_between(buyer_id: Uuid, seller_id: Uuid) -> Escrow {
+    escrow_with_pin(buyer_id, seller_id).0
+}
+
+/// Like `escrow_between`, along with the release PIN the buyer was sent.
+pub fn escrow_with_pin(buyer_id: Uuid, seller_id: Uuid) -> (Escrow, String) {
+    Escrow::new(Decimal::from(1500), "ZAR", buyer_id, seller_id.to_string(), "Stock", 30)
+}
+
+/// A user with the given role and the phone number +27820000000.
+pub fn user(user_type: UserType) -> User {
+    User::new(Uuid::new_v4(), format!("{:?}", user_type), "+27820000000", user_type)
+}
+
+/// A fresh directory under the system temp dir, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("spaza-test-{}", Uuid::new_v4())))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -38,6 +38,12 @@ pub struct TrustManager {
     profiles: HashMap<Uuid, UserTrustProfile>,
 }
 
+impl Default for TrustManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TrustManager {
     pub fn new() -> Self {
         Self {
@@ -45,6 +51,18 @@ impl TrustManager {
         }
     }
 
+    pub fn from_profiles(profiles: impl IntoIterator<Item = UserTrustProfile>) -> Self {
+        Self {
+            profiles: profiles.into_iter().map(|p| (p.user_id, p)).collect(),
+        }
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &UserTrustProfile> {
+        self.profiles.values()
+    }
+
+    /// Starts tracking a user. Existing profiles are left untouched so that
+    /// repeat customers keep their history.
     pub fn register_user(&mut self, user_id: Uuid) {
         let now = Utc::now();
         let profile = UserTrustProfile {
@@ -58,7 +76,7 @@ impl TrustManager {
             join_date: now,
         };
         
-        self.profiles.insert(user_id, profile);
+        self.profiles.entry(user_id).or_insert(profile);
     }
 
     pub fn record_transaction(
@@ -102,7 +120,7 @@ impl TrustManager {
         let penalty = dispute_rate * 20.0;
         
         let mut final_score = base_score - penalty;
-        final_score = final_score.max(0.0).min(100.0);
+        final_score = final_score.clamp(0.0, 100.0);
         
         (final_score * 10.0).round() / 10.0
     }
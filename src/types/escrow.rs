@@ -1,10 +1,16 @@
+use crate::escrow::pin;
 use chrono::{DateTime, Utc, Duration};
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EscrowState {
+    /// Waiting for the seller to accept the amount, description and
+    /// deadline. Nothing can be funded until they do.
+    AwaitingSellerAcceptance,
+    /// Terms agreed; ready to be funded.
     Created,
     Funded,
     Completed,
@@ -13,7 +19,24 @@ pub enum EscrowState {
     Refunded,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl FromStr for EscrowState {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "awaitingselleracceptance" | "awaitingacceptance" => Ok(EscrowState::AwaitingSellerAcceptance),
+            "created" => Ok(EscrowState::Created),
+            "funded" => Ok(EscrowState::Funded),
+            "completed" => Ok(EscrowState::Completed),
+            "cancelled" => Ok(EscrowState::Cancelled),
+            "indispute" => Ok(EscrowState::InDispute),
+            "refunded" => Ok(EscrowState::Refunded),
+            _ => Err(format!("Unknown escrow state: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Escrow {
     pub id: Uuid,
     pub amount: Decimal,
@@ -26,31 +49,446 @@ pub struct Escrow {
     pub expires_at: DateTime<Utc>,
     pub funded_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
-    pub release_pin: Option<String>,
+    // Argon2 hash of the release PIN; the PIN itself is only ever handed
+    // out by `Escrow::new`. Serialized under its original name so existing
+    // records and audit hashes stay readable.
+    #[serde(rename = "release_pin")]
+    pub release_pin_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "ReleaseMode::is_pin")]
+    pub release_mode: ReleaseMode,
+    // Base32 secret shared with the buyer's authenticator app when
+    // `release_mode` is `Totp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    // Time step of the last code that released funds. Codes from that step
+    // or earlier are refused, so a code seen at the door cannot be reused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_last_step: Option<i64>,
     pub arbitrators: Vec<Uuid>,
     pub dispute_resolution: Option<DisputeResolution>,
+    // Driver the buyer assigned to deliver and collect the release code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handover: Option<Handover>,
+    // Deliveries released one at a time, each with its own PIN. Empty for
+    // an all-or-nothing escrow.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub milestones: Vec<Milestone>,
+    // What was ordered, when the order was itemised. `description` then
+    // summarises these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub line_items: Vec<LineItem>,
+    // How the funds were finally divided, when not all to one side.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settlement: Option<Settlement>,
+    #[serde(default, skip_serializing_if = "PinAttempts::is_clear")]
+    pub pin_attempts: PinAttempts,
+    // Bumped by every state transition; used for optimistic concurrency.
+    // Omitted while zero so creation snapshots in the audit log hash the same
+    // as before the field existed.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u64,
+}
+
+/// What the buyer hands over to release funds: the static PIN sent by SMS,
+/// or the current code from an authenticator app sharing the escrow's secret.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReleaseMode {
+    #[default]
+    Pin,
+    Totp,
+}
+
+impl ReleaseMode {
+    pub fn is_pin(&self) -> bool {
+        *self == ReleaseMode::Pin
+    }
+}
+
+/// Wrong release PINs entered since the last lockout, the lockout currently
+/// in force, if any, and how often the buyer has had the PIN reissued.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinAttempts {
+    pub failed: u32,
+    pub lockouts: u32,
+    pub locked_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub reissues: u32,
+    #[serde(default)]
+    pub last_reissued_at: Option<DateTime<Utc>>,
+}
+
+impl PinAttempts {
+    pub fn is_clear(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Proof of delivery: who entered the release code at the door, when, and
+/// where their phone said they were.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Handover {
+    pub released_by: Uuid,
+    pub at: DateTime<Utc>,
+    pub location: Option<GpsLocation>,
+}
+
+/// One delivery of a larger order. Released with its own PIN, after which
+/// `amount` goes to the seller.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Milestone {
+    /// 1-based position in the escrow.
+    pub number: u32,
+    pub description: String,
+    pub amount: Decimal,
+    /// Argon2 hash of this milestone's PIN, dropped once it is released.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handover: Option<Handover>,
+}
+
+impl Milestone {
+    pub fn is_released(&self) -> bool {
+        self.handover.is_some()
+    }
+}
+
+/// One product on an itemised order. `received` is filled in when the buyer
+/// confirms what actually arrived.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineItem {
+    pub sku: String,
+    pub quantity: u32,
+    pub unit_price: Decimal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received: Option<u32>,
+}
+
+impl LineItem {
+    pub fn new(sku: impl Into<String>, quantity: u32, unit_price: Decimal) -> Self {
+        Self {
+            sku: sku.into(),
+            quantity,
+            unit_price,
+            received: None,
+        }
+    }
+    
+    pub fn total(&self) -> Decimal {
+        self.unit_price * Decimal::from(self.quantity)
+    }
+}
+
+impl FromStr for LineItem {
+    type Err = String;
+    
+    /// Parses `SKU:QUANTITY@UNIT_PRICE`, e.g. `MILK-1L:24@18.50`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Expected SKU:QUANTITY@UNIT_PRICE but got: {}", s);
+        let (sku, rest) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let (quantity, unit_price) = rest.split_once('@').ok_or_else(invalid)?;
+        Ok(Self::new(
+            sku.trim(),
+            quantity.trim().parse().map_err(|_| invalid())?,
+            Decimal::from_str(unit_price.trim()).map_err(|_| invalid())?,
+        ))
+    }
+}
+
+/// The final split of an escrow's funds between the two parties. The two
+/// payouts always add up to the amount that was settled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub to_seller: Decimal,
+    pub to_buyer: Decimal,
+}
+
+impl Settlement {
+    /// Decimal places payouts are rounded to: cents for every currency the
+    /// service handles.
+    pub const PAYOUT_DECIMALS: u32 = 2;
+    
+    /// Gives the seller `seller_percent` of `total`, rounded half-to-even to
+    /// whole cents, and the buyer exactly what is left, so nothing is lost
+    /// or created by rounding.
+    pub fn split(total: Decimal, seller_percent: Decimal) -> Self {
+        let to_seller = (total * seller_percent / Decimal::ONE_HUNDRED).round_dp_with_strategy(
+            Self::PAYOUT_DECIMALS,
+            RoundingStrategy::MidpointNearestEven,
+        );
+        Self {
+            to_seller,
+            to_buyer: total - to_seller,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GpsLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GpsLocation {
+    pub fn new(latitude: f64, longitude: f64) -> Result<Self, String> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(format!("Coordinates out of range: {},{}", latitude, longitude));
+        }
+        Ok(Self { latitude, longitude })
+    }
+}
+
+impl FromStr for GpsLocation {
+    type Err = String;
+    
+    /// Parses `latitude,longitude` in decimal degrees, e.g. `-26.2041,28.0473`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (latitude, longitude) = s
+            .split_once(',')
+            .ok_or_else(|| format!("Expected latitude,longitude but got: {}", s))?;
+        let parse = |part: &str| {
+            part.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Not a coordinate: {}", part))
+        };
+        Self::new(parse(latitude)?, parse(longitude)?)
+    }
+}
+
+impl std::fmt::Display for GpsLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.5},{:.5}", self.latitude, self.longitude)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DisputeResolution {
     pub raised_by: Uuid,
     pub raised_at: DateTime<Utc>,
     pub votes: Vec<Vote>,
     pub resolved_at: Option<DateTime<Utc>>,
     pub decision: Option<DisputeDecision>,
+    /// Evidence is accepted until this time. `None` for disputes raised
+    /// before evidence could be submitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evidence_deadline: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub evidence: Vec<Evidence>,
+    /// Arbitrators must vote by this time, after which missing ones are
+    /// replaced or the dispute is escalated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voting_deadline: Option<DateTime<Utc>>,
+    /// Panel members dropped for not voting in time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replaced: Vec<Uuid>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub replacement_rounds: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalated_at: Option<DateTime<Utc>>,
+    /// An admin must decide an escalated dispute by this time, or the
+    /// default outcome applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation_deadline: Option<DateTime<Utc>>,
+    /// `None` for disputes whose panel decisions are final at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appeal_rules: Option<AppealRules>,
+    /// Set while a panel decision can still be appealed; the funds stay
+    /// held until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appeal_deadline: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub appeals: Vec<Appeal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppealRules {
+    pub window_secs: i64,
+    pub max_appeals: u32,
+}
+
+/// A panel decision that was appealed, kept with the votes behind it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Appeal {
+    pub appealed_by: Uuid,
+    pub appealed_at: DateTime<Utc>,
+    pub panel: Vec<Uuid>,
+    pub votes: Vec<Vote>,
+    pub decision: DisputeDecision,
+}
+
+impl DisputeResolution {
+    pub fn accepts_evidence_at(&self, at: DateTime<Utc>) -> bool {
+        self.decision.is_none() && self.evidence_deadline.is_some_and(|deadline| at <= deadline)
+    }
+    
+    pub fn accepts_votes_at(&self, at: DateTime<Utc>) -> bool {
+        self.decision.is_none()
+            && self.escalated_at.is_none()
+            && self.voting_deadline.is_none_or(|deadline| at <= deadline)
+    }
+    
+    /// Whether the deadline currently running has passed: the appeal
+    /// window on a decision, otherwise the admin's once escalated and the
+    /// arbitrators' before that.
+    pub fn is_overdue_at(&self, at: DateTime<Utc>) -> bool {
+        let deadline = match self.decision {
+            Some(_) => self.appeal_deadline,
+            None => self.escalation_deadline.or(self.voting_deadline),
+        };
+        deadline.is_some_and(|deadline| at > deadline)
+    }
+    
+    /// Whether the panel's decision may still be appealed at `at`.
+    pub fn accepts_appeal_at(&self, at: DateTime<Utc>) -> bool {
+        self.decision.is_some() && self.appeal_deadline.is_some_and(|deadline| at <= deadline)
+    }
+    
+    /// Whether another appeal is allowed after the current decision.
+    pub fn can_be_appealed(&self) -> bool {
+        self.appeal_rules
+            .as_ref()
+            .is_some_and(|rules| (self.appeals.len() as u32) < rules.max_appeals)
+    }
+}
+
+/// Something one of the parties puts before the arbitrators.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evidence {
+    pub submitted_by: Uuid,
+    pub kind: EvidenceKind,
+    /// The statement or note itself, the photo's SHA-256 in hex, or where
+    /// the file can be found.
+    pub content: String,
+    pub submitted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvidenceKind {
+    Statement,
+    /// Photos are kept off the ledger; the hash pins down which one was meant.
+    PhotoHash,
+    FileReference,
+    DeliveryNote,
+}
+
+impl EvidenceKind {
+    /// Longest statement, note or file reference accepted, in characters.
+    pub const MAX_CONTENT_CHARS: usize = 2000;
+    
+    pub fn check_content(&self, content: &str) -> Result<(), String> {
+        let content = content.trim();
+        if content.is_empty() {
+            return Err("Evidence cannot be empty".to_string());
+        }
+        if content.chars().count() > Self::MAX_CONTENT_CHARS {
+            return Err(format!("Evidence is limited to {} characters", Self::MAX_CONTENT_CHARS));
+        }
+        if *self == EvidenceKind::PhotoHash
+            && !(content.len() == 64 && content.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            return Err("A photo hash must be a SHA-256 digest in hex".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for EvidenceKind {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "statement" => Ok(EvidenceKind::Statement),
+            "photo" | "photohash" => Ok(EvidenceKind::PhotoHash),
+            "file" | "filereference" => Ok(EvidenceKind::FileReference),
+            "note" | "deliverynote" => Ok(EvidenceKind::DeliveryNote),
+            _ => Err(format!("Unknown evidence kind: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for EvidenceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            EvidenceKind::Statement => "statement",
+            EvidenceKind::PhotoHash => "photo hash",
+            EvidenceKind::FileReference => "file reference",
+            EvidenceKind::DeliveryNote => "delivery note",
+        };
+        f.write_str(text)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Vote {
     pub arbitrator_id: Uuid,
     pub vote: bool,
     pub voted_at: DateTime<Utc>,
+    /// Set for a vote to split the funds, and then overrides `vote`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seller_percent: Option<Decimal>,
+}
+
+impl Vote {
+    /// The share of the disputed funds this vote gives the seller; a plain
+    /// release vote is 100, a refund vote 0.
+    pub fn seller_percent(&self) -> Decimal {
+        match (self.seller_percent, self.vote) {
+            (Some(percent), _) => percent,
+            (None, true) => Decimal::ONE_HUNDRED,
+            (None, false) => Decimal::ZERO,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DisputeDecision {
     ReleaseToSeller,
     RefundToBuyer,
+    /// The seller gets `seller_percent` of the disputed funds and the buyer
+    /// the rest; see `Escrow::settlement` for the amounts.
+    Split { seller_percent: Decimal },
+}
+
+impl std::fmt::Display for DisputeDecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputeDecision::ReleaseToSeller => write!(f, "release to seller"),
+            DisputeDecision::RefundToBuyer => write!(f, "refund buyer"),
+            DisputeDecision::Split { seller_percent } => {
+                write!(f, "give seller {}%", seller_percent.normalize())
+            }
+        }
+    }
+}
+
+impl DisputeDecision {
+    /// Whether `party` came out of the decision with less than all of the
+    /// funds; under a split both parties did.
+    pub fn went_against(&self, party: Uuid, escrow: &Escrow) -> bool {
+        let seller_percent = self.seller_percent();
+        (party == escrow.buyer_id && seller_percent > Decimal::ZERO)
+            || (party == escrow.seller_id && seller_percent < Decimal::ONE_HUNDRED)
+    }
+    
+    pub fn seller_percent(&self) -> Decimal {
+        match self {
+            DisputeDecision::ReleaseToSeller => Decimal::ONE_HUNDRED,
+            DisputeDecision::RefundToBuyer => Decimal::ZERO,
+            DisputeDecision::Split { seller_percent } => *seller_percent,
+        }
+    }
+    
+    pub fn from_seller_percent(seller_percent: Decimal) -> Self {
+        if seller_percent == Decimal::ONE_HUNDRED {
+            DisputeDecision::ReleaseToSeller
+        } else if seller_percent.is_zero() {
+            DisputeDecision::RefundToBuyer
+        } else {
+            DisputeDecision::Split { seller_percent }
+        }
+    }
 }
 
 impl Escrow {
@@ -61,35 +499,365 @@ impl Escrow {
         seller_id: impl Into<String>,
         description: impl Into<String>,
         days_to_expire: i64,
-    ) -> Self {
+    ) -> (Self, String) {
         let now = Utc::now();
-        let release_pin = Self::generate_pin();
+        let release_pin = pin::generate();
         
-        Self {
+        let escrow = Self {
             id: Uuid::new_v4(),
             amount,
             currency: currency.into(),
             buyer_id,
             seller_id: Uuid::parse_str(&seller_id.into()).unwrap_or_else(|_| Uuid::new_v4()),
             description: description.into(),
-            state: EscrowState::Created,
+            state: EscrowState::AwaitingSellerAcceptance,
             created_at: now,
             expires_at: now + Duration::days(days_to_expire),
             funded_at: None,
             completed_at: None,
-            release_pin: Some(release_pin),
-            arbitrators: vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()],
+            release_pin_hash: Some(pin::hash(&release_pin)),
+            release_mode: ReleaseMode::Pin,
+            totp_secret: None,
+            totp_last_step: None,
+            arbitrators: Vec::new(),
             dispute_resolution: None,
+            driver_id: None,
+            handover: None,
+            milestones: Vec::new(),
+            line_items: Vec::new(),
+            settlement: None,
+            pin_attempts: PinAttempts::default(),
+            version: 0,
+        };
+        
+        (escrow, release_pin)
+    }
+    
+    /// Switches release to one-time codes derived from `secret`. The static
+    /// PIN is dropped so it cannot be used instead.
+    pub fn use_totp(&mut self, secret: String) {
+        self.release_mode = ReleaseMode::Totp;
+        self.totp_secret = Some(secret);
+        self.release_pin_hash = None;
+        for milestone in &mut self.milestones {
+            milestone.pin_hash = None;
         }
     }
     
-    fn generate_pin() -> String {
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        format!("{:06}", rng.gen_range(100000..999999))
+    /// Splits the escrow into deliveries of `(description, amount)`, which
+    /// must add up to the escrow amount, and returns each one's clear PIN in
+    /// order. The escrow-wide PIN is dropped. Under `ReleaseMode::Totp` the
+    /// authenticator code releases each milestone instead, so no PINs are
+    /// made and none are returned.
+    pub fn split_into_milestones(&mut self, parts: Vec<(String, Decimal)>) -> Result<Vec<String>, String> {
+        if parts.is_empty() {
+            return Err("At least one milestone is required".to_string());
+        }
+        if parts.iter().any(|(_, amount)| *amount <= Decimal::ZERO) {
+            return Err("Milestone amounts must be positive".to_string());
+        }
+        let total: Decimal = parts.iter().map(|(_, amount)| *amount).sum();
+        if total != self.amount {
+            return Err(format!(
+                "Milestones add up to {} but the escrow is for {}",
+                total, self.amount
+            ));
+        }
+        
+        let totp = self.release_mode == ReleaseMode::Totp;
+        let mut pins = Vec::new();
+        self.milestones = parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, (description, amount))| {
+                let pin_hash = (!totp).then(|| {
+                    let pin = pin::generate();
+                    let hash = pin::hash(&pin);
+                    pins.push(pin);
+                    hash
+                });
+                Milestone {
+                    number: i as u32 + 1,
+                    description,
+                    amount,
+                    pin_hash,
+                    handover: None,
+                }
+            })
+            .collect();
+        self.release_pin_hash = None;
+        
+        Ok(pins)
+    }
+    
+    /// Itemises the order. The items must add up to the escrow amount and
+    /// each SKU may appear only once.
+    pub fn set_line_items(&mut self, items: Vec<LineItem>) -> Result<(), String> {
+        if items.is_empty() {
+            return Err("At least one line item is required".to_string());
+        }
+        for (i, item) in items.iter().enumerate() {
+            if item.sku.is_empty() || item.quantity == 0 || item.unit_price < Decimal::ZERO {
+                return Err(format!("Invalid line item: {}", item.sku));
+            }
+            if items[..i].iter().any(|other| other.sku == item.sku) {
+                return Err(format!("SKU {} appears more than once", item.sku));
+            }
+        }
+        let total: Decimal = items.iter().map(LineItem::total).sum();
+        if total != self.amount {
+            return Err(format!(
+                "Line items add up to {} but the escrow is for {}",
+                total, self.amount
+            ));
+        }
+        
+        self.line_items = items;
+        Ok(())
+    }
+    
+    /// What each side gets if `received[i]` units of line item `i` arrived.
+    /// Prices are exact, so no rounding is involved.
+    pub fn settlement_for_received(&self, received: &[u32]) -> Settlement {
+        let to_seller: Decimal = self
+            .line_items
+            .iter()
+            .zip(received)
+            .map(|(item, quantity)| item.unit_price * Decimal::from(*quantity))
+            .sum();
+        Settlement {
+            to_seller,
+            to_buyer: self.amount - to_seller,
+        }
+    }
+    
+    /// What is still held: the escrow amount less any milestones already
+    /// released.
+    pub fn outstanding_amount(&self) -> Decimal {
+        let released: Decimal = self
+            .milestones
+            .iter()
+            .filter(|m| m.is_released())
+            .map(|m| m.amount)
+            .sum();
+        self.amount - released
+    }
+    
+    /// Panel members who have not voted on the open dispute.
+    pub fn arbitrators_yet_to_vote(&self) -> Vec<Uuid> {
+        let votes = self.dispute_resolution.as_ref().map(|d| d.votes.as_slice()).unwrap_or_default();
+        self.arbitrators
+            .iter()
+            .filter(|id| !votes.iter().any(|v| v.arbitrator_id == **id))
+            .copied()
+            .collect()
+    }
+    
+    pub fn milestone(&self, number: u32) -> Option<&Milestone> {
+        self.milestones.iter().find(|m| m.number == number)
+    }
+    
+    /// Every secret stored on the escrow, for code that encrypts them.
+    pub fn secrets_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        [&mut self.release_pin_hash, &mut self.totp_secret]
+            .into_iter()
+            .chain(self.milestones.iter_mut().map(|m| &mut m.pin_hash))
+            .flatten()
+    }
+    
+    /// A copy that is safe to display, with every secret replaced by a
+    /// placeholder.
+    pub fn redacted(&self) -> Self {
+        let mut escrow = self.clone();
+        for value in escrow.secrets_mut() {
+            *value = "[redacted]".to_string();
+        }
+        escrow
     }
     
     pub fn is_expired(&self) -> bool {
         Utc::now() > self.expires_at
     }
-}
\ No newline at end of file
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::escrow;
+    use rstest::rstest;
+
+    #[test]
+    fn milestones_must_add_up_to_the_escrow_amount() {
+        let mut escrow = escrow();
+        let part = |amount: i64| ("Load".to_string(), Decimal::from(amount));
+
+        assert!(escrow.split_into_milestones(vec![]).is_err());
+        assert!(escrow.split_into_milestones(vec![part(1000), part(400)]).is_err());
+        assert!(escrow.split_into_milestones(vec![part(1600), part(-100)]).is_err());
+        assert!(escrow.milestones.is_empty());
+        assert!(escrow.release_pin_hash.is_some());
+
+        let pins = escrow.split_into_milestones(vec![part(1000), part(500)]).unwrap();
+        assert_eq!(pins.len(), 2);
+        assert_eq!(escrow.milestones.iter().map(|m| m.number).collect::<Vec<_>>(), [1, 2]);
+        assert!(pin::verify(escrow.milestone(2).unwrap().pin_hash.as_deref().unwrap(), &pins[1]));
+        assert!(escrow.release_pin_hash.is_none());
+    }
+
+    #[test]
+    fn milestones_released_by_one_time_codes_get_no_pins() {
+        let mut escrow = escrow();
+        escrow.use_totp("GEZDGNBVGY3TQOJQ".to_string());
+        let part = |amount: i64| ("Load".to_string(), Decimal::from(amount));
+
+        let pins = escrow.split_into_milestones(vec![part(1000), part(500)]).unwrap();
+        assert!(pins.is_empty());
+        assert_eq!(escrow.milestones.len(), 2);
+        assert!(escrow.milestones.iter().all(|m| m.pin_hash.is_none()));
+    }
+
+    #[rstest]
+    #[case("MILK-1L:24@18.50", "MILK-1L", 24, Decimal::new(1850, 2))]
+    #[case(" BREAD : 10 @ 12 ", "BREAD", 10, Decimal::from(12))]
+    #[case("CASE:12:3@99.99", "CASE:12", 3, Decimal::new(9999, 2))]
+    fn parses_line_items(#[case] text: &str, #[case] sku: &str, #[case] quantity: u32, #[case] unit_price: Decimal) {
+        assert_eq!(text.parse::<LineItem>().unwrap(), LineItem::new(sku, quantity, unit_price));
+    }
+
+    #[rstest]
+    #[case("MILK-1L")]
+    #[case("MILK-1L:24")]
+    #[case("MILK-1L:-2@18.50")]
+    #[case("MILK-1L:24@cheap")]
+    fn refuses_malformed_line_items(#[case] text: &str) {
+        assert!(text.parse::<LineItem>().is_err());
+    }
+
+    #[test]
+    fn line_items_must_be_distinct_and_add_up() {
+        let mut escrow = escrow();
+        let item = |sku: &str, quantity: u32| LineItem::new(sku, quantity, Decimal::from(15));
+
+        assert!(escrow.set_line_items(vec![]).is_err());
+        assert!(escrow.set_line_items(vec![item("MILK-1L", 60), item("BREAD", 30)]).is_err());
+        assert!(escrow.set_line_items(vec![item("MILK-1L", 60), item("MILK-1L", 40)]).is_err());
+        assert!(escrow.set_line_items(vec![item("MILK-1L", 100), item("BREAD", 0)]).is_err());
+        assert!(escrow.set_line_items(vec![item("", 100)]).is_err());
+        assert!(escrow.line_items.is_empty());
+
+        escrow.set_line_items(vec![item("MILK-1L", 60), item("BREAD", 40)]).unwrap();
+        assert_eq!(escrow.line_items.len(), 2);
+    }
+
+    #[test]
+    fn settlement_pays_the_seller_for_what_arrived() {
+        let mut escrow = escrow();
+        escrow
+            .set_line_items(vec![
+                LineItem::new("MILK-1L", 60, Decimal::new(1250, 2)),
+                LineItem::new("BREAD", 30, Decimal::from(25)),
+            ])
+            .unwrap();
+
+        let settlement = escrow.settlement_for_received(&[58, 30]);
+        assert_eq!(settlement.to_seller, Decimal::from(1475));
+        assert_eq!(settlement.to_buyer, Decimal::from(25));
+    }
+
+    #[rstest]
+    #[case(Decimal::new(100001, 2), Decimal::new(335, 1), Decimal::new(33500, 2), Decimal::new(66501, 2))]
+    #[case(Decimal::new(9999, 2), Decimal::from(50), Decimal::new(5000, 2), Decimal::new(4999, 2))]
+    #[case(Decimal::new(1, 2), Decimal::from(50), Decimal::ZERO, Decimal::new(1, 2))]
+    #[case(Decimal::new(3, 2), Decimal::from(50), Decimal::new(2, 2), Decimal::new(1, 2))]
+    #[case(Decimal::from(1500), Decimal::ZERO, Decimal::ZERO, Decimal::from(1500))]
+    #[case(Decimal::from(1500), Decimal::ONE_HUNDRED, Decimal::from(1500), Decimal::ZERO)]
+    fn split_rounds_the_sellers_share_to_cents(
+        #[case] total: Decimal,
+        #[case] seller_percent: Decimal,
+        #[case] to_seller: Decimal,
+        #[case] to_buyer: Decimal,
+    ) {
+        assert_eq!(Settlement::split(total, seller_percent), Settlement { to_seller, to_buyer });
+    }
+
+    #[test]
+    fn split_never_loses_or_creates_a_cent() {
+        for cents in [1, 7, 99, 1001, 33333, 100001] {
+            let total = Decimal::new(cents, 2);
+            for hundredths in (0..=10000).step_by(37) {
+                let settlement = Settlement::split(total, Decimal::new(hundredths, 2));
+                assert_eq!(settlement.to_seller + settlement.to_buyer, total);
+                assert!(settlement.to_seller.scale() <= Settlement::PAYOUT_DECIMALS);
+                assert!(settlement.to_buyer >= Decimal::ZERO);
+            }
+        }
+    }
+
+    #[test]
+    fn whole_shares_become_plain_decisions() {
+        assert!(matches!(
+            DisputeDecision::from_seller_percent(Decimal::ONE_HUNDRED),
+            DisputeDecision::ReleaseToSeller
+        ));
+        assert!(matches!(DisputeDecision::from_seller_percent(Decimal::ZERO), DisputeDecision::RefundToBuyer));
+        assert!(matches!(
+            DisputeDecision::from_seller_percent(Decimal::from(40)),
+            DisputeDecision::Split { seller_percent } if seller_percent == Decimal::from(40)
+        ));
+    }
+
+    #[test]
+    fn split_decisions_go_against_both_parties() {
+        let split = DisputeDecision::from_seller_percent(Decimal::from(40));
+        assert_eq!(split.seller_percent(), Decimal::from(40));
+
+        let escrow = escrow();
+        let seller_id = escrow.seller_id;
+        assert!(split.went_against(escrow.buyer_id, &escrow) && split.went_against(seller_id, &escrow));
+        assert!(!DisputeDecision::ReleaseToSeller.went_against(seller_id, &escrow));
+        assert!(!DisputeDecision::RefundToBuyer.went_against(escrow.buyer_id, &escrow));
+    }
+
+    #[rstest]
+    #[case(EvidenceKind::Statement, "The milk was past its date", true)]
+    #[case(EvidenceKind::Statement, "   ", false)]
+    #[case(EvidenceKind::PhotoHash, &"ab".repeat(32), true)]
+    #[case(EvidenceKind::PhotoHash, &"ab".repeat(31), false)]
+    #[case(EvidenceKind::PhotoHash, &"zz".repeat(32), false)]
+    #[case(EvidenceKind::FileReference, &"x".repeat(EvidenceKind::MAX_CONTENT_CHARS), true)]
+    #[case(EvidenceKind::FileReference, &"x".repeat(EvidenceKind::MAX_CONTENT_CHARS + 1), false)]
+    fn checks_evidence_content(#[case] kind: EvidenceKind, #[case] content: &str, #[case] valid: bool) {
+        assert_eq!(kind.check_content(content).is_ok(), valid);
+    }
+
+    #[test]
+    fn parses_evidence_kinds() {
+        assert_eq!("photo".parse::<EvidenceKind>().unwrap(), EvidenceKind::PhotoHash);
+        assert_eq!("delivery-note".parse::<EvidenceKind>().unwrap(), EvidenceKind::DeliveryNote);
+        assert_eq!("File_Reference".parse::<EvidenceKind>().unwrap(), EvidenceKind::FileReference);
+        assert!("video".parse::<EvidenceKind>().is_err());
+    }
+
+    #[test]
+    fn redacted_copy_hides_every_secret() {
+        let mut escrow = escrow();
+        let part = |amount: i64| ("Load".to_string(), Decimal::from(amount));
+        escrow.split_into_milestones(vec![part(1000), part(500)]).unwrap();
+        let hashes: Vec<String> = escrow.secrets_mut().map(|hash| hash.clone()).collect();
+        assert_eq!(hashes.len(), 2);
+
+        let shown = serde_json::to_string(&escrow.redacted()).unwrap();
+        assert!(hashes.iter().all(|hash| !shown.contains(hash.as_str())));
+        assert_eq!(shown.matches("[redacted]").count(), 2);
+
+        escrow.use_totp("GEZDGNBVGY3TQOJQ".to_string());
+        let shown = serde_json::to_string(&escrow.redacted()).unwrap();
+        assert!(!shown.contains("GEZDGNBVGY3TQOJQ"));
+        assert!(shown.contains("[redacted]"));
+        assert_eq!(escrow.totp_secret.as_deref(), Some("GEZDGNBVGY3TQOJQ"));
+    }
+}
@@ -1,5 +1,6 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,13 +11,50 @@ pub struct User {
     pub user_type: UserType,
     pub trust_score: TrustScore,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Hex-encoded Ed25519 public key; the secret key stays on the user's
+    /// device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl User {
+    pub fn new(id: Uuid, name: impl Into<String>, phone_number: impl Into<String>, user_type: UserType) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            phone_number: phone_number.into(),
+            user_type,
+            trust_score: TrustScore::new(),
+            created_at: chrono::Utc::now(),
+            public_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserType {
     Buyer,
     Seller,
     Arbitrator,
+    /// Delivers stock and collects the buyer's PIN at the door.
+    Driver,
+    /// Operates the service; see `escrow::permissions` for what that allows.
+    Admin,
+}
+
+impl FromStr for UserType {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "buyer" => Ok(UserType::Buyer),
+            "seller" => Ok(UserType::Seller),
+            "arbitrator" => Ok(UserType::Arbitrator),
+            "driver" => Ok(UserType::Driver),
+            "admin" => Ok(UserType::Admin),
+            _ => Err(format!("Unknown user type: {}", s)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +66,12 @@ pub struct TrustScore {
     pub last_updated: chrono::DateTime<chrono::Utc>,
 }
 
+impl Default for TrustScore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TrustScore {
     pub fn new() -> Self {
         Self {